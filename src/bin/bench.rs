@@ -173,7 +173,7 @@ fn run(config: Config) -> Result<(), Box<Error>> {
 
     // create a default sled config
     let sled_config = sled::Config::default();
-    let tree = sled_config.tree();
+    let tree = sled_config.tree().unwrap();
 
     perform_tree_operations(tree, config);
 
@@ -256,13 +256,23 @@ fn perform_tree_operations(tree: Tree, config: Config) {
     println!("99.99th: {}us", histo.percentile(99.99).round() as usize);
     println!("99.999th: {}us", histo.percentile(99.999).round() as usize);
     println!("100th: {}us", histo.percentile(100.).round() as usize);
+
+    let key_bytes = sled::M.node_key_bytes.load(Ordering::Relaxed);
+    let saved = sled::M.node_key_bytes_saved.load(Ordering::Relaxed);
+    println!("");
+    println!("serialized node key bytes: {}", key_bytes);
+    println!(
+        "saved by key prefix compression: {} ({:.1}%)",
+        saved,
+        100. * saved as f64 / key_bytes.max(1) as f64
+    );
 }
 
 fn perform_set_operation(tree: &Tree) {
     info!("Performing set operation");
     let kv = KV::new();
 
-    tree.set(kv.key, kv.value);
+    tree.set(kv.key, kv.value).unwrap();
 }
 
 fn perform_scan_operation(tree: &Tree) {
@@ -276,14 +286,14 @@ fn perform_get_operation(tree: &Tree) {
     info!("Performing get operation");
     let kv = KV::new();
 
-    tree.get(&kv.key);
+    tree.get(&kv.key).unwrap();
 }
 
 fn perform_delete_operation(tree: &Tree) {
     info!("Performing delete operation");
     let kv = KV::new();
 
-    tree.del(&kv.key);
+    tree.del(&kv.key).unwrap();
 }
 
 fn perform_cas_operation(tree: &Tree) {
@@ -293,7 +303,7 @@ fn perform_cas_operation(tree: &Tree) {
     let old_value = kv.value;
     let new_value = KV::new().value;
 
-    tree.cas(kv.key, Some(old_value), Some(new_value));
+    tree.cas(kv.key, Some(old_value), Some(new_value)).unwrap();
 }
 
 fn get_operation_choice(ops: &Vec<(Op, usize)>, sum_ops: usize) -> Option<&Op> {
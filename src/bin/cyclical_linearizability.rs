@@ -7,7 +7,7 @@ fn verify(tree: &sled::Tree) -> (u32, u32) {
     // key 0 should always be the highest value, as that's where we increment
     // at some point, it might go down by one
     // it should never return, or go down again after that
-    let mut iter = tree.iter();
+    let mut iter = tree.iter().map(|res| res.unwrap());
     let highest = match iter.next() {
         None => return (0, 0),
         Some((_k, v)) => slice_to_u32(&*v),
@@ -44,7 +44,8 @@ fn verify(tree: &sled::Tree) -> (u32, u32) {
     let low_beginning = u32_to_vec(contiguous + 1);
 
     println!("from {} and up expecting {:?}", contiguous + 1, lowest_vec);
-    for (mut k, v) in tree.scan(&*low_beginning) {
+    for res in tree.scan(&*low_beginning) {
+        let (mut k, v) = res.unwrap();
         if v != lowest_vec {
             k.reverse();
             println!("k: {} v: {}", slice_to_u32(&*k), slice_to_u32(&*v));
@@ -82,7 +83,7 @@ fn main() {
         .snapshot_after_ops(1 << 16);
 
     println!("restoring");
-    let tree = config.tree();
+    let tree = config.tree().unwrap();
 
     println!("verifying");
     let (key, highest) = verify(&tree);
@@ -107,6 +108,6 @@ fn main() {
         let mut key = u32_to_vec((hu % cycle) as u32);
         key.reverse();
         let value = u32_to_vec((hu / cycle) as u32);
-        tree.set(key, value);
+        tree.set(key, value).unwrap();
     }
 }
@@ -52,21 +52,23 @@ fn run(tree: Arc<sled::Tree>, shutdown: Arc<AtomicBool>, total: Arc<AtomicUsize>
 
         match choice {
             0 => {
-                tree.set(byte(), byte());
+                tree.set(byte(), byte()).unwrap();
             }
             1 => {
-                tree.get(&*byte());
+                tree.get(&*byte()).unwrap();
             }
             2 => {
-                tree.del(&*byte());
+                tree.del(&*byte()).unwrap();
             }
             3 => {
-                if let Err(_) = tree.cas(byte(), Some(byte()), Some(byte())) {};
+                if let Err(_) = tree.cas(byte(), Some(byte()), Some(byte()))
+                    .unwrap() {};
             }
             4 => {
                 tree.scan(&*byte())
                     .take(rng.gen_range(0, 15))
-                    .collect::<Vec<_>>();
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap();
             }
             _ => panic!("impossible choice"),
         }
@@ -94,7 +96,7 @@ fn main() {
         .flush_every_ms(Some(100))
         .snapshot_after_ops(1000000);
 
-    let tree = Arc::new(config.tree());
+    let tree = Arc::new(config.tree().unwrap());
 
     let mut threads = vec![];
 
@@ -66,7 +66,7 @@ fn prepopulate(tree: Arc<sled::Tree>) {
         let bytes: [u8; 8] = unsafe { mem::transmute(i) };
         let k = bytes[8 - unsafe { KEY_BYTES }..8].to_vec();
         let v = vec![];
-        tree.set(k, v);
+        tree.set(k, v).unwrap();
     }
 }
 
@@ -95,7 +95,7 @@ fn main() {
         .snapshot_after_ops(1 << 16);
 
     println!("recovering");
-    let tree = Arc::new(config.tree());
+    let tree = Arc::new(config.tree().unwrap());
 
     macro_rules! cloned {
         ($f:expr) => {{
@@ -136,15 +136,16 @@ fn main() {
     println!("spinning up threads");
     #[rustfmt_skip]
     spin_up![
-        args.flag_get, |t: &Arc<sled::Tree>| t.get(&*byte());
-        args.flag_set, |t: &Arc<sled::Tree>| t.set(byte(), byte());
-        args.flag_del, |t: &Arc<sled::Tree>| t.del(&*byte());
+        args.flag_get, |t: &Arc<sled::Tree>| t.get(&*byte()).unwrap();
+        args.flag_set, |t: &Arc<sled::Tree>| t.set(byte(), byte()).unwrap();
+        args.flag_del, |t: &Arc<sled::Tree>| t.del(&*byte()).unwrap();
         args.flag_cas, |t: &Arc<sled::Tree>| {
-            let _ = t.cas(byte(), Some(byte()), Some(byte()));
+            let _ = t.cas(byte(), Some(byte()), Some(byte())).unwrap();
         };
         args.flag_scan, |t: &Arc<sled::Tree>| t.scan(&*byte())
             .take(thread_rng().gen_range(1, 3))
-            .collect::<Vec<_>>();
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
     ];
 
     let now = std::time::Instant::now();
@@ -20,7 +20,8 @@ use super::*;
 ///     .snapshot_after_ops(100_000);
 /// ```
 ///
-/// Read-only mode
+/// Read-only mode, which opens an existing file without write access
+/// and returns `Error::ReadOnly` from every write
 /// ```
 /// let _config = sled::Config::default()
 ///     .path("/path/to/data".to_owned())
@@ -65,6 +66,10 @@ impl Default for Config {
             segment_cleanup_threshold: 0.2,
             min_free_segments: 3,
             zero_copy_storage: false,
+            merge_operator: None,
+            key_comparator: None,
+            reap_every_ms: Some(1000),
+            blob_threshold: 64 * 1024, // 64kb
             tc: ThreadCache::default(),
             tmp_path: tmp_path.to_owned(),
         }));
@@ -90,13 +95,18 @@ impl DerefMut for Config {
 
 impl Config {
     /// create a new `Tree` based on this configuration
-    pub fn tree(&self) -> Tree {
+    pub fn tree(&self) -> Result<Tree> {
         Tree::new(self.clone())
     }
 
+    /// create a new `Db` based on this configuration
+    pub fn db(&self) -> Result<Db> {
+        Db::new(self.clone())
+    }
+
     /// create a new `Log` based on this
     /// configuration
-    pub fn log(&self) -> Log {
+    pub fn log(&self) -> Result<Log> {
         Log::start_system(self.clone())
     }
 }
@@ -120,6 +130,10 @@ pub struct ConfigInner {
     segment_cleanup_threshold: f64,
     min_free_segments: usize,
     zero_copy_storage: bool,
+    merge_operator: Option<MergeOperator>,
+    key_comparator: Option<(&'static str, KeyComparator)>,
+    reap_every_ms: Option<u64>,
+    blob_threshold: usize,
     tc: ThreadCache<fs::File>,
     tmp_path: String,
 }
@@ -144,6 +158,8 @@ macro_rules! builder {
             pub fn $name(&self, to: $t) -> Config {
                 let mut ret = self.clone();
                 ret.$name = to;
+                // the path or mode we open files with may have changed
+                ret.tc = ThreadCache::default();
                 Config { inner: Arc::new(UnsafeCell::new(ret))}
             }
         )*
@@ -157,7 +173,7 @@ impl ConfigInner {
         (blink_fanout, get_blink_fanout, set_blink_fanout, usize, "b-link node fanout, minimum of 2"),
         (page_consolidation_threshold, get_page_consolidation_threshold, set_page_consolidation_threshold, usize, "page consolidation threshold"),
         (path, get_path, set_path, String, "path for the main storage file"),
-        (read_only, get_read_only, set_read_only, bool, "whether to run in read-only mode"),
+        (read_only, get_read_only, set_read_only, bool, "whether to open existing storage read-only, refusing writes"),
         (cache_bits, get_cache_bits, set_cache_bits, usize, "log base 2 of the number of cache shards"),
         (cache_capacity, get_cache_capacity, set_cache_capacity, usize, "maximum size for the system page cache"),
         (use_os_cache, get_use_os_cache, set_use_os_cache, bool, "whether to use the OS page cache"),
@@ -168,21 +184,28 @@ impl ConfigInner {
         (cache_fixup_threshold, get_cache_fixup_threshold, set_cache_fixup_threshold, usize, "the maximum length of a cached page fragment chain"),
         (segment_cleanup_threshold, get_segment_cleanup_threshold, set_segment_cleanup_threshold, f64, "the proportion of remaining valid pages in the segment"),
         (min_free_segments, get_min_free_segments, set_min_free_segments, usize, "the minimum number of free segments to have on-deck before a compaction occurs"),
-        (zero_copy_storage, get_zero_copy_storage, set_zero_copy_storage, bool, "disabling of the log segment copy cleaner")
+        (zero_copy_storage, get_zero_copy_storage, set_zero_copy_storage, bool, "disabling of the log segment copy cleaner"),
+        (merge_operator, get_merge_operator, set_merge_operator, Option<MergeOperator>, "the operator used by Tree::merge to combine values"),
+        (key_comparator, get_key_comparator, set_key_comparator, Option<(&'static str, KeyComparator)>, "a named comparator for ordering keys, which trees must be reopened with"),
+        (reap_every_ms, get_reap_every_ms, set_reap_every_ms, Option<u64>, "number of ms between deletions of expired keys"),
+        (blob_threshold, get_blob_threshold, set_blob_threshold, usize, "values longer than this are stored outside of tree leaves")
     );
 
     /// Retrieve a thread-local file handle to the
     /// configured underlying storage,
     /// or create a new one if this is the first time the
-    /// thread is accessing it.
-    pub fn cached_file(&self) -> Rc<RefCell<fs::File>> {
+    /// thread is accessing it. In read-only mode the
+    /// file must already exist, and is opened for reading only.
+    pub fn cached_file(&self) -> std::io::Result<Rc<RefCell<fs::File>>> {
         self.tc.get_or_else(|| {
             let path = self.get_path();
             let mut options = fs::OpenOptions::new();
-            options.create(true);
             options.read(true);
-            options.write(true);
-            options.open(path).unwrap()
+            if !self.get_read_only() {
+                options.create(true);
+                options.write(true);
+            }
+            options.open(path)
         })
     }
 
@@ -198,18 +221,15 @@ impl ConfigInner {
     }
 
     /// returns the snapshot file paths for this system
-    pub fn get_snapshot_files(&self) -> Vec<String> {
+    pub fn get_snapshot_files(&self) -> std::io::Result<Vec<String>> {
         let mut prefix = self.snapshot_prefix();
 
         prefix.push_str(".");
 
-        let err_msg = format!("could not read snapshot directory ({})", prefix);
-
-
         let abs_prefix: String = if Path::new(&prefix).is_absolute() {
             prefix
         } else {
-            let mut abs_path = std::env::current_dir().expect(&*err_msg);
+            let mut abs_path = std::env::current_dir()?;
             abs_path.push(prefix.clone());
             abs_path.to_str().unwrap().to_owned()
         };
@@ -231,17 +251,18 @@ impl ConfigInner {
             }
         };
 
-        let snap_dir = Path::new(&abs_prefix).parent().expect(&*err_msg);
+        let snap_dir = Path::new(&abs_prefix).parent().expect(
+            "could not parse snapshot path",
+        );
 
         if !snap_dir.exists() {
-            std::fs::create_dir_all(snap_dir).unwrap();
+            if self.get_read_only() {
+                return Ok(vec![]);
+            }
+            std::fs::create_dir_all(snap_dir)?;
         }
 
-        snap_dir
-            .read_dir()
-            .expect(&*err_msg)
-            .filter_map(filter)
-            .collect()
+        Ok(snap_dir.read_dir()?.filter_map(filter).collect())
     }
 }
 
@@ -256,7 +277,7 @@ impl Drop for ConfigInner {
 
         let _res = fs::remove_file(self.tmp_path.clone());
 
-        let candidates = self.get_snapshot_files();
+        let candidates = self.get_snapshot_files().unwrap_or_default();
         for path in candidates {
             if let Err(_e) = std::fs::remove_file(path) {
                 warn!("failed to remove old snapshot file, maybe snapshot race? {}", _e);
@@ -29,21 +29,21 @@ impl<T> Default for ThreadCache<T> {
 }
 
 impl<T> ThreadCache<T> {
-    pub fn get_or_else<F>(&self, mut f: F) -> Rc<RefCell<T>>
-        where F: FnMut() -> T
+    pub fn get_or_else<F, E>(&self, mut f: F) -> Result<Rc<RefCell<T>>, E>
+        where F: FnMut() -> Result<T, E>
     {
         let id = thread::current().id();
 
         {
             let map = self.inner.read().unwrap();
             if map.contains_key(&id) {
-                return map.get(&id).unwrap().clone();
+                return Ok(map.get(&id).unwrap().clone());
             }
         }
 
-        let t = Rc::new(RefCell::new(f()));
+        let t = Rc::new(RefCell::new(f()?));
         let mut map = self.inner.write().unwrap();
         map.insert(id, t);
-        map.get(&id).unwrap().clone()
+        Ok(map.get(&id).unwrap().clone())
     }
 }
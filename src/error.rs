@@ -0,0 +1,98 @@
+use std::error;
+use std::fmt::{self, Display};
+use std::io;
+
+use super::*;
+
+/// The result of an operation that may fail. Operations that can be
+/// refused for reasons of their own, like a `Tree::cas` whose old
+/// value does not match, return those refusals inside the `Ok`.
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// An error returned by a `Tree`, `PageCache` or `Log`.
+///
+/// Once writing to the underlying file has failed, the log can't
+/// tell which of the writes in flight made it to disk, so every
+/// later write fails with the same error. Reads of data that was
+/// already written may still succeed.
+#[derive(Debug)]
+pub enum Error {
+    /// The underlying file could not be read or written.
+    Io(io::Error),
+    /// A log record could not be read back as it was written.
+    Corruption {
+        /// The log sequence number the record was expected to have.
+        lsn: Lsn,
+        /// The file offset of the record.
+        lid: LogID,
+    },
+    /// A write was attempted with `Config::read_only` set.
+    ReadOnly,
+    /// The operation is not supported by the configuration it was
+    /// attempted with, for the given reason.
+    Unsupported(String),
+}
+
+impl Clone for Error {
+    fn clone(&self) -> Error {
+        match *self {
+            Error::Io(ref e) => {
+                Error::Io(io::Error::new(e.kind(), e.to_string()))
+            }
+            Error::Corruption { lsn, lid } => Error::Corruption {
+                lsn: lsn,
+                lid: lid,
+            },
+            Error::ReadOnly => Error::ReadOnly,
+            Error::Unsupported(ref why) => Error::Unsupported(why.clone()),
+        }
+    }
+}
+
+// io errors are compared by their kind, as they can't be compared
+// directly.
+impl PartialEq for Error {
+    fn eq(&self, other: &Error) -> bool {
+        match (self, other) {
+            (&Error::Io(ref a), &Error::Io(ref b)) => a.kind() == b.kind(),
+            (
+                &Error::Corruption { lsn, lid },
+                &Error::Corruption {
+                    lsn: other_lsn,
+                    lid: other_lid,
+                },
+            ) => lsn == other_lsn && lid == other_lid,
+            (&Error::ReadOnly, &Error::ReadOnly) => true,
+            (&Error::Unsupported(ref a), &Error::Unsupported(ref b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "IO error: {}", e),
+            Error::Corruption { lsn, lid } => write!(
+                f,
+                "read corrupted data for lsn {} at lid {}",
+                lsn,
+                lid
+            ),
+            Error::ReadOnly => {
+                write!(f, "tried to write while configured read-only")
+            }
+            Error::Unsupported(ref why) => {
+                write!(f, "unsupported operation: {}", why)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {}
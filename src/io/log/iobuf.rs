@@ -1,3 +1,4 @@
+use std::fs::File;
 use std::io::{Seek, Write};
 use std::path::Path;
 use std::sync::{Condvar, Mutex};
@@ -40,12 +41,16 @@ pub(super) struct IoBufs {
     stable: AtomicUsize,
     file_for_writing: Mutex<std::fs::File>,
     segment_accountant: Mutex<SegmentAccountant>,
+    // set along with error once a write to the file has failed, so
+    // that reservations only need to check the flag.
+    failed: AtomicBool,
+    error: Mutex<Option<Error>>,
 }
 
 /// `IoBufs` is a set of lock-free buffers for coordinating
 /// writes to underlying storage.
 impl IoBufs {
-    pub fn new(config: Config) -> IoBufs {
+    pub fn new(config: Config) -> Result<IoBufs> {
         let path = config.get_path();
 
         let dir = Path::new(&path).parent().expect(
@@ -54,21 +59,24 @@ impl IoBufs {
 
         if dir != Path::new("") {
             if dir.is_file() {
-                panic!(
-                    "provided parent directory is a file, \
-                    not a directory: {:?}",
-                    dir
-                );
+                return Err(Error::Io(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "provided parent directory is a file, \
+                        not a directory: {:?}",
+                        dir
+                    ),
+                )));
             }
 
-            if !dir.exists() {
-                std::fs::create_dir_all(dir).unwrap();
+            if !dir.exists() && !config.get_read_only() {
+                std::fs::create_dir_all(dir)?;
             }
         }
 
         let io_buf_size = config.get_io_buf_size();
 
-        let mut segment_accountant = SegmentAccountant::new(config.clone());
+        let mut segment_accountant = SegmentAccountant::new(config.clone())?;
 
         let bufs = rep_no_copy![IoBuf::new(io_buf_size); config.get_io_bufs()];
 
@@ -76,11 +84,15 @@ impl IoBufs {
         let recovered_lsn = segment_accountant.recovered_lsn();
         let recovered_lid = segment_accountant.recovered_lid();
 
-        // open file for writing
+        // open file for writing, unless we may only read it
         let mut options = std::fs::OpenOptions::new();
-        options.create(true);
-        options.write(true);
-        let mut file = options.open(&path).unwrap();
+        if config.get_read_only() {
+            options.read(true);
+        } else {
+            options.create(true);
+            options.write(true);
+        }
+        let mut file = options.open(&path)?;
 
         trace!(
             "starting IoBufs with recovered_lsn: {} \
@@ -97,9 +109,9 @@ impl IoBufs {
             iobuf.set_capacity(io_buf_size - SEG_TRAILER_LEN);
             iobuf.store_segment_header(recovered_lsn, last_given);
 
-            file.seek(SeekFrom::Start(lid)).unwrap();
-            file.write_all(&*vec![0; config.get_io_buf_size()]).unwrap();
-            file.sync_all().unwrap();
+            if !config.get_read_only() {
+                write_at(&mut file, lid, &*vec![0; config.get_io_buf_size()])?;
+            }
 
             debug!(
                 "starting log at clean offset {}, recovered lsn {}",
@@ -121,7 +133,7 @@ impl IoBufs {
             );
         }
 
-        IoBufs {
+        Ok(IoBufs {
             bufs: bufs,
             current_buf: AtomicUsize::new(current_buf),
             written_bufs: AtomicUsize::new(0),
@@ -131,7 +143,34 @@ impl IoBufs {
             config: config,
             file_for_writing: Mutex::new(file),
             segment_accountant: Mutex::new(segment_accountant),
+            failed: AtomicBool::new(false),
+            error: Mutex::new(None),
+        })
+    }
+
+    /// Returns the error that a write to the file failed with, if
+    /// any has.
+    pub(super) fn check_error(&self) -> Result<()> {
+        if !self.failed.load(SeqCst) {
+            return Ok(());
         }
+        Err(self.error.lock().unwrap().clone().unwrap())
+    }
+
+    // remembers the first failed write, and wakes up threads waiting
+    // in make_stable for offsets that will now never become stable.
+    fn set_error(&self, e: std::io::Error) {
+        error!("failed to write to the log: {}", e);
+        {
+            let mut error = self.error.lock().unwrap();
+            if error.is_none() {
+                *error = Some(Error::Io(e));
+            }
+        }
+        self.failed.store(true, SeqCst);
+
+        let _intervals = self.intervals.lock().unwrap();
+        self.interval_updated.notify_all();
     }
 
     /// SegmentAccountant access for coordination with the `PageCache`
@@ -206,12 +245,18 @@ impl IoBufs {
     /// linearizability across CAS operations that may need to
     /// persist part of their operation.
     ///
+    /// Returns `Error::ReadOnly` if the log was opened read-only.
+    ///
     /// # Panics
     ///
     /// Panics if the desired reservation is greater than the
     /// io buffer size minus the size of a segment header +
     /// a segment footer + a message header.
-    pub(super) fn reserve(&self, raw_buf: Vec<u8>) -> Reservation {
+    pub(super) fn reserve(&self, raw_buf: Vec<u8>) -> Result<Reservation> {
+        if self.config.get_read_only() {
+            return Err(Error::ReadOnly);
+        }
+
         let start = clock();
 
         assert_eq!((raw_buf.len() + MSG_HEADER_LEN) >> 32, 0);
@@ -238,6 +283,8 @@ impl IoBufs {
         }
         let mut spins = 0;
         loop {
+            self.check_error()?;
+
             debug_delay();
             let written_bufs = self.written_bufs.load(SeqCst);
             debug_delay();
@@ -356,7 +403,7 @@ impl IoBufs {
                 reservation_offset,
             );
 
-            return Reservation {
+            return Ok(Reservation {
                 idx: idx,
                 iobufs: self,
                 data: buf,
@@ -364,7 +411,7 @@ impl IoBufs {
                 flushed: false,
                 lsn: reservation_lsn,
                 lid: reservation_offset,
-            };
+            });
         }
     }
 
@@ -501,10 +548,10 @@ impl IoBufs {
             // zero out the entire new segment on disk
             debug!("zeroing out segment beginning at {}", next_offset);
             let mut f = self.file_for_writing.lock().unwrap();
-            f.seek(SeekFrom::Start(next_offset)).unwrap();
-            f.write_all(&*vec![0; self.config.get_io_buf_size()])
-                .unwrap();
-            f.sync_all().unwrap();
+            let zeroes = vec![0; self.config.get_io_buf_size()];
+            if let Err(e) = write_at(&mut f, next_offset, &*zeroes) {
+                self.set_error(e);
+            }
 
             (next_offset, Some(last_given))
         } else {
@@ -591,10 +638,13 @@ impl IoBufs {
 
         let data = unsafe { (*iobuf.buf.get()).as_mut_slice() };
 
+        // a failed write leaves the buffer's lsns unstable forever,
+        // but the buffer is still recycled so that threads waiting
+        // on it can observe the error.
         let mut f = self.file_for_writing.lock().unwrap();
-        f.seek(SeekFrom::Start(lid)).unwrap();
-        f.write_all(&data[..res_len]).unwrap();
-        f.sync_all().unwrap();
+        if let Err(e) = write_at(&mut f, lid, &data[..res_len]) {
+            self.set_error(e);
+        }
 
         // write a trailer if we're maxed
         if iobuf.get_maxed() {
@@ -620,9 +670,9 @@ impl IoBufs {
                 trailer_lsn
             );
 
-            f.seek(SeekFrom::Start(trailer_lid)).unwrap();
-            f.write_all(&trailer_bytes).unwrap();
-            f.sync_all().unwrap();
+            if let Err(e) = write_at(&mut f, trailer_lid, &trailer_bytes) {
+                self.set_error(e);
+            }
             iobuf.set_maxed(false);
 
             // transition this segment into deplete-only mode now
@@ -652,7 +702,7 @@ impl IoBufs {
             _written_bufs % self.config.get_io_bufs()
         );
 
-        if res_len != 0 {
+        if res_len != 0 && !self.failed.load(SeqCst) {
             let interval = (base_lsn, base_lsn + res_len as Lsn);
 
             debug!("wrote lsns {}-{} to disk at offsets {}-{}", 
@@ -716,11 +766,17 @@ impl IoBufs {
 
 impl Drop for IoBufs {
     fn drop(&mut self) {
+        if self.config.get_read_only() {
+            // nothing could have been written
+            return;
+        }
         for _ in 0..self.config.get_io_bufs() {
             self.flush();
         }
         let f = self.file_for_writing.lock().unwrap();
-        f.sync_all().unwrap();
+        if let Err(e) = f.sync_all() {
+            error!("failed to sync the log while shutting down: {}", e);
+        }
 
         debug!("IoBufs dropped");
     }
@@ -865,6 +921,12 @@ impl IoBuf {
     }
 }
 
+fn write_at(f: &mut File, lid: LogID, bytes: &[u8]) -> std::io::Result<()> {
+    f.seek(SeekFrom::Start(lid))?;
+    f.write_all(bytes)?;
+    f.sync_all()
+}
+
 #[inline(always)]
 fn is_sealed(v: u32) -> bool {
     v >> 31 == 1
@@ -64,7 +64,13 @@ impl<'a> Iterator for Iter<'a> {
                 return None;
             }
 
-            let cached_f = self.config.cached_file();
+            let cached_f = match self.config.cached_file() {
+                Ok(cached_f) => cached_f,
+                Err(e) => {
+                    error!("failed to open log file in Iter::next: {}", e);
+                    return None;
+                }
+            };
             let mut f = cached_f.borrow_mut();
             match f.read_message(lid, self.segment_len, self.use_compression) {
                 Ok(LogRead::Flush(lsn, buf, on_disk_len)) => {
@@ -100,7 +106,7 @@ impl<'a> Iter<'a> {
         trace!("Iter::read_segment lsn: {:?} cur_lsn: {:?}", lsn, self.cur_lsn);
         // TODO done? don't skip segments in SA, unify reuse_segment logic, remove from ordering consistently assert!(lsn >= offset, "lsn should never be less than the log offset");
         assert!(lsn + self.segment_len as Lsn >= self.cur_lsn);
-        let cached_f = self.config.cached_file();
+        let cached_f = self.config.cached_file()?;
         let mut f = cached_f.borrow_mut();
         let segment_header = f.read_segment_header(offset)?;
         assert_eq!(offset % self.segment_len as Lsn, 0);
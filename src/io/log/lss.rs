@@ -11,18 +11,18 @@ use super::*;
 /// # Working with `Log`
 ///
 /// ```
-/// let log = sled::Config::default().log();
-/// let (first_lsn, _first_offset) = log.write(b"1".to_vec());
-/// log.write(b"22".to_vec());
-/// log.write(b"333".to_vec());
+/// let log = sled::Config::default().log().unwrap();
+/// let (first_lsn, _first_offset) = log.write(b"1".to_vec()).unwrap();
+/// log.write(b"22".to_vec()).unwrap();
+/// log.write(b"333".to_vec()).unwrap();
 ///
 /// // stick an abort in the middle, which should not be returned
-/// let res = log.reserve(b"never_gonna_hit_disk".to_vec());
+/// let res = log.reserve(b"never_gonna_hit_disk".to_vec()).unwrap();
 /// res.abort();
 ///
-/// log.write(b"4444".to_vec());
-/// let (last_lsn, _last_offset) = log.write(b"55555".to_vec());
-/// log.make_stable(last_lsn);
+/// log.write(b"4444".to_vec()).unwrap();
+/// let (last_lsn, _last_offset) = log.write(b"55555".to_vec()).unwrap();
+/// log.make_stable(last_lsn).unwrap();
 /// let mut iter = log.iter_from(first_lsn);
 /// assert_eq!(iter.next().unwrap().2, b"1".to_vec());
 /// assert_eq!(iter.next().unwrap().2, b"22".to_vec());
@@ -62,7 +62,9 @@ impl Drop for Log {
 impl Log {
     /// Start the log, open or create the configured file,
     /// and optionally start the periodic buffer flush thread.
-    pub fn start_system(config: Config) -> Log {
+    /// A read-only log only opens an existing file, and has
+    /// nothing to flush.
+    pub fn start_system(config: Config) -> Result<Log> {
         #[cfg(feature = "env_logger")]
         let _r = env_logger::init();
 
@@ -79,7 +81,7 @@ impl Log {
         }
 
 
-        let iobufs = Arc::new(IoBufs::new(config.clone()));
+        let iobufs = Arc::new(IoBufs::new(config.clone())?);
 
         let flusher_shutdown = Arc::new(AtomicBool::new(false));
 
@@ -90,35 +92,41 @@ impl Log {
             flusher_handle: None,
         };
 
-        let flusher_handle =
-            config.get_flush_every_ms().map(|flush_every_ms| {
-                periodic_flusher::flusher(
-                    "log flusher".to_owned(),
-                    iobufs,
-                    flusher_shutdown,
-                    flush_every_ms,
-                ).unwrap()
-            });
-
-        log.flusher_handle = flusher_handle;
+        let flush_every_ms = match config.get_flush_every_ms() {
+            Some(ms) if !config.get_read_only() => Some(ms),
+            _ => None,
+        };
+        if let Some(flush_every_ms) = flush_every_ms {
+            let flusher_handle = periodic_flusher::flusher(
+                "log flusher".to_owned(),
+                iobufs,
+                flusher_shutdown,
+                flush_every_ms,
+            )?;
+            log.flusher_handle = Some(flusher_handle);
+        }
 
-        log
+        Ok(log)
     }
 
-    /// Flush the next io buffer.
-    pub fn flush(&self) {
+    /// Flush the next io buffer, returning an error if a write
+    /// to the file has failed.
+    pub fn flush(&self) -> Result<()> {
         self.iobufs.flush();
+        self.iobufs.check_error()
     }
 
     /// Reserve space in the log for a pending linearized operation.
-    pub fn reserve(&self, buf: Vec<u8>) -> Reservation {
+    /// Fails if a write to the file has failed, in which case
+    /// nothing more can be written.
+    pub fn reserve(&self, buf: Vec<u8>) -> Result<Reservation> {
         self.iobufs.reserve(buf)
     }
 
     /// Write a buffer into the log. Returns the log sequence
     /// number and the file offset of the write.
-    pub fn write(&self, buf: Vec<u8>) -> (Lsn, LogID) {
-        self.iobufs.reserve(buf).complete()
+    pub fn write(&self, buf: Vec<u8>) -> Result<(Lsn, LogID)> {
+        self.iobufs.reserve(buf).map(|res| res.complete())
     }
 
     /// Return an iterator over the log, starting with
@@ -146,26 +154,29 @@ impl Log {
     }
 
     /// read a buffer from the disk
-    pub fn read(&self, lsn: Lsn, lid: LogID) -> io::Result<LogRead> {
+    pub fn read(&self, lsn: Lsn, lid: LogID) -> Result<LogRead> {
         trace!("reading log lsn {} lid {}", lsn, lid);
         // TODO don't skip segments in SA, unify reuse_segment logic, remove from ordering consistently assert!(lsn >= lid, "lsn should never be less than the log offset");
-        self.make_stable(lsn);
-        let cached_f = self.config.cached_file();
+        self.make_stable(lsn)?;
+        let cached_f = self.config.cached_file()?;
         let mut f = cached_f.borrow_mut();
 
         let read = f.read_message(
             lid,
             self.config.get_io_buf_size(),
             self.config.get_use_compression(),
-        );
-
-        read.and_then(|log_read| match log_read {
-            LogRead::Flush(read_lsn, _, _) => {
-                assert_eq!(lsn, read_lsn);
-                Ok(log_read)
+        )?;
+
+        match read {
+            LogRead::Flush(read_lsn, _, _) if read_lsn != lsn => {
+                // the offset holds some other record
+                Err(Error::Corruption {
+                    lsn: lsn,
+                    lid: lid,
+                })
             }
-            _ => Ok(log_read),
-        })
+            _ => Ok(read),
+        }
     }
 
     /// returns the current stable offset written to disk
@@ -174,8 +185,13 @@ impl Log {
     }
 
     /// blocks until the specified log sequence number has
-    /// been made stable on disk
-    pub fn make_stable(&self, lsn: Lsn) {
+    /// been made stable on disk, or writing to the file fails.
+    pub fn make_stable(&self, lsn: Lsn) -> Result<()> {
+        if self.config.get_read_only() {
+            // everything in a read-only log was read from the file
+            return Ok(());
+        }
+
         let start = clock();
 
         // NB we make sure stable > lsn because stable starts at 0,
@@ -186,6 +202,13 @@ impl Log {
             // block until another thread updates the stable lsn
             let waiter = self.iobufs.intervals.lock().unwrap();
 
+            // NB a failed write sets the error before taking the
+            // intervals lock to wake us, so we can't miss it here.
+            if let Err(e) = self.iobufs.check_error() {
+                M.make_stable.measure(clock() - start);
+                return Err(e);
+            }
+
             if self.iobufs.stable() <= lsn {
                 trace!("waiting on cond var for make_stable({})", lsn);
                 let _waiter =
@@ -197,6 +220,7 @@ impl Log {
         }
 
         M.make_stable.measure(clock() - start);
+        Ok(())
     }
 
     // SegmentAccountant access for coordination with the `PageCache`
@@ -111,7 +111,7 @@ impl LogReader for File {
                         // we've hit the end of the file
                         break;
                     }
-                    panic!("{:?}", e);
+                    return Err(e);
                 }
                 if byte[0] != 1 {
                     len += 1;
@@ -144,7 +144,7 @@ impl LogReader for File {
                 let start = clock();
                 let res = Ok(LogRead::Flush(
                     header.lsn,
-                    decompress(&*buf, segment_len).unwrap(),
+                    decompress(&*buf, segment_len)?,
                     len,
                 ));
                 M.decompress.measure(clock() - start);
@@ -94,6 +94,9 @@ pub struct SegmentAccountant {
     pause_rewriting: bool,
     last_given: LogID,
     ordering: BTreeMap<Lsn, LogID>,
+    // lsns that readers need the log to keep the state of, along
+    // with how many readers pinned each one.
+    pinned_lsns: BTreeMap<Lsn, usize>,
 }
 
 // We use a `SegmentDropper` to ensure that we never
@@ -157,7 +160,7 @@ impl Default for SegmentState {
 }
 
 impl Segment {
-    fn _is_free(&self) -> bool {
+    fn is_free(&self) -> bool {
         match self.state {
             Free => true,
             _ => false,
@@ -256,6 +259,12 @@ impl Segment {
         self.lsn.unwrap()
     }
 
+    /// Returns `true` if the segment still holds what was written to it
+    /// starting at the given `Lsn`, and has not been reused since.
+    pub fn holds(&self, segment_lsn: Lsn) -> bool {
+        self.lsn == Some(segment_lsn) && self.state != Free
+    }
+
     /// Add a pid to the Segment. The caller must provide
     /// the Segment's LSN.
     pub fn insert_pid(&mut self, pid: PageID, lsn: Lsn) {
@@ -304,11 +313,11 @@ impl Segment {
 }
 
 impl SegmentAccountant {
-    pub fn new(config: Config) -> SegmentAccountant {
+    pub fn new(config: Config) -> Result<SegmentAccountant> {
         let mut ret = SegmentAccountant::default();
         ret.config = config;
-        ret.scan_segment_lsns();
-        ret
+        ret.scan_segment_lsns()?;
+        Ok(ret)
     }
 
     /// Called from the `PageCache` recovery logic, this initializes the
@@ -322,6 +331,13 @@ impl SegmentAccountant {
             .map(|(_lsn, lid)| *lid)
             .collect();
 
+        let io_buf_size = self.config.get_io_buf_size() as LogID;
+        let tip_segment = if self.recovered_lid % io_buf_size != 0 {
+            Some(self.recovered_lid / io_buf_size * io_buf_size)
+        } else {
+            None
+        };
+
         for (idx, ref mut segment) in segments.iter_mut().enumerate() {
             if segment.lsn.is_none() {
                 continue;
@@ -331,6 +347,14 @@ impl SegmentAccountant {
 
             let lsn = segment.lsn();
 
+            if Some(segment_start) == tip_segment && segment.is_empty() {
+                // the IoBufs will continue writing into this segment, so
+                // it must stay active even though it holds no live pages,
+                // which happens when it only contains an abandoned batch.
+                trace!("keeping empty tip segment {} active", segment_start);
+                continue;
+            }
+
             // populate free and to_clean if the segment has seen
             if segment.is_empty() {
                 // can be reused immediately
@@ -445,13 +469,13 @@ impl SegmentAccountant {
 
     // Scan the log file if we don't know of any Lsn offsets yet, and recover
     // the order of segments, and the highest Lsn.
-    fn scan_segment_lsns(&mut self) {
+    fn scan_segment_lsns(&mut self) -> Result<()> {
         assert!(self.segments.is_empty());
 
         let segment_len = self.config.get_io_buf_size() as LogID;
         let mut cursor = 0;
 
-        let cached_f = self.config.cached_file();
+        let cached_f = self.config.cached_file()?;
         let mut f = cached_f.borrow_mut();
         while let Ok(segment) = f.read_segment_header(cursor) {
             // in the future this can be optimized to just read
@@ -474,7 +498,7 @@ impl SegmentAccountant {
 
         // Check that the last <# io buffers> segments properly
         // link their previous segment pointers.
-        self.clean_tail_tears(&mut f);
+        self.clean_tail_tears(&mut f)?;
 
         // Drop the file so that the `Iter` below is able to borrow
         // the thread's file handle.
@@ -529,13 +553,12 @@ impl SegmentAccountant {
             if !empty_tip {
                 // if we found any later
                 let mut f = cached_f.borrow_mut();
-                let (_, _, len) = f.read_message(
+                let read = f.read_message(
                     tip,
                     segment_len as usize,
                     self.config.get_use_compression(),
-                ).unwrap()
-                    .flush()
-                    .unwrap();
+                )?;
+                let (_, _, len) = read.unwrap();
                 tip += MSG_HEADER_LEN as LogID + len as LogID;
                 self.recovered_lid = tip;
             }
@@ -576,6 +599,8 @@ impl SegmentAccountant {
             self.recovered_lsn,
             self.recovered_lid
         );
+
+        Ok(())
     }
 
     fn free_segment(&mut self, lid: LogID, in_recovery: bool) {
@@ -623,7 +648,7 @@ impl SegmentAccountant {
     // the header. This is important because we expect that
     // the last <# io buffers> segments will join up, and we
     // never reuse buffers within this safety range.
-    fn clean_tail_tears(&mut self, f: &mut File) {
+    fn clean_tail_tears(&mut self, f: &mut File) -> std::io::Result<()> {
         let safety_buffer = self.config.get_io_bufs();
         let logical_tail: Vec<(Lsn, LogID)> = self.ordering
             .iter()
@@ -641,7 +666,7 @@ impl SegmentAccountant {
             }
 
             // check link
-            let segment_header = f.read_segment_header(lid).unwrap();
+            let segment_header = f.read_segment_header(lid)?;
             if !segment_header.ok {
                 error!(
                     "read corrupted segment header during recovery of segment {}",
@@ -677,6 +702,8 @@ impl SegmentAccountant {
                 // false recovery.
             }
         }
+
+        Ok(())
     }
 
     pub fn recovered_lid(&self) -> LogID {
@@ -699,6 +726,48 @@ impl SegmentAccountant {
         self.pause_rewriting = false;
     }
 
+    /// Returns the number of bytes in segments that hold data or are
+    /// being written to, leaving out free segments awaiting reuse.
+    pub fn size_on_disk(&self) -> u64 {
+        let io_buf_size = self.config.get_io_buf_size() as u64;
+        let in_use = self.segments.iter().filter(|s| !s.is_free()).count();
+        in_use as u64 * io_buf_size
+    }
+
+    /// Prevents segments that may contain updates at or below
+    /// `lsn` from being reused until `unpin_lsn` is called.
+    pub fn pin_lsn(&mut self, lsn: Lsn) {
+        *self.pinned_lsns.entry(lsn).or_insert(0) += 1;
+    }
+
+    /// Releases a pin previously taken with `pin_lsn`.
+    pub fn unpin_lsn(&mut self, lsn: Lsn) {
+        let remaining = {
+            let count = self.pinned_lsns.get_mut(&lsn).expect(
+                "unpinned an lsn that was never pinned",
+            );
+            *count -= 1;
+            *count
+        };
+        if remaining == 0 {
+            self.pinned_lsns.remove(&lsn);
+        }
+    }
+
+    /// Whether the free segment at `lid` may still hold updates
+    /// needed at a pinned lsn.
+    fn is_pinned(&mut self, lid: LogID) -> bool {
+        let max_pinned = match self.pinned_lsns.keys().next_back() {
+            Some(&max_pinned) => max_pinned,
+            None => return false,
+        };
+        let idx = self.lid_to_idx(lid);
+        self.segments[idx]
+            .lsn
+            .map(|lsn| lsn <= max_pinned)
+            .unwrap_or(false)
+    }
+
     /// Called by the `PageCache` when a page has been rewritten completely.
     /// We mark all of the old segments that contained the previous state
     /// from the page, and if the old segments are empty or clear enough to
@@ -712,7 +781,33 @@ impl SegmentAccountant {
     ) {
         trace!("mark_replace pid {} at lid {} with lsn {}", pid, new_lid, lsn);
         self.pending_clean.remove(&pid);
+        self.remove_old_lids(pid, lsn, old_lids, new_lid);
+        self.mark_link(pid, lsn, new_lid);
+    }
+
+    /// Called by the `PageCache` when a page has been freed. Like
+    /// `mark_replace`, but the segment holding the `Free` record does
+    /// not keep the page alive, so segments full of blobs that were
+    /// freed can be reused.
+    pub fn mark_free(
+        &mut self,
+        pid: PageID,
+        lsn: Lsn,
+        old_lids: Vec<LogID>,
+        free_lid: LogID,
+    ) {
+        trace!("mark_free pid {} at lid {} with lsn {}", pid, free_lid, lsn);
+        self.pending_clean.remove(&pid);
+        self.remove_old_lids(pid, lsn, old_lids, free_lid);
+    }
 
+    fn remove_old_lids(
+        &mut self,
+        pid: PageID,
+        lsn: Lsn,
+        old_lids: Vec<LogID>,
+        new_lid: LogID,
+    ) {
         let new_idx = new_lid as usize / self.config.get_io_buf_size();
 
         // make sure we're not actively trying to replace the destination
@@ -746,15 +841,9 @@ impl SegmentAccountant {
 
             self.segments[old_idx].remove_pid(pid, lsn);
 
-            if self.segments[old_idx].can_free() {
-                // can be reused immediately
-                self.segments[old_idx].draining_to_free(lsn);
-                self.to_clean.remove(&segment_start);
-                trace!("freed segment {} in replace", segment_start);
-                self.free_segment(segment_start, false);
-            } else if self.segments[old_idx].is_inactive() &&
-                       self.segments[old_idx].live_pct() <=
-                           self.config.get_segment_cleanup_threshold()
+            if self.segments[old_idx].is_inactive() &&
+                self.segments[old_idx].live_pct() <=
+                    self.config.get_segment_cleanup_threshold()
             {
                 // can be cleaned
                 trace!(
@@ -764,9 +853,17 @@ impl SegmentAccountant {
                 self.segments[old_idx].inactive_to_draining(lsn);
                 self.to_clean.insert(segment_start);
             }
-        }
 
-        self.mark_link(pid, lsn, new_lid);
+            // NB this follows the transition to Draining, because a
+            // segment holding a single blob is emptied by one removal.
+            if self.segments[old_idx].can_free() {
+                // can be reused immediately
+                self.segments[old_idx].draining_to_free(lsn);
+                self.to_clean.remove(&segment_start);
+                trace!("freed segment {} in replace", segment_start);
+                self.free_segment(segment_start, false);
+            }
+        }
     }
 
     /// Called by the `PageCache` to find useful pages
@@ -888,16 +985,15 @@ impl SegmentAccountant {
             "unaligned Lsn provided to next!"
         );
 
-        // pop free or add to end
-        let lid = if self.pause_rewriting {
+        // pop free or add to end. NB we only ever take the front of
+        // the free list, to preserve the distance that
+        // ensure_safe_free_distance maintains.
+        let front = self.free.lock().unwrap().front().cloned();
+        let reusable = front.map(|lid| !self.is_pinned(lid)).unwrap_or(false);
+        let lid = if self.pause_rewriting || !reusable {
             self.bump_tip()
         } else {
-            let res = self.free.lock().unwrap().pop_front();
-            if res.is_none() {
-                self.bump_tip()
-            } else {
-                res.unwrap()
-            }
+            self.free.lock().unwrap().pop_front().unwrap()
         };
 
         let last_given = self.last_given;
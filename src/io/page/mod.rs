@@ -1,4 +1,4 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{self, Debug};
 use std::sync::Arc;
 use std::sync::atomic::AtomicUsize;
@@ -54,6 +54,18 @@ pub enum CacheEntry<M: Send + Sync> {
     Flush(Lsn, LogID),
 }
 
+impl<M: Send + Sync> CacheEntry<M> {
+    /// The `Lsn` at which this entry's page fragment was logged.
+    pub fn lsn(&self) -> Lsn {
+        match *self {
+            CacheEntry::MergedResident(_, lsn, _) |
+            CacheEntry::Resident(_, lsn, _) |
+            CacheEntry::PartialFlush(lsn, _) |
+            CacheEntry::Flush(lsn, _) => lsn,
+        }
+    }
+}
+
 /// `LoggedUpdate` is for writing blocks of `Update`'s to disk
 /// sequentially, to reduce IO during page reads.
 #[serde(bound(deserialize = ""))]
@@ -74,6 +86,25 @@ enum Update<PageFrag>
     Compact(PageFrag),
     Free,
     Alloc,
+    BatchStart,
+    /// An `Append` that is only recovered if the batch that began at
+    /// the given `Lsn` commits.
+    BatchAppend(Lsn, PageFrag),
+    BatchCommit(Lsn),
+    BatchAbort(Lsn),
+}
+
+impl<PageFrag> Update<PageFrag>
+    where PageFrag: DeserializeOwned + Serialize
+{
+    fn is_batch_marker(&self) -> bool {
+        match *self {
+            Update::BatchStart |
+            Update::BatchCommit(_) |
+            Update::BatchAbort(_) => true,
+            _ => false,
+        }
+    }
 }
 
 struct PidDropper(PageID, Arc<Stack<PageID>>);
@@ -1,5 +1,6 @@
 use std::io::{Read, Seek, Write};
 use std::path::Path;
+use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
 use coco::epoch::{Owned, Ptr, Scope, pin};
@@ -49,23 +50,29 @@ use super::*;
 ///     let path = "test_pagecache_doc.log";
 ///     let conf = sled::Config::default().path(path.to_owned());
 ///     let pc = sled::PageCache::new(TestMaterializer,
-///                                   conf.clone());
+///                                   conf.clone()).unwrap();
 ///     pin(|scope| {
-///         let (id, key) = pc.allocate(scope);
+///         let (id, key) = pc.allocate(scope).unwrap();
 ///
 ///         // The first item in a page should be set using replace,
 ///         // which signals that this is the beginning of a new
 ///         // page history, and that any previous items associated
 ///         // with this page should be forgotten.
-///         let key = pc.replace(id, key, "a".to_owned(), scope).unwrap();
+///         let key = pc.replace(id, key, "a".to_owned(), scope)
+///             .unwrap()
+///             .unwrap();
 ///
 ///         // Subsequent atomic updates should be added with link.
-///         let key = pc.link(id, key, "b".to_owned(), scope).unwrap();
-///         let _key = pc.link(id, key, "c".to_owned(), scope).unwrap();
+///         let key = pc.link(id, key, "b".to_owned(), scope)
+///             .unwrap()
+///             .unwrap();
+///         let _key = pc.link(id, key, "c".to_owned(), scope)
+///             .unwrap()
+///             .unwrap();
 ///
 ///         // When getting a page, the provide `Materializer` is
 ///         // used to merge all pages together.
-///         let (consolidated, _key) = pc.get(id, scope).unwrap();
+///         let (consolidated, _key) = pc.get(id, scope).unwrap().unwrap();
 ///
 ///         assert_eq!(consolidated, "abc".to_owned());
 ///     });
@@ -86,6 +93,64 @@ pub struct PageCache<PM, P, R>
     lru: Lru,
     updates: AtomicUsize,
     last_snapshot: Mutex<Option<Snapshot<R>>>,
+    // the number of pinned lsns, which readers at an lsn may need
+    // older versions of pages for.
+    pinned: AtomicUsize,
+    // the stacks that replaced or freed pages had while any lsn
+    // was pinned, for reads at that lsn.
+    history: Mutex<History<P>>,
+    // the pages that each open batch has linked updates to, keyed by
+    // the lsn the batch began at.
+    batches: Mutex<BTreeMap<Lsn, BTreeSet<PageID>>>,
+}
+
+// the lsns returned by pin_lsn that are still pinned, and the old
+// stacks of each page that reads at them may need.
+struct History<P>
+    where P: 'static + Send + Sync
+{
+    pins: BTreeMap<Lsn, usize>,
+    stacks: HashMap<PageID, Vec<Vec<CacheEntry<P>>>>,
+}
+
+impl<P> History<P>
+    where P: 'static + Send + Sync
+{
+    // drops the stacks of a page that no pinned lsn reads, which
+    // leaves at most the newest one at or below each of them.
+    fn prune(&mut self, pid: PageID) {
+        let remaining = match self.stacks.get_mut(&pid) {
+            Some(stacks) => {
+                let base_lsn = |stack: &Vec<CacheEntry<P>>| {
+                    stack.last().unwrap().lsn()
+                };
+                let read: Vec<usize> = self.pins
+                    .keys()
+                    .filter_map(|&pin| {
+                        stacks
+                            .iter()
+                            .enumerate()
+                            .filter(|&(_, stack)| base_lsn(stack) <= pin)
+                            .max_by_key(|&(_, stack)| {
+                                (base_lsn(stack), stack.len())
+                            })
+                            .map(|(idx, _)| idx)
+                    })
+                    .collect();
+                let mut idx = 0;
+                stacks.retain(|_| {
+                    let kept = read.contains(&idx);
+                    idx += 1;
+                    kept
+                });
+                stacks.len()
+            }
+            None => return,
+        };
+        if remaining == 0 {
+            self.stacks.remove(&pid);
+        }
+    }
 }
 
 unsafe impl<PM, P, R> Send for PageCache<PM, P, R>
@@ -129,28 +194,34 @@ impl<PM, P, R> PageCache<PM, P, R>
           R: Debug + Clone + Serialize + DeserializeOwned + Send
 {
     /// Instantiate a new `PageCache`.
-    pub fn new(pm: PM, config: Config) -> PageCache<PM, P, R> {
+    pub fn new(pm: PM, config: Config) -> Result<PageCache<PM, P, R>> {
         let cache_capacity = config.get_cache_capacity();
         let cache_shard_bits = config.get_cache_bits();
         let lru = Lru::new(cache_capacity, cache_shard_bits);
 
-        PageCache {
+        Ok(PageCache {
             t: pm,
             config: config.clone(),
             inner: Radix::default(),
             max_pid: AtomicUsize::new(0),
             free: Arc::new(Stack::default()),
-            log: Log::start_system(config),
+            log: Log::start_system(config)?,
             lru: lru,
             updates: AtomicUsize::new(0),
             last_snapshot: Mutex::new(None),
-        }
+            pinned: AtomicUsize::new(0),
+            history: Mutex::new(History {
+                pins: BTreeMap::new(),
+                stacks: HashMap::new(),
+            }),
+            batches: Mutex::new(BTreeMap::new()),
+        })
     }
 
     /// Read updates from the log, apply them to our pagecache.
-    pub fn recover(&mut self) -> Option<R> {
+    pub fn recover(&mut self) -> Result<Option<R>> {
         // pull any existing snapshot off disk
-        self.read_snapshot();
+        self.read_snapshot()?;
 
         // we call advance_snapshot here to "catch-up" the snapshot using the
         // logged updates before recovering from it. this allows us to reuse
@@ -158,7 +229,7 @@ impl<PM, P, R> PageCache<PM, P, R>
         // also important for ensuring that we feed the provided `Materializer`
         // a single, linearized history, rather than going back in time
         // when generating a snapshot.
-        self.advance_snapshot();
+        self.advance_snapshot(true)?;
 
         // now we read it back in
         self.load_snapshot();
@@ -176,12 +247,15 @@ impl<PM, P, R> PageCache<PM, P, R>
             recovery
         );
 
-        recovery
+        Ok(recovery)
     }
 
     /// Create a new page, trying to reuse old freed pages if possible
     /// to maximize underlying `Radix` pointer density.
-    pub fn allocate<'s>(&self, _: &'s Scope) -> (PageID, HPtr<'s, P>) {
+    pub fn allocate<'s>(
+        &self,
+        scope: &'s Scope,
+    ) -> Result<(PageID, HPtr<'s, P>)> {
         let pid = self.free.pop().unwrap_or_else(
             || self.max_pid.fetch_add(1, SeqCst),
         );
@@ -198,20 +272,22 @@ impl<PM, P, R> PageCache<PM, P, R>
         let bytes = serialize(&prepend, Infinite).unwrap();
         M.serialize.measure(clock() - serialize_start);
 
-        let (lsn, lid) = self.log.write(bytes);
+        let (lsn, lid) = match self.log.write(bytes) {
+            Ok(written) => written,
+            Err(e) => {
+                self.inner.del(pid, scope);
+                self.free.push(pid);
+                return Err(e);
+            }
+        };
         trace!("allocating pid {} at lsn {} lid {}", pid, lsn, lid);
 
-        (pid, Ptr::null())
+        Ok((pid, Ptr::null()))
     }
 
     /// Free a particular page.
-    pub fn free(&self, pid: PageID) {
+    pub fn free(&self, pid: PageID) -> Result<()> {
         pin(|scope| {
-            let deleted = self.inner.del(pid, scope);
-            if deleted.is_none() {
-                return;
-            }
-
             // write info to log
             let prepend: LoggedUpdate<P> = LoggedUpdate {
                 pid: pid,
@@ -221,7 +297,21 @@ impl<PM, P, R> PageCache<PM, P, R>
             let bytes = serialize(&prepend, Infinite).unwrap();
             M.serialize.measure(clock() - serialize_start);
 
-            let res = self.log.reserve(bytes);
+            // NB the reservation must precede recording the page's
+            // history, so that an lsn pinned after the check in
+            // record_history is above our Free.
+            let res = self.log.reserve(bytes)?;
+
+            if let Some(stack_ptr) = self.inner.get(pid, scope) {
+                let head = unsafe { stack_ptr.deref().head(scope) };
+                self.record_history(pid, head, scope);
+            }
+
+            let deleted = self.inner.del(pid, scope);
+            if deleted.is_none() {
+                res.abort();
+                return Ok(());
+            }
 
             // add pid to free stack to reduce fragmentation over time
             unsafe {
@@ -231,7 +321,7 @@ impl<PM, P, R> PageCache<PM, P, R>
                 let lid = res.lid();
 
                 self.log.with_sa(|sa| {
-                    sa.mark_replace(
+                    sa.mark_free(
                         pid,
                         lsn,
                         lids_from_stack(cas_key, scope),
@@ -251,7 +341,269 @@ impl<PM, P, R> PageCache<PM, P, R>
                 scope.defer_drop(ptr);
                 scope.flush();
             }
-        });
+
+            Ok(())
+        })
+    }
+
+    /// Begin a batch of updates which will be recovered atomically.
+    /// The updates linked with `link_in_batch` and the returned `Lsn`
+    /// are recovered in full if the batch is passed to `commit_batch`,
+    /// and not at all if it is aborted, or never closed before a
+    /// crash. Other updates are recovered as usual. Pages that an open
+    /// batch has linked to are not compacted until it closes, and
+    /// callers should not `replace` them either, since the new page
+    /// would keep the batch's updates even if it never commits.
+    pub fn begin_batch(&self) -> Result<Lsn> {
+        let batch_lsn = self.write_update(Update::BatchStart)?;
+        self.batches.lock().unwrap().insert(batch_lsn, BTreeSet::new());
+        Ok(batch_lsn)
+    }
+
+    /// Commit a batch that was started with `begin_batch`, allowing its
+    /// updates to be recovered.
+    pub fn commit_batch(&self, batch_lsn: Lsn) -> Result<()> {
+        self.close_batch(batch_lsn, Update::BatchCommit(batch_lsn))
+    }
+
+    /// Abort a batch that was started with `begin_batch`, so that none
+    /// of its updates are recovered. They are not removed from the
+    /// pages they were linked to.
+    pub fn abort_batch(&self, batch_lsn: Lsn) -> Result<()> {
+        self.close_batch(batch_lsn, Update::BatchAbort(batch_lsn))
+    }
+
+    fn close_batch(&self, batch_lsn: Lsn, marker: Update<P>) -> Result<()> {
+        // if the marker can't be logged, the batch stays open, and its
+        // pages are never compacted.
+        self.write_update(marker)?;
+        self.batches.lock().unwrap().remove(&batch_lsn);
+        Ok(())
+    }
+
+    /// Returns `true` if a batch that is still open has linked an
+    /// update to the page.
+    pub fn in_open_batch(&self, pid: PageID) -> bool {
+        let batches = self.batches.lock().unwrap();
+        batches.values().any(|pids| pids.contains(&pid))
+    }
+
+    fn write_update(&self, update: Update<P>) -> Result<Lsn> {
+        let prepend: LoggedUpdate<P> = LoggedUpdate {
+            pid: 0,
+            update: update,
+        };
+        let serialize_start = clock();
+        let bytes = serialize(&prepend, Infinite).unwrap();
+        M.serialize.measure(clock() - serialize_start);
+
+        let (lsn, lid) = self.log.write(bytes)?;
+        trace!("wrote batch marker at lsn {} lid {}", lsn, lid);
+
+        Ok(lsn)
+    }
+
+    /// Returns the number of pages that are currently allocated.
+    pub fn page_count(&self) -> usize {
+        self.pids().len()
+    }
+
+    /// Returns the IDs of the pages that are currently allocated, in
+    /// order.
+    pub fn pids(&self) -> Vec<PageID> {
+        pin(|scope| {
+            (0..self.max_pid.load(SeqCst))
+                .filter(|&pid| self.inner.get(pid, scope).is_some())
+                .collect()
+        })
+    }
+
+    /// Returns the number of bytes of the log in use, according to the
+    /// segments the `SegmentAccountant` has not yet freed.
+    pub fn size_on_disk(&self) -> u64 {
+        self.log.with_sa(|sa| sa.size_on_disk())
+    }
+
+    /// Returns the `Lsn` at which the page fragment pointed to by a key
+    /// returned from `link` or `replace` was written to the log.
+    pub fn lsn_of<'s>(&self, key: HPtr<'s, P>) -> Lsn {
+        unsafe { key.deref() }.lsn()
+    }
+
+    /// Pin the current `Lsn`, so that `get_at` can read every page as
+    /// of this moment until `unpin_lsn` is called. Every update at or
+    /// below the returned `Lsn` is stable once this returns. Log
+    /// segments that may hold updates needed at the pinned `Lsn` are
+    /// not reused while it is pinned. Nothing stays pinned if this
+    /// fails.
+    pub fn pin_lsn(&self) -> Result<Lsn> {
+        {
+            let _history = self.history.lock().unwrap();
+            self.pinned.fetch_add(1, SeqCst);
+        }
+
+        // keep every segment until we know the lsn to pin
+        self.log.with_sa(|sa| sa.pin_lsn(Lsn::max_value()));
+
+        // an aborted reservation gives us an lsn that every update
+        // linked or replaced from now on will be above. nothing can
+        // be written to a read-only log, so every update is below
+        // the stable offset.
+        let stable = if self.config.get_read_only() {
+            Ok(self.log.stable_offset())
+        } else {
+            self.log.reserve(vec![0]).and_then(|reservation| {
+                let lsn = reservation.lsn();
+                reservation.abort();
+
+                // wait for updates below our lsn to finish being applied
+                self.log.make_stable(lsn).map(|_| lsn)
+            })
+        };
+
+        match stable {
+            Ok(lsn) => {
+                self.log.with_sa(|sa| {
+                    sa.pin_lsn(lsn);
+                    sa.unpin_lsn(Lsn::max_value());
+                });
+                let mut history = self.history.lock().unwrap();
+                *history.pins.entry(lsn).or_insert(0) += 1;
+                Ok(lsn)
+            }
+            Err(e) => {
+                self.unpin_lsn(Lsn::max_value());
+                Err(e)
+            }
+        }
+    }
+
+    /// Release an `Lsn` returned by `pin_lsn`.
+    pub fn unpin_lsn(&self, lsn: Lsn) {
+        self.log.with_sa(|sa| sa.unpin_lsn(lsn));
+
+        let mut history = self.history.lock().unwrap();
+        let released = match history.pins.get_mut(&lsn) {
+            Some(count) => {
+                *count -= 1;
+                *count == 0
+            }
+            None => false,
+        };
+        if released {
+            history.pins.remove(&lsn);
+        }
+        if self.pinned.fetch_sub(1, SeqCst) == 1 {
+            history.stacks.clear();
+        } else if self.pins_known(&history) {
+            let pids: Vec<PageID> = history.stacks.keys().cloned().collect();
+            for pid in pids {
+                history.prune(pid);
+            }
+        }
+    }
+
+    // whether every pinned lsn is known, and none is still being
+    // chosen by pin_lsn. the history is only pruned then, since an
+    // lsn being chosen may read any stack recorded in the meantime.
+    fn pins_known(&self, history: &History<P>) -> bool {
+        let known: usize = history.pins.values().sum();
+        known == self.pinned.load(SeqCst)
+    }
+
+    /// Returns the number of old page versions kept for reads at
+    /// pinned `Lsn`s.
+    #[doc(hidden)]
+    pub fn history_len(&self) -> usize {
+        let history = self.history.lock().unwrap();
+        history.stacks.values().map(|stacks| stacks.len()).sum()
+    }
+
+    /// Retrieve a page as it was at an `Lsn` returned by `pin_lsn`,
+    /// ignoring page fragments logged after it.
+    pub fn get_at<'s>(
+        &self,
+        pid: PageID,
+        lsn: Lsn,
+        scope: &'s Scope,
+    ) -> Result<Option<P>> {
+        // NB the current stack must be read before the history, since
+        // a replace records the old stack before swapping it out.
+        let mut stacks: Vec<Vec<CacheEntry<P>>> = vec![];
+        if let Some(stack_ptr) = self.inner.get(pid, scope) {
+            let head = unsafe { stack_ptr.deref().head(scope) };
+            let stack_iter = StackIter::from_ptr(head, scope);
+            stacks.push(stack_iter.map(|ptr| (*ptr).clone()).collect());
+        }
+        if let Some(old_stacks) =
+            self.history.lock().unwrap().stacks.get(&pid)
+        {
+            stacks.extend(old_stacks.iter().cloned());
+        }
+
+        // the stack with the newest base at or below our lsn. stacks
+        // with the same base only differ by the fragments linked on
+        // top, so the longest one has seen the most of them.
+        let stack = stacks
+            .into_iter()
+            .filter(|stack| {
+                stack.last().map(|base| base.lsn() <= lsn).unwrap_or(false)
+            })
+            .max_by_key(|stack| (stack.last().unwrap().lsn(), stack.len()));
+
+        let stack = match stack {
+            Some(stack) => stack,
+            None => return Ok(None),
+        };
+
+        let mut frags = vec![];
+        for entry in stack.into_iter().filter(|entry| entry.lsn() <= lsn) {
+            match entry {
+                CacheEntry::MergedResident(page_frag, _, _) => {
+                    // already merged with the rest of the stack
+                    frags.push(page_frag);
+                    break;
+                }
+                CacheEntry::Resident(page_frag, _, _) => frags.push(page_frag),
+                CacheEntry::PartialFlush(lsn, lid) |
+                CacheEntry::Flush(lsn, lid) => {
+                    frags.push(self.pull(lsn, lid)?)
+                }
+            }
+        }
+
+        let combined: Vec<&P> = frags.iter().rev().collect();
+        Ok(Some(self.t.merge(&*combined)))
+    }
+
+    fn record_history<'s>(
+        &self,
+        pid: PageID,
+        head: HPtr<'s, P>,
+        scope: &'s Scope,
+    ) {
+        if self.pinned.load(SeqCst) == 0 {
+            return;
+        }
+
+        let mut history = self.history.lock().unwrap();
+        if self.pinned.load(SeqCst) == 0 {
+            return;
+        }
+        let stack_iter = StackIter::from_ptr(head, scope);
+        let stack: Vec<CacheEntry<P>> =
+            stack_iter.map(|ptr| (*ptr).clone()).collect();
+        if !stack.is_empty() {
+            history.stacks.entry(pid).or_insert_with(Vec::new).push(stack);
+            if self.pins_known(&history) {
+                history.prune(pid);
+            }
+        }
+    }
+
+    /// Blocks until the given `Lsn` has been made stable on disk.
+    pub fn make_stable(&self, lsn: Lsn) -> Result<()> {
+        self.log.make_stable(lsn)
     }
 
     /// Try to retrieve a page by its logical ID.
@@ -259,10 +611,10 @@ impl<PM, P, R> PageCache<PM, P, R>
         &self,
         pid: PageID,
         scope: &'s Scope,
-    ) -> Option<(PM::PageFrag, HPtr<'s, P>)> {
+    ) -> Result<Option<(PM::PageFrag, HPtr<'s, P>)>> {
         let stack_ptr = self.inner.get(pid, scope);
         if stack_ptr.is_none() {
-            return None;
+            return Ok(None);
         }
 
         let stack_ptr = stack_ptr.unwrap();
@@ -272,7 +624,11 @@ impl<PM, P, R> PageCache<PM, P, R>
         self.page_in(pid, head, stack_ptr, scope)
     }
 
-    fn page_out<'s>(&self, to_evict: Vec<PageID>, scope: &'s Scope) {
+    fn page_out<'s>(
+        &self,
+        to_evict: Vec<PageID>,
+        scope: &'s Scope,
+    ) -> Result<()> {
         let start = clock();
         for pid in to_evict {
             let stack_ptr = self.inner.get(pid, scope);
@@ -289,25 +645,24 @@ impl<PM, P, R> PageCache<PM, P, R>
                 stack_iter.map(|ptr| (*ptr).clone()).collect();
 
             // ensure the last entry is a Flush
-            let last = cache_entries.pop().map(|last_ce| match last_ce {
-                CacheEntry::MergedResident(_, lsn, lid) |
-                CacheEntry::Resident(_, lsn, lid) |
-                CacheEntry::Flush(lsn, lid) => {
+            let last = match cache_entries.pop() {
+                Some(CacheEntry::MergedResident(_, lsn, lid)) |
+                Some(CacheEntry::Resident(_, lsn, lid)) |
+                Some(CacheEntry::Flush(lsn, lid)) => {
                     // NB stabilize the most recent LSN before
                     // paging out! This SHOULD very rarely block...
                     // TODO measure to make sure
-                    self.log.make_stable(lsn);
+                    self.log.make_stable(lsn)?;
                     CacheEntry::Flush(lsn, lid)
                 }
-                CacheEntry::PartialFlush(_, _) => {
+                Some(CacheEntry::PartialFlush(_, _)) => {
                     panic!("got PartialFlush at end of stack...")
                 }
-            });
-
-            if last.is_none() {
-                M.page_out.measure(clock() - start);
-                return;
-            }
+                None => {
+                    M.page_out.measure(clock() - start);
+                    return Ok(());
+                }
+            };
 
             let mut new_stack = Vec::with_capacity(cache_entries.len() + 1);
             for entry in cache_entries {
@@ -322,7 +677,7 @@ impl<PM, P, R> PageCache<PM, P, R>
                     }
                 }
             }
-            new_stack.push(last.unwrap());
+            new_stack.push(last);
             let node = node_from_frag_vec(new_stack);
 
             debug_delay();
@@ -335,27 +690,41 @@ impl<PM, P, R> PageCache<PM, P, R>
             }
         }
         M.page_out.measure(clock() - start);
+        Ok(())
     }
 
-    fn pull(&self, lsn: Lsn, lid: LogID) -> P {
+    fn pull(&self, lsn: Lsn, lid: LogID) -> Result<P> {
         trace!("pulling lsn {} lid {} from disk", lsn, lid);
         let start = clock();
-        let bytes = match self.log.read(lsn, lid).map_err(|_| ()) {
-            Ok(LogRead::Flush(_lsn, data, _len)) => data,
-            _ => panic!("read invalid data at lid {}", lid),
+        let corrupt = Error::Corruption {
+            lsn: lsn,
+            lid: lid,
+        };
+        let bytes = match self.log.read(lsn, lid)? {
+            LogRead::Flush(_lsn, data, _len) => data,
+            _ => {
+                error!("read invalid data at lid {}", lid);
+                return Err(corrupt);
+            }
         };
 
         let deserialize_start = clock();
-        let logged_update = deserialize::<LoggedUpdate<P>>(&*bytes)
-            .map_err(|_| ())
-            .expect("failed to deserialize data");
+        let deserialization = deserialize::<LoggedUpdate<P>>(&*bytes);
         M.deserialize.measure(clock() - deserialize_start);
 
         M.pull.measure(clock() - start);
-        match logged_update.update {
-            Update::Compact(page_frag) |
-            Update::Append(page_frag) => page_frag,
-            _ => panic!("non-append/compact found in pull"),
+        match deserialization.map(|logged_update| logged_update.update) {
+            Ok(Update::Compact(page_frag)) |
+            Ok(Update::Append(page_frag)) |
+            Ok(Update::BatchAppend(_, page_frag)) => Ok(page_frag),
+            Ok(_) => {
+                error!("non-append/compact found in pull at lid {}", lid);
+                Err(corrupt)
+            }
+            Err(e) => {
+                error!("failed to deserialize data at lid {}: {:?}", lid, e);
+                Err(corrupt)
+            }
         }
     }
 
@@ -365,7 +734,7 @@ impl<PM, P, R> PageCache<PM, P, R>
         mut head: Ptr<'s, ds::stack::Node<CacheEntry<P>>>,
         stack_ptr: Ptr<'s, ds::stack::Stack<CacheEntry<P>>>,
         scope: &'s Scope,
-    ) -> Option<(PM::PageFrag, HPtr<'s, P>)> {
+    ) -> Result<Option<(PM::PageFrag, HPtr<'s, P>)>> {
         let start = clock();
         let stack_iter = StackIter::from_ptr(head, scope);
 
@@ -386,7 +755,7 @@ impl<PM, P, R> PageCache<PM, P, R>
                     if lids.is_empty() {
                         // Short circuit merging and fix-up if we only
                         // have one frag.
-                        return Some((page_frag.clone(), head));
+                        return Ok(Some((page_frag.clone(), head)));
                     }
                     if !merged_resident {
                         to_merge.push(page_frag);
@@ -404,7 +773,7 @@ impl<PM, P, R> PageCache<PM, P, R>
 
         if lids.is_empty() {
             M.page_in.measure(clock() - start);
-            return None;
+            return Ok(None);
         }
 
         let mut fetched = Vec::with_capacity(lids.len());
@@ -419,13 +788,13 @@ impl<PM, P, R> PageCache<PM, P, R>
                 let mut pulled: Vec<P> = to_pull
                     .par_iter()
                     .map(|&(lsn, lid)| self.pull(lsn, lid))
-                    .collect();
+                    .collect::<Result<_>>()?;
                 fetched.append(&mut pulled);
             }
 
             #[cfg(not(feature = "rayon"))]
             for &(lsn, lid) in to_pull {
-                fetched.push(self.pull(lsn, lid));
+                fetched.push(self.pull(lsn, lid)?);
             }
         }
 
@@ -443,9 +812,13 @@ impl<PM, P, R> PageCache<PM, P, R>
         let size = std::mem::size_of_val(&merged);
         let to_evict = self.lru.accessed(pid, size);
         trace!("accessed pid {} -> paging out pid {:?}", pid, to_evict);
-        self.page_out(to_evict, scope);
+        self.page_out(to_evict, scope)?;
 
-        if lids.len() > self.config.get_page_consolidation_threshold() {
+        // a read-only log can't take the consolidated page, so it is
+        // only fixed up in memory below.
+        if lids.len() > self.config.get_page_consolidation_threshold() &&
+            !self.config.get_read_only()
+        {
             trace!("consolidating pid {} with len {}!", pid, lids.len());
             match self.replace_recurse_once(
                 pid,
@@ -453,9 +826,9 @@ impl<PM, P, R> PageCache<PM, P, R>
                 merged.clone(),
                 scope,
                 true,
-            ) {
+            )? {
                 Ok(new_head) => head = new_head,
-                Err(None) => return None,
+                Err(None) => return Ok(None),
                 _ => (),
             }
         } else if !fetched.is_empty() ||
@@ -507,7 +880,7 @@ impl<PM, P, R> PageCache<PM, P, R>
 
         M.page_in.measure(clock() - start);
 
-        Some((merged, head))
+        Ok(Some((merged, head)))
     }
 
     /// Replace an existing page with a different set of `PageFrag`s.
@@ -520,7 +893,7 @@ impl<PM, P, R> PageCache<PM, P, R>
         old: HPtr<'s, P>,
         new: P,
         scope: &'s Scope,
-    ) -> Result<HPtr<'s, P>, Option<HPtr<'s, P>>> {
+    ) -> Result<CasResult<'s, P>> {
         self.replace_recurse_once(pid, old, new, scope, false)
     }
 
@@ -531,11 +904,15 @@ impl<PM, P, R> PageCache<PM, P, R>
         new: P,
         scope: &'s Scope,
         recursed: bool,
-    ) -> Result<HPtr<'s, P>, Option<HPtr<'s, P>>> {
+    ) -> Result<CasResult<'s, P>> {
         trace!("replacing pid {}", pid);
+        if recursed && self.in_open_batch(pid) {
+            // this compaction can wait until the batch closes
+            return Ok(Err(Some(old)));
+        }
         let stack_ptr = self.inner.get(pid, scope);
         if stack_ptr.is_none() {
-            return Err(None);
+            return Ok(Err(None));
         }
         let stack_ptr = stack_ptr.unwrap();
 
@@ -546,7 +923,7 @@ impl<PM, P, R> PageCache<PM, P, R>
         let serialize_start = clock();
         let bytes = serialize(&replace, Infinite).unwrap();
         M.serialize.measure(clock() - serialize_start);
-        let log_reservation = self.log.reserve(bytes);
+        let log_reservation = self.log.reserve(bytes)?;
         let lsn = log_reservation.lsn();
         let lid = log_reservation.lid();
 
@@ -554,6 +931,11 @@ impl<PM, P, R> PageCache<PM, P, R>
 
         let node = node_from_frag_vec(vec![cache_entry]).into_ptr(scope);
 
+        // readers at a pinned lsn may need the stack we are replacing.
+        // if our swap fails, the recorded stack is still a real past
+        // version of the page, so it can stay.
+        self.record_history(pid, old, scope);
+
         debug_delay();
         let result = unsafe { stack_ptr.deref().cas(old.clone(), node, scope) };
 
@@ -566,9 +948,18 @@ impl<PM, P, R> PageCache<PM, P, R>
                 sa.mark_replace(pid, lsn, lids, lid);
                 if recursed { None } else { sa.clean(Some(pid)) }
             });
+
+            // NB complete must happen AFTER calls to SA, because
+            // when the iobuf's n_writers hits 0, we may transition
+            // the segment to inactive, resulting in a race otherwise.
+            log_reservation.complete();
+
+            // NB cleaning must happen after complete, because holding
+            // our reservation while reserving space for the cleaned
+            // page can keep the io buffers from ever being written.
             if let Some(to_clean) = to_clean {
                 assert_ne!(pid, to_clean);
-                if let Some((page, key)) = self.get(to_clean, scope) {
+                if let Ok(Some((page, key))) = self.get(to_clean, scope) {
                     let _ = self.replace_recurse_once(
                         to_clean,
                         key,
@@ -579,22 +970,17 @@ impl<PM, P, R> PageCache<PM, P, R>
                 }
             }
 
-            // NB complete must happen AFTER calls to SA, because
-            // when the iobuf's n_writers hits 0, we may transition
-            // the segment to inactive, resulting in a race otherwise.
-            log_reservation.complete();
-
             let count = self.updates.fetch_add(1, SeqCst) + 1;
             let should_snapshot =
                 count % self.config.get_snapshot_after_ops() == 0;
             if should_snapshot {
-                self.advance_snapshot();
+                self.maybe_advance_snapshot();
             }
         } else {
             log_reservation.abort();
         }
 
-        result.map_err(|e| Some(e))
+        Ok(result.map_err(|e| Some(e)))
     }
 
 
@@ -608,25 +994,58 @@ impl<PM, P, R> PageCache<PM, P, R>
         old: HPtr<'s, P>,
         new: P,
         scope: &'s Scope,
-    ) -> Result<HPtr<'s, P>, Option<HPtr<'s, P>>> {
+    ) -> Result<CasResult<'s, P>> {
+        self.link_inner(pid, old, new, None, scope)
+    }
+
+    /// Like `link`, but the `PageFrag` is part of a batch started with
+    /// `begin_batch`, and is only recovered if the batch commits.
+    pub fn link_in_batch<'s>(
+        &self,
+        pid: PageID,
+        old: HPtr<'s, P>,
+        new: P,
+        batch_lsn: Lsn,
+        scope: &'s Scope,
+    ) -> Result<CasResult<'s, P>> {
+        // NB the page must be known to be in the batch before the frag
+        // is visible, because a compaction that reads it first would
+        // otherwise log the batch's update outside of it.
+        self.batches
+            .lock()
+            .unwrap()
+            .get_mut(&batch_lsn)
+            .expect("linked to a batch that is not open")
+            .insert(pid);
+        self.link_inner(pid, old, new, Some(batch_lsn), scope)
+    }
+
+    fn link_inner<'s>(
+        &self,
+        pid: PageID,
+        old: HPtr<'s, P>,
+        new: P,
+        batch_lsn: Option<Lsn>,
+        scope: &'s Scope,
+    ) -> Result<CasResult<'s, P>> {
         let stack_ptr = self.inner.get(pid, scope);
         if stack_ptr.is_none() {
-            return Err(None);
+            return Ok(Err(None));
         }
         let stack_ptr = stack_ptr.unwrap();
 
         let prepend: LoggedUpdate<P> = LoggedUpdate {
             pid: pid,
-            update: if old.is_null() {
-                Update::Compact(new.clone())
-            } else {
-                Update::Append(new.clone())
+            update: match batch_lsn {
+                Some(batch_lsn) => Update::BatchAppend(batch_lsn, new.clone()),
+                None if old.is_null() => Update::Compact(new.clone()),
+                None => Update::Append(new.clone()),
             },
         };
         let serialize_start = clock();
         let bytes = serialize(&prepend, Infinite).unwrap();
         M.serialize.measure(clock() - serialize_start);
-        let log_reservation = self.log.reserve(bytes);
+        let log_reservation = self.log.reserve(bytes)?;
         let lsn = log_reservation.lsn();
         let lid = log_reservation.lid();
 
@@ -641,8 +1060,15 @@ impl<PM, P, R> PageCache<PM, P, R>
                 sa.mark_link(pid, lsn, lid);
                 sa.clean(None)
             });
+
+            // NB complete must happen AFTER calls to SA, because
+            // when the iobuf's n_writers hits 0, we may transition
+            // the segment to inactive, resulting in a race otherwise.
+            log_reservation.complete();
+
+            // NB see replace_recurse_once for why this follows complete
             if let Some(to_clean) = to_clean {
-                if let Some((page, key)) = self.get(to_clean, scope) {
+                if let Ok(Some((page, key))) = self.get(to_clean, scope) {
                     let _ = self.replace_recurse_once(
                         to_clean,
                         key,
@@ -653,26 +1079,30 @@ impl<PM, P, R> PageCache<PM, P, R>
                 }
             }
 
-            // NB complete must happen AFTER calls to SA, because
-            // when the iobuf's n_writers hits 0, we may transition
-            // the segment to inactive, resulting in a race otherwise.
-            log_reservation.complete();
-
             let count = self.updates.fetch_add(1, SeqCst) + 1;
             let should_snapshot =
                 count % self.config.get_snapshot_after_ops() == 0;
             if should_snapshot {
-                self.advance_snapshot();
+                self.maybe_advance_snapshot();
             }
         }
 
-        result.map_err(|e| Some(e))
+        Ok(result.map_err(|e| Some(e)))
     }
 
-    fn advance_snapshot(&self) {
+    // the update that triggered a snapshot has already been logged, so
+    // failing to snapshot is only reported. the log is replayed from
+    // the last snapshot that was written.
+    fn maybe_advance_snapshot(&self) {
+        if let Err(e) = self.advance_snapshot(false) {
+            error!("failed to advance snapshot: {}", e);
+        }
+    }
+
+    fn advance_snapshot(&self, in_recovery: bool) -> Result<()> {
         let start = clock();
 
-        self.log.flush();
+        self.log.flush()?;
 
         let snapshot_opt_res = self.last_snapshot.try_lock();
         if snapshot_opt_res.is_err() {
@@ -682,7 +1112,7 @@ impl<PM, P, R> PageCache<PM, P, R>
                   appears not to have completed"
             );
             M.advance_snapshot.measure(clock() - start);
-            return;
+            return Ok(());
         }
         let mut snapshot_opt = snapshot_opt_res.unwrap();
         let mut snapshot =
@@ -714,11 +1144,9 @@ impl<PM, P, R> PageCache<PM, P, R>
                 // we've gone past the known-stable offset.
                 break;
             }
-            let segment_lsn = lsn / io_buf_size as Lsn * io_buf_size as Lsn;
 
             trace!(
-                "in advance_snapshot looking at item: segment lsn {} lsn {} lid {}",
-                segment_lsn,
+                "in advance_snapshot looking at item: lsn {} lid {}",
                 lsn,
                 log_id
             );
@@ -737,17 +1165,6 @@ impl<PM, P, R> PageCache<PM, P, R>
             assert!(lsn > max_lsn);
             max_lsn = lsn;
 
-            let idx = log_id as usize / io_buf_size;
-            if snapshot.segments.len() < idx + 1 {
-                snapshot.segments.resize(idx + 1, log::Segment::default());
-            }
-
-            assert_eq!(
-                segment_lsn / io_buf_size as Lsn * io_buf_size as Lsn,
-                segment_lsn,
-                "segment lsn is unaligned! fix above lsn statement..."
-            );
-
             // unwrapping this because it's already passed the crc check
             // in the log iterator
             trace!("trying to deserialize buf for lid {} lsn {}", log_id, lsn);
@@ -766,156 +1183,310 @@ impl<PM, P, R> PageCache<PM, P, R>
 
             let prepend = deserialization.unwrap();
 
-            if prepend.pid >= snapshot.max_pid {
-                snapshot.max_pid = prepend.pid + 1;
+            self.apply_logged_update(
+                &mut snapshot,
+                &mut last_segment,
+                &mut recovery,
+                lsn,
+                log_id,
+                prepend,
+            );
+        }
+
+        if in_recovery {
+            // these batches were never closed before we crashed
+            let open: Vec<Lsn> = snapshot.batches.keys().cloned().collect();
+            for batch_lsn in open {
+                self.abandon_batch(&mut snapshot, last_segment, batch_lsn);
             }
+        }
+
+        snapshot.free.sort();
+        snapshot.free.reverse();
+        snapshot.max_lsn = max_lsn;
+        snapshot.recovery = recovery;
+
+        // a read-only snapshot lives only in memory
+        let written = if self.config.get_read_only() {
+            Ok(())
+        } else {
+            self.write_snapshot(&snapshot)
+        };
+
+        trace!("generated new snapshot: {:?}", snapshot);
 
-            snapshot.segments[idx].recovery_ensure_initialized(segment_lsn);
+        self.log.with_sa(|sa| sa.resume_rewriting());
+
+        // NB replacing the snapshot must come after the resume_rewriting call
+        // otherwise we create a race condition where we corrupt an in-progress
+        // snapshot generating iterator. the snapshot is kept even if it
+        // could not be written, since it still reflects the log.
+        *snapshot_opt = Some(snapshot);
+
+        M.advance_snapshot.measure(clock() - start);
+
+        written
+    }
+
+    fn recover_segment(
+        &self,
+        snapshot: &mut Snapshot<R>,
+        last_segment: &mut Option<usize>,
+        lsn: Lsn,
+        log_id: LogID,
+    ) -> (usize, Lsn) {
+        let io_buf_size = self.config.get_io_buf_size();
+        let segment_lsn = lsn / io_buf_size as Lsn * io_buf_size as Lsn;
+
+        let idx = log_id as usize / io_buf_size;
+        if snapshot.segments.len() < idx + 1 {
+            snapshot.segments.resize(idx + 1, log::Segment::default());
+        }
+
+        assert_eq!(
+            segment_lsn / io_buf_size as Lsn * io_buf_size as Lsn,
+            segment_lsn,
+            "segment lsn is unaligned! fix above lsn statement..."
+        );
+
+        snapshot.segments[idx].recovery_ensure_initialized(segment_lsn);
 
-            let last_idx = *last_segment.get_or_insert(idx);
-            if last_idx != idx {
-                // if we have moved to a new segment, mark the previous one
-                // as inactive.
+        let last_idx = *last_segment.get_or_insert(idx);
+        if last_idx != idx {
+            // if we have moved to a new segment, mark the previous one
+            // as inactive.
+            trace!(
+                "PageCache recovery setting segment {} to inactive",
+                log_id
+            );
+            snapshot.segments[last_idx].active_to_inactive(segment_lsn, true);
+            if snapshot.segments[last_idx].is_empty() {
                 trace!(
-                    "PageCache recovery setting segment {} to inactive",
+                    "PageCache recovery setting segment {} to draining",
                     log_id
                 );
-                snapshot.segments[last_idx].active_to_inactive(
-                    segment_lsn,
-                    true,
-                );
-                if snapshot.segments[last_idx].is_empty() {
-                    trace!(
-                        "PageCache recovery setting segment {} to draining",
-                        log_id
-                    );
-                    snapshot.segments[last_idx].inactive_to_draining(
-                        segment_lsn,
-                    );
-                }
+                snapshot.segments[last_idx].inactive_to_draining(segment_lsn);
             }
-            last_segment = Some(idx);
-
-            match prepend.update {
-                Update::Append(partial_page) => {
-                    // Because we rewrite pages over time, we may have relocated
-                    // a page's initial Compact to a later segment. We should skip
-                    // over pages here unless we've encountered a Compact or Alloc
-                    // for them.
-                    if let Some(lids) = snapshot.pt.get_mut(&prepend.pid) {
-                        trace!(
-                            "append of pid {} at lid {} lsn {}",
-                            prepend.pid,
-                            log_id,
-                            lsn
-                        );
-
-                        snapshot.segments[idx].insert_pid(
-                            prepend.pid,
-                            segment_lsn,
-                        );
-
-                        let r = self.t.recover(&partial_page);
-                        if r.is_some() {
-                            recovery = r;
-                        }
+        }
+        *last_segment = Some(idx);
 
-                        lids.push((lsn, log_id));
-                    }
-                }
-                Update::Compact(partial_page) => {
+        (idx, segment_lsn)
+    }
+
+    fn apply_logged_update(
+        &self,
+        snapshot: &mut Snapshot<R>,
+        last_segment: &mut Option<usize>,
+        recovery: &mut Option<R>,
+        lsn: Lsn,
+        log_id: LogID,
+        prepend: LoggedUpdate<P>,
+    ) {
+        let io_buf_size = self.config.get_io_buf_size();
+        let (idx, segment_lsn) =
+            self.recover_segment(snapshot, last_segment, lsn, log_id);
+
+        if !prepend.update.is_batch_marker() &&
+            prepend.pid >= snapshot.max_pid
+        {
+            snapshot.max_pid = prepend.pid + 1;
+        }
+
+        match prepend.update {
+            Update::Append(partial_page) => {
+                // Because we rewrite pages over time, we may have relocated
+                // a page's initial Compact to a later segment. We should skip
+                // over pages here unless we've encountered a Compact or Alloc
+                // for them.
+                if let Some(lids) = snapshot.pt.get_mut(&prepend.pid) {
                     trace!(
-                        "compact of pid {} at lid {} lsn {}",
+                        "append of pid {} at lid {} lsn {}",
                         prepend.pid,
                         log_id,
                         lsn
                     );
-                    if let Some(lids) = snapshot.pt.remove(&prepend.pid) {
-                        for (_lsn, old_lid) in lids {
-                            let old_idx = old_lid as usize / io_buf_size;
-                            if old_idx == idx {
-                                // don't remove pid if it's still there
-                                continue;
-                            }
-                            let old_segment = &mut snapshot.segments[old_idx];
-
-                            old_segment.remove_pid(prepend.pid, segment_lsn);
-                        }
-                    }
 
                     snapshot.segments[idx].insert_pid(prepend.pid, segment_lsn);
 
                     let r = self.t.recover(&partial_page);
                     if r.is_some() {
-                        recovery = r;
+                        *recovery = r;
                     }
 
-                    snapshot.pt.insert(prepend.pid, vec![(lsn, log_id)]);
+                    lids.push((lsn, log_id));
                 }
-                Update::Free => {
-                    trace!(
-                        "del of pid {} at lid {} lsn {}",
-                        prepend.pid,
-                        log_id,
-                        lsn
-                    );
-                    if let Some(lids) = snapshot.pt.remove(&prepend.pid) {
-                        // this could fail if our Alloc was nuked
-                        for (_lsn, old_lid) in lids {
-                            let old_idx = old_lid as usize / io_buf_size;
-                            if old_idx == idx {
-                                // don't remove pid if it's still there
-                                continue;
-                            }
-                            let old_segment = &mut snapshot.segments[old_idx];
-                            old_segment.remove_pid(prepend.pid, segment_lsn);
+            }
+            Update::Compact(partial_page) => {
+                trace!(
+                    "compact of pid {} at lid {} lsn {}",
+                    prepend.pid,
+                    log_id,
+                    lsn
+                );
+                if let Some(lids) = snapshot.pt.remove(&prepend.pid) {
+                    for (_lsn, old_lid) in lids {
+                        let old_idx = old_lid as usize / io_buf_size;
+                        if old_idx == idx {
+                            // don't remove pid if it's still there
+                            continue;
                         }
+                        let old_segment = &mut snapshot.segments[old_idx];
+
+                        old_segment.remove_pid(prepend.pid, segment_lsn);
                     }
+                }
 
-                    snapshot.segments[idx].insert_pid(prepend.pid, segment_lsn);
+                snapshot.segments[idx].insert_pid(prepend.pid, segment_lsn);
 
-                    snapshot.free.push(prepend.pid);
+                let r = self.t.recover(&partial_page);
+                if r.is_some() {
+                    *recovery = r;
                 }
-                Update::Alloc => {
-                    trace!(
-                        "alloc of pid {} at lid {} lsn {}",
-                        prepend.pid,
-                        log_id,
-                        lsn
-                    );
 
-                    snapshot.pt.insert(prepend.pid, vec![]);
-                    snapshot.free.retain(|&pid| pid != prepend.pid);
-                    snapshot.segments[idx].insert_pid(prepend.pid, segment_lsn);
-                }
+                snapshot.pt.insert(prepend.pid, vec![(lsn, log_id)]);
             }
-        }
-
-        snapshot.free.sort();
-        snapshot.free.reverse();
-        snapshot.max_lsn = max_lsn;
-        snapshot.recovery = recovery;
-
-        self.write_snapshot(&snapshot);
+            Update::Free => {
+                trace!(
+                    "del of pid {} at lid {} lsn {}",
+                    prepend.pid,
+                    log_id,
+                    lsn
+                );
+                if let Some(lids) = snapshot.pt.remove(&prepend.pid) {
+                    // this could fail if our Alloc was nuked
+                    for (_lsn, old_lid) in lids {
+                        let old_idx = old_lid as usize / io_buf_size;
+                        if old_idx == idx {
+                            // don't remove pid if it's still there
+                            continue;
+                        }
+                        let old_segment = &mut snapshot.segments[old_idx];
+                        old_segment.remove_pid(prepend.pid, segment_lsn);
+                    }
+                }
 
-        trace!("generated new snapshot: {:?}", snapshot);
+                snapshot.segments[idx].insert_pid(prepend.pid, segment_lsn);
 
-        self.log.with_sa(|sa| sa.resume_rewriting());
+                snapshot.free.push(prepend.pid);
+            }
+            Update::Alloc => {
+                trace!(
+                    "alloc of pid {} at lid {} lsn {}",
+                    prepend.pid,
+                    log_id,
+                    lsn
+                );
 
-        // NB replacing the snapshot must come after the resume_rewriting call
-        // otherwise we create a race condition where we corrupt an in-progress
-        // snapshot generating iterator.
-        *snapshot_opt = Some(snapshot);
+                snapshot.pt.insert(prepend.pid, vec![]);
+                snapshot.free.retain(|&pid| pid != prepend.pid);
+                snapshot.segments[idx].insert_pid(prepend.pid, segment_lsn);
+            }
+            Update::BatchAppend(batch_lsn, _) => {
+                trace!(
+                    "batch {} append of pid {} at lid {} lsn {}",
+                    batch_lsn,
+                    prepend.pid,
+                    log_id,
+                    lsn
+                );
+                // the segment must learn about the page while it is
+                // still active, so it is told now, and forgets it
+                // again if the batch is abandoned.
+                if snapshot.pt.contains_key(&prepend.pid) {
+                    snapshot.segments[idx].insert_pid(prepend.pid, segment_lsn);
+                }
+                snapshot
+                    .batches
+                    .entry(batch_lsn)
+                    .or_insert_with(Vec::new)
+                    .push((prepend.pid, lsn, log_id));
+            }
+            Update::BatchStart => {
+                trace!("batch start at lid {} lsn {}", log_id, lsn);
+                snapshot.batches.insert(lsn, vec![]);
+            }
+            Update::BatchCommit(batch_lsn) => {
+                trace!(
+                    "batch {} commit at lid {} lsn {}",
+                    batch_lsn,
+                    log_id,
+                    lsn
+                );
+                let appends =
+                    snapshot.batches.remove(&batch_lsn).unwrap_or_default();
+                for (pid, lsn, log_id) in appends {
+                    // appends from other threads may have been logged
+                    // since, so the frag goes in its place by lsn. a
+                    // page compacted since then already includes it.
+                    if let Some(lids) = snapshot.pt.get_mut(&pid) {
+                        let base_lsn = match lids.first() {
+                            Some(&(base_lsn, _)) => base_lsn,
+                            None => continue,
+                        };
+                        if base_lsn < lsn {
+                            let at = lids.iter()
+                                .position(|&(other, _)| other > lsn)
+                                .unwrap_or(lids.len());
+                            lids.insert(at, (lsn, log_id));
+                        }
+                    }
+                }
+            }
+            Update::BatchAbort(batch_lsn) => {
+                trace!(
+                    "batch {} abort at lid {} lsn {}",
+                    batch_lsn,
+                    log_id,
+                    lsn
+                );
+                self.abandon_batch(snapshot, Some(idx), batch_lsn);
+            }
+        }
+    }
 
-        M.advance_snapshot.measure(clock() - start);
+    // drops the updates of a batch that will never commit. the
+    // segments they were written to forget their pages, unless the
+    // page has other updates there, or the segment is still active
+    // and could take more of them, or has been reused.
+    fn abandon_batch(
+        &self,
+        snapshot: &mut Snapshot<R>,
+        active_segment: Option<usize>,
+        batch_lsn: Lsn,
+    ) {
+        let io_buf_size = self.config.get_io_buf_size();
+        let appends = snapshot.batches.remove(&batch_lsn).unwrap_or_default();
+        for (pid, lsn, log_id) in appends {
+            trace!("abandoning batch {} update at lsn {}", batch_lsn, lsn);
+            let idx = log_id as usize / io_buf_size;
+            if Some(idx) == active_segment {
+                continue;
+            }
+            let same_segment = |lid: LogID| lid as usize / io_buf_size == idx;
+            let recovered = snapshot
+                .pt
+                .get(&pid)
+                .map(|lids| lids.iter().any(|&(_, lid)| same_segment(lid)))
+                .unwrap_or(false);
+            let pending = snapshot.batches.values().any(|appends| {
+                appends.iter().any(|&(other_pid, _, lid)| {
+                    other_pid == pid && same_segment(lid)
+                })
+            });
+            let segment_lsn = lsn / io_buf_size as Lsn * io_buf_size as Lsn;
+            let segment = &mut snapshot.segments[idx];
+            if !recovered && !pending && segment.holds(segment_lsn) {
+                segment.remove_pid(pid, lsn);
+            }
+        }
     }
 
-    fn write_snapshot(&self, snapshot: &Snapshot<R>) {
+    fn write_snapshot(&self, snapshot: &Snapshot<R>) -> Result<()> {
         let raw_bytes = serialize(&snapshot, Infinite).unwrap();
 
         #[cfg(feature = "zstd")]
         let bytes = if self.config.get_use_compression() {
-            compress(&*raw_bytes, 5).unwrap()
+            compress(&*raw_bytes, 5)?
         } else {
             raw_bytes
         };
@@ -932,23 +1503,22 @@ impl<PM, P, R> PageCache<PM, P, R>
         let mut f = std::fs::OpenOptions::new()
             .write(true)
             .create(true)
-            .open(&path_1)
-            .unwrap();
+            .open(&path_1)?;
 
         // write the snapshot bytes, followed by a crc64 checksum at the end
-        f.write_all(&*bytes).unwrap();
-        f.write_all(&crc64).unwrap();
-        f.sync_all().unwrap();
+        f.write_all(&*bytes)?;
+        f.write_all(&crc64)?;
+        f.sync_all()?;
         drop(f);
 
         trace!("wrote snapshot to {}", path_1);
 
-        std::fs::rename(path_1, &path_2).expect("failed to write snapshot");
+        std::fs::rename(path_1, &path_2)?;
 
         trace!("renamed snapshot to {}", path_2);
 
         // clean up any old snapshots
-        let candidates = self.config.get_snapshot_files();
+        let candidates = self.config.get_snapshot_files()?;
         for path in candidates {
             let path_str =
                 Path::new(&path).file_name().unwrap().to_str().unwrap();
@@ -963,43 +1533,56 @@ impl<PM, P, R> PageCache<PM, P, R>
                 }
             }
         }
+
+        Ok(())
     }
 
-    fn read_snapshot(&self) {
-        let mut candidates = self.config.get_snapshot_files();
-        if candidates.is_empty() {
-            info!("no previous snapshot found");
-            return;
+    fn read_snapshot(&self) -> Result<()> {
+        let mut candidates = vec![];
+        for path in self.config.get_snapshot_files()? {
+            let created = std::fs::metadata(&path)?.created()?;
+            candidates.push((created, path));
         }
+        candidates.sort();
 
-        candidates.sort_by_key(
-            |path| std::fs::metadata(path).unwrap().created().unwrap(),
-        );
+        let path = match candidates.pop() {
+            Some((_created, path)) => path,
+            None => {
+                info!("no previous snapshot found");
+                return Ok(());
+            }
+        };
 
-        let path = candidates.pop().unwrap();
+        let invalid = |why: &str| {
+            let msg = format!("snapshot file {:?} {}", path, why);
+            Error::Io(std::io::Error::new(std::io::ErrorKind::InvalidData, msg))
+        };
 
-        let mut f = std::fs::OpenOptions::new().read(true).open(&path).unwrap();
+        let mut f = std::fs::OpenOptions::new().read(true).open(&path)?;
 
         let mut buf = vec![];
-        f.read_to_end(&mut buf).unwrap();
+        f.read_to_end(&mut buf)?;
         let len = buf.len();
+        if len < 8 {
+            return Err(invalid("is too short to hold a crc"));
+        }
         buf.split_off(len - 8);
 
         let mut crc_expected_bytes = [0u8; 8];
-        f.seek(std::io::SeekFrom::End(-8)).unwrap();
-        f.read_exact(&mut crc_expected_bytes).unwrap();
+        f.seek(std::io::SeekFrom::End(-8))?;
+        f.read_exact(&mut crc_expected_bytes)?;
 
         let crc_expected: u64 =
             unsafe { std::mem::transmute(crc_expected_bytes) };
         let crc_actual = crc64(&*buf);
 
         if crc_expected != crc_actual {
-            panic!("crc for snapshot file {:?} failed!", path);
+            return Err(invalid("failed its crc check"));
         }
 
         #[cfg(feature = "zstd")]
         let bytes = if self.config.get_use_compression() {
-            decompress(&*buf, self.config.get_io_buf_size()).unwrap()
+            decompress(&*buf, self.config.get_io_buf_size())?
         } else {
             buf
         };
@@ -1007,10 +1590,13 @@ impl<PM, P, R> PageCache<PM, P, R>
         #[cfg(not(feature = "zstd"))]
         let bytes = buf;
 
-        let snapshot = deserialize::<Snapshot<R>>(&*bytes).unwrap();
+        let snapshot = deserialize::<Snapshot<R>>(&*bytes)
+            .map_err(|_| invalid("could not be deserialized"))?;
 
         let mut mu = self.last_snapshot.lock().unwrap();
         *mu = Some(snapshot);
+
+        Ok(())
     }
 
     fn load_snapshot(&mut self) {
@@ -8,6 +8,9 @@ pub struct Snapshot<R> {
     pub segments: Vec<log::Segment>,
     pub free: Vec<PageID>,
     pub recovery: Option<R>,
+    /// The updates of batches that have neither committed nor aborted
+    /// yet, keyed by the `Lsn` each batch began at.
+    pub batches: BTreeMap<Lsn, Vec<(PageID, Lsn, LogID)>>,
 }
 
 impl<R> Default for Snapshot<R> {
@@ -19,6 +22,7 @@ impl<R> Default for Snapshot<R> {
             segments: vec![],
             free: vec![],
             recovery: None,
+            batches: BTreeMap::new(),
         }
     }
 }
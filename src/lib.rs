@@ -1,14 +1,16 @@
 //! `sled` is a flash-sympathetic persistent lock-free B+ tree, pagecache, and log.
 //!
 //! ```
-//! let t = sled::Config::default().tree();
-//! t.set(b"yo!".to_vec(), b"v1".to_vec());
-//! assert_eq!(t.get(b"yo!"), Some(b"v1".to_vec()));
-//! t.cas(b"yo!".to_vec(), Some(b"v1".to_vec()), Some(b"v2".to_vec())).unwrap();
+//! let t = sled::Config::default().tree().unwrap();
+//! t.set(b"yo!".to_vec(), b"v1".to_vec()).unwrap();
+//! assert_eq!(t.get(b"yo!"), Ok(Some(b"v1".to_vec())));
+//! t.cas(b"yo!".to_vec(), Some(b"v1".to_vec()), Some(b"v2".to_vec()))
+//!     .unwrap()
+//!     .unwrap();
 //! let mut iter = t.scan(b"a non-present key before yo!");
-//! assert_eq!(iter.next(), Some((b"yo!".to_vec(), b"v2".to_vec())));
+//! assert_eq!(iter.next(), Some(Ok((b"yo!".to_vec(), b"v2".to_vec()))));
 //! assert_eq!(iter.next(), None);
-//! t.del(b"yo!");
+//! t.del(b"yo!").unwrap();
 //! ```
 
 #![deny(missing_docs)]
@@ -39,12 +41,15 @@ extern crate cpuprofiler;
 extern crate rand;
 
 /// atomic lock-free tree
-pub use tree::{Iter, Tree};
+pub use tree::{Batch, BulkLoadError, Db, Event, Iter, OrderedKey, Snapshot,
+               SnapshotIter, Subscriber, Tree, Tx, TypedIter, TypedTree};
 /// lock-free pagecache
 #[doc(hidden)]
 pub use ds::{Radix, Stack};
 /// general-purpose configuration
 pub use config::Config;
+/// errors returned by fallible operations
+pub use error::{Error, Result};
 pub use io::*;
 
 macro_rules! rep_no_copy {
@@ -76,6 +81,7 @@ fn test_fail() -> bool {
 mod io;
 mod tree;
 mod config;
+mod error;
 mod hash;
 mod ds;
 mod metrics;
@@ -93,8 +99,23 @@ type Key = Vec<u8>;
 type KeyRef<'a> = &'a [u8];
 type Value = Vec<u8>;
 
+/// Combines the existing value of a key, if any, with an operand passed
+/// to `Tree::merge`, returning the new value, or `None` to delete the key.
+pub type MergeOperator = fn(key: &[u8],
+                            existing: Option<&[u8]>,
+                            operand: &[u8])
+                            -> Option<Vec<u8>>;
+
+/// Orders the keys of a `Tree` configured with `Config::key_comparator`,
+/// in place of lexicographic byte order.
+pub type KeyComparator = fn(a: &[u8], b: &[u8]) -> std::cmp::Ordering;
+
 type HPtr<'s, P> = coco::epoch::Ptr<'s, ds::stack::Node<io::CacheEntry<P>>>;
 
+// the outcome of an atomic update of a page that was logged: the new
+// key, or the actual key if the page changed, or `None` if it's gone.
+type CasResult<'s, P> = std::result::Result<HPtr<'s, P>, Option<HPtr<'s, P>>>;
+
 lazy_static! {
     /// A metric collector for all sled instances running in this
     /// process.
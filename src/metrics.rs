@@ -11,7 +11,9 @@ pub struct Metrics {
     pub tree_get: Histo,
     pub tree_del: Histo,
     pub tree_cas: Histo,
+    pub tree_merge: Histo,
     pub tree_scan: Histo,
+    pub tree_tx: Histo,
     pub page_in: Histo,
     pub merge_page: Histo,
     pub page_out: Histo,
@@ -29,6 +31,10 @@ pub struct Metrics {
     pub log_loops: AtomicUsize,
     pub accountant_lock: Histo,
     pub accountant_hold: Histo,
+    // only counted with the key_prefix_metrics feature, since every
+    // serialized node would otherwise update them.
+    pub node_key_bytes: AtomicUsize,
+    pub node_key_bytes_saved: AtomicUsize,
 }
 
 impl Metrics {
@@ -92,7 +98,9 @@ impl Metrics {
             f("set", &self.tree_set),
             f("del", &self.tree_del),
             f("cas", &self.tree_cas),
+            f("merge", &self.tree_merge),
             f("scan", &self.tree_scan),
+            f("tx", &self.tree_tx),
         ]);
         println!("tree contention loops: {}", self.tree_loops.load(Acquire));
 
@@ -114,6 +122,11 @@ impl Metrics {
             f("compress", &self.compress),
             f("decompress", &self.decompress),
         ]);
+        println!(
+            "node key bytes: {}, saved by prefix compression: {}",
+            self.node_key_bytes.load(Acquire),
+            self.node_key_bytes_saved.load(Acquire)
+        );
 
         println!("{}", repeat("-").take(103).collect::<String>());
         println!("log:");
@@ -0,0 +1,97 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use super::*;
+
+/// A batch of `set` and `del` operations that will be applied to a
+/// `Tree` atomically with `Tree::apply_batch`. If the same key is
+/// written several times, only the last write is kept.
+///
+/// # Examples
+///
+/// ```
+/// use sled::{Batch, Config};
+/// let t = Config::default().tree().unwrap();
+/// t.set(vec![1], vec![1]).unwrap();
+///
+/// let mut batch = Batch::default();
+/// batch.set(vec![2], vec![2]);
+/// batch.set(vec![3], vec![3]);
+/// batch.del(vec![1]);
+///
+/// t.apply_batch(batch).unwrap();
+/// assert_eq!(t.get(&[1]), Ok(None));
+/// assert_eq!(t.get(&[2]), Ok(Some(vec![2])));
+/// assert_eq!(t.get(&[3]), Ok(Some(vec![3])));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Batch {
+    pub(super) writes: BTreeMap<Key, Option<Value>>,
+}
+
+impl Batch {
+    /// Set a key to a new value when the batch is applied.
+    pub fn set(&mut self, key: Key, value: Value) {
+        self.writes.insert(key, Some(value));
+    }
+
+    /// Delete a key when the batch is applied.
+    pub fn del(&mut self, key: Key) {
+        self.writes.insert(key, None);
+    }
+
+    /// Returns the number of keys written by this batch.
+    pub fn len(&self) -> usize {
+        self.writes.len()
+    }
+
+    /// Returns `true` if this batch contains no writes.
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+}
+
+// a batch whose writes are being linked to a tree. if it is dropped
+// before it commits, because one of its writes failed or panicked, its
+// writes are undone and it is aborted, rather than left open. the
+// leaves it has claimed are released once it is dropped either way.
+pub(super) struct OpenBatch<'a> {
+    pub(super) tree: &'a Tree,
+    pub(super) lsn: Lsn,
+    pub(super) writes: RefCell<Vec<BatchWrite>>,
+    pub(super) committed: bool,
+}
+
+// a write linked as part of a batch, and the record it replaced. the
+// replaced value is only freed once the batch commits, since it is
+// what gets recovered if the batch never does.
+pub(super) struct BatchWrite {
+    pub(super) key: Key,
+    pub(super) old: Option<(StoredValue, Option<u64>)>,
+    pub(super) new: Option<StoredValue>,
+}
+
+impl<'a> OpenBatch<'a> {
+    pub(super) fn begin(tree: &'a Tree) -> Result<OpenBatch<'a>> {
+        let lsn = tree.pages.begin_batch()?;
+        tree.committing.lock().unwrap().insert(lsn);
+        Ok(OpenBatch {
+            tree: tree,
+            lsn: lsn,
+            writes: RefCell::new(vec![]),
+            committed: false,
+        })
+    }
+}
+
+impl<'a> Drop for OpenBatch<'a> {
+    fn drop(&mut self) {
+        if !self.committed {
+            let writes = self.writes.replace(vec![]);
+            if let Err(e) = self.tree.abort_batch(self.lsn, writes) {
+                error!("failed to abort batch {}: {}", self.lsn, e);
+            }
+        }
+        self.tree.committing.lock().unwrap().remove(&self.lsn);
+    }
+}
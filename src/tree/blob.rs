@@ -0,0 +1,156 @@
+use super::*;
+
+use coco::epoch::Scope;
+
+/// A value as it is kept in a leaf. Values longer than
+/// `Config::blob_threshold` are split into chunks that each become the
+/// only frag of their own page, so consolidating the leaf does not
+/// rewrite them, and the `SegmentAccountant` keeps the segments they
+/// live in from being reused until their pages are freed.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum StoredValue {
+    Inline(Value),
+    /// The pages holding the chunks of the value, in order.
+    Blob(Vec<PageID>),
+}
+
+/// Stores a value inline if it is short enough, and writes it out as
+/// a blob otherwise. A blob that is never linked into a leaf must be
+/// passed to `free`. If writing a chunk fails, those already written
+/// are freed.
+pub(super) fn store<'s>(
+    pages: &PageCache<BLinkMaterializer, Frag, Meta>,
+    config: &Config,
+    value: Value,
+    scope: &'s Scope,
+) -> Result<StoredValue> {
+    if value.len() <= config.get_blob_threshold() {
+        return Ok(StoredValue::Inline(value));
+    }
+
+    // recovery only looks for leaked blobs if the Meta says that some
+    // were written, so record that before writing the first one.
+    if !meta::meta(pages, scope)?.0.stored_blobs() {
+        meta::update(pages, |meta| meta.set_stored_blobs(), scope)?;
+    }
+
+    // leave room for the headers of the log record and its segment,
+    // so that two chunks fit in each segment.
+    let chunk_len = config.get_io_buf_size() / 2 - 64;
+    let mut pids = vec![];
+    for chunk in value.chunks(chunk_len) {
+        let written = pages.allocate(scope).and_then(|(pid, cas_key)| {
+            pids.push(pid);
+            let frag = Frag::Blob(chunk.to_vec());
+            pages.replace(pid, cas_key, frag, scope).map(|cas| {
+                cas.unwrap();
+            })
+        });
+        if let Err(e) = written {
+            let _ = free(pages, &StoredValue::Blob(pids));
+            return Err(e);
+        }
+    }
+    Ok(StoredValue::Blob(pids))
+}
+
+/// Reads the bytes of a stored value. Returns `None` if a blob was
+/// freed because its key was written to after the leaf was read, in
+/// which case the leaf should be read again.
+pub(super) fn load<'s>(
+    pages: &PageCache<BLinkMaterializer, Frag, Meta>,
+    stored: &StoredValue,
+    scope: &'s Scope,
+) -> Result<Option<Value>> {
+    let pids = match *stored {
+        StoredValue::Inline(ref value) => return Ok(Some(value.clone())),
+        StoredValue::Blob(ref pids) => pids,
+    };
+
+    let mut value = vec![];
+    for &pid in pids {
+        match pages.get(pid, scope)? {
+            Some((Frag::Blob(chunk), _cas_key)) => {
+                value.extend_from_slice(&*chunk)
+            }
+            _ => return Ok(None),
+        }
+    }
+    Ok(Some(value))
+}
+
+/// Frees the pages of a value once no leaf points to it. Blobs are
+/// written before the leaf that points to them, so a crash in between
+/// leaves their pages allocated until `reclaim` runs during recovery.
+pub(super) fn free(
+    pages: &PageCache<BLinkMaterializer, Frag, Meta>,
+    stored: &StoredValue,
+) -> Result<()> {
+    if let StoredValue::Blob(ref pids) = *stored {
+        for &pid in pids {
+            pages.free(pid)?;
+        }
+    }
+    Ok(())
+}
+
+/// Frees the blob pages that no leaf points to, which a crash between
+/// writing a blob and installing the leaf that points to it leaves
+/// behind. Nodes that were merged away but not yet freed still count
+/// as pointing to their blobs, so this may leave a few allocated.
+pub(super) fn reclaim<'s>(
+    pages: &PageCache<BLinkMaterializer, Frag, Meta>,
+    scope: &'s Scope,
+) -> Result<()> {
+    if !meta::meta(pages, scope)?.0.stored_blobs() {
+        return Ok(());
+    }
+
+    let mut referenced = HashSet::new();
+    let mut blobs = vec![];
+    for pid in pages.pids() {
+        match pages.get(pid, scope)? {
+            Some((Frag::Base(node, _root), _cas_key)) => {
+                if let Data::Leaf(ref items) = node.data {
+                    for &(_, ref stored, _) in items {
+                        if let StoredValue::Blob(ref pids) = *stored {
+                            referenced.extend(pids.iter().cloned());
+                        }
+                    }
+                }
+            }
+            Some((Frag::Blob(_), _cas_key)) => blobs.push(pid),
+            _ => {}
+        }
+    }
+
+    for pid in blobs {
+        if !referenced.contains(&pid) {
+            debug!("freeing blob page {} that no leaf points to", pid);
+            pages.free(pid)?;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_reclaim_leaked_blob() {
+    use coco::epoch::pin;
+
+    let conf = Config::default()
+        .blob_threshold(100)
+        .io_buf_size(10_000)
+        .flush_every_ms(None);
+    let t = conf.tree().unwrap();
+    t.set(b"kept".to_vec(), vec![1; 1000]).unwrap();
+    let page_count = t.page_count();
+
+    // crash after writing a blob, but before a leaf points to it
+    pin(|scope| store(&t.pages, &conf, vec![2; 20_000], scope)).unwrap();
+    assert!(t.page_count() > page_count);
+    drop(t);
+
+    let t = conf.tree().unwrap();
+    assert_eq!(t.page_count(), page_count);
+    assert_eq!(t.get(b"kept"), Ok(Some(vec![1; 1000])));
+}
@@ -1,5 +1,7 @@
 use std::cmp::Ordering;
 
+use super::*;
+
 #[derive(Clone, Debug, Ord, Eq, PartialEq, Serialize, Deserialize)]
 pub enum Bound {
     Inc(Vec<u8>),
@@ -19,42 +21,83 @@ impl Bound {
 
 impl PartialOrd for Bound {
     fn partial_cmp(&self, other: &Bound) -> Option<Ordering> {
-        use self::Bound::*;
-        match *self {
-            Inc(ref lhs) => {
-                match *other {
-                    Inf => Some(Ordering::Less),
-                    Inc(ref rhs) => Some(lhs.cmp(rhs)),
-                    Non(ref rhs) => {
-                        if lhs < rhs {
-                            Some(Ordering::Less)
-                        } else {
-                            Some(Ordering::Greater)
-                        }
-                    }
-                }
+        Some(KeyOrder::default().cmp_bounds(self, other))
+    }
+}
+
+/// The order of the keys in a `Tree`, which is lexicographic unless
+/// a `KeyComparator` was configured. The empty key is the low bound
+/// of the leftmost leaf, so it sorts first under any comparator.
+#[derive(Clone, Copy, Default)]
+pub struct KeyOrder {
+    cmp: Option<KeyComparator>,
+}
+
+impl KeyOrder {
+    pub fn new(cmp: Option<KeyComparator>) -> KeyOrder {
+        KeyOrder {
+            cmp: cmp,
+        }
+    }
+
+    /// Whether keys are in lexicographic byte order.
+    pub fn is_lexicographic(&self) -> bool {
+        self.cmp.is_none()
+    }
+
+    pub fn cmp_keys(&self, lhs: &[u8], rhs: &[u8]) -> Ordering {
+        match self.cmp {
+            Some(_) if lhs.is_empty() || rhs.is_empty() => {
+                rhs.is_empty().cmp(&lhs.is_empty())
             }
-            Non(ref lhs) => {
-                match *other {
-                    Inf => Some(Ordering::Less),
-                    Inc(ref rhs) => {
-                        if lhs <= rhs {
-                            Some(Ordering::Less)
-                        } else {
-                            Some(Ordering::Greater)
-                        }
-                    }
-                    Non(ref rhs) => Some(lhs.cmp(rhs)),
+            Some(cmp) => cmp(lhs, rhs),
+            None => lhs.cmp(rhs),
+        }
+    }
+
+    pub fn cmp_bounds(&self, lhs: &Bound, rhs: &Bound) -> Ordering {
+        use self::Bound::*;
+        match (lhs, rhs) {
+            (&Inf, &Inf) => Ordering::Equal,
+            (&Inf, _) => Ordering::Greater,
+            (_, &Inf) => Ordering::Less,
+            (&Inc(ref lhs), &Inc(ref rhs)) |
+            (&Non(ref lhs), &Non(ref rhs)) => self.cmp_keys(lhs, rhs),
+            (&Inc(ref lhs), &Non(ref rhs)) => {
+                if self.cmp_keys(lhs, rhs) == Ordering::Less {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
                 }
             }
-            Inf => {
-                match *other {
-                    Inf => Some(Ordering::Equal),
-                    _ => Some(Ordering::Greater),
+            (&Non(ref lhs), &Inc(ref rhs)) => {
+                if self.cmp_keys(lhs, rhs) == Ordering::Greater {
+                    Ordering::Greater
+                } else {
+                    Ordering::Less
                 }
             }
         }
     }
+
+    /// The shortest key that sorts above `lhs` and at or below `rhs`,
+    /// for use as the separator between two nodes. Custom comparators
+    /// need not order keys by their prefixes, so they get `rhs` whole.
+    pub fn separator(&self, lhs: &[u8], rhs: &[u8]) -> Key {
+        if self.cmp.is_some() {
+            return rhs.to_vec();
+        }
+        let common = lhs.iter().zip(rhs).take_while(|&(a, b)| a == b).count();
+        rhs[..common + 1].to_vec()
+    }
+
+    pub fn lt(&self, lhs: &Bound, rhs: &Bound) -> bool {
+        self.cmp_bounds(lhs, rhs) == Ordering::Less
+    }
+
+    pub fn le(&self, lhs: &Bound, rhs: &Bound) -> bool {
+        self.cmp_bounds(lhs, rhs) != Ordering::Greater
+    }
 }
 
 #[test]
@@ -69,4 +112,24 @@ fn test_bounds() {
     assert!(Inc(vec![]) < Inf);
     assert!(Non(vec![]) < Inf);
     assert!(Inf > Inc(vec![0, 0, 0, 0, 0, 0, 136, 184]));
+
+    fn reversed(a: &[u8], b: &[u8]) -> Ordering {
+        b.cmp(a)
+    }
+    let order = KeyOrder::new(Some(reversed));
+    assert!(order.lt(&Inc(b"b".to_vec()), &Inc(b"a".to_vec())));
+    assert!(order.lt(&Non(b"a".to_vec()), &Inc(b"a".to_vec())));
+    assert!(order.lt(&Inc(vec![]), &Inc(b"b".to_vec())));
+    assert!(order.le(&Non(vec![]), &Inc(vec![])));
+    assert!(order.lt(&Inc(vec![0]), &Inf));
+    assert_eq!(order.separator(b"aaa", b"abc"), b"abc".to_vec());
+}
+
+#[test]
+fn test_separator() {
+    let order = KeyOrder::default();
+    assert_eq!(order.separator(b"users/alice", b"users/bob"), b"users/b");
+    assert_eq!(order.separator(b"", b"zzz"), b"z");
+    assert_eq!(order.separator(b"ab", b"abc"), b"abc");
+    assert_eq!(order.separator(b"abz", b"ac"), b"ac");
 }
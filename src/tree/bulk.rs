@@ -0,0 +1,185 @@
+use coco::epoch::{Ptr, pin};
+
+use super::*;
+
+/// The reasons `Tree::bulk_load` can refuse to load its input.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BulkLoadError {
+    /// The `Tree` already contained keys.
+    NotEmpty,
+    /// The input was not sorted in ascending order: this key was not
+    /// above the one before it.
+    Unsorted(Key),
+}
+
+/// Writes the pages of a new tree holding `items`, from the leaves
+/// up, and returns its root along with the number of items. Nothing
+/// points to the new pages until the caller records the root, and if
+/// the input turns out to be unsorted, or a page can't be written,
+/// they are freed again.
+pub(super) fn build<I>(
+    pages: &PageCache<BLinkMaterializer, Frag, Meta>,
+    config: &Config,
+    order: KeyOrder,
+    items: I,
+) -> Result<std::result::Result<(PageID, usize), BulkLoadError>>
+    where I: IntoIterator<Item = (Key, Value)>
+{
+    let mut builder = Builder {
+        pages: pages,
+        config: config,
+        order: order,
+        allocated: vec![],
+    };
+    let built = builder.leaves(items).and_then(|leaves| match leaves {
+        Ok((children, len)) => {
+            builder.index(children).map(|root| Ok((root, len)))
+        }
+        Err(e) => Ok(Err(e)),
+    });
+    if let Ok(Ok(_)) = built {
+        return built;
+    }
+    for &pid in &builder.allocated {
+        // the first error is the one worth reporting
+        let _ = pages.free(pid);
+    }
+    built
+}
+
+struct Builder<'a> {
+    pages: &'a PageCache<BLinkMaterializer, Frag, Meta>,
+    config: &'a Config,
+    order: KeyOrder,
+    // every page written so far, including those of blobs.
+    allocated: Vec<PageID>,
+}
+
+impl<'a> Builder<'a> {
+    // fills leaves up to the fanout, returning the lowest key and the
+    // pid of each. a leaf is written once the first key of the next
+    // one is known, since that determines its separator.
+    fn leaves<I>(
+        &mut self,
+        items: I,
+    ) -> Result<std::result::Result<(Vec<(Key, PageID)>, usize), BulkLoadError>>
+        where I: IntoIterator<Item = (Key, Value)>
+    {
+        let fanout = self.config.get_blink_fanout();
+        let mut children = vec![(vec![], self.allocate()?)];
+        let mut leaf: Vec<(Key, StoredValue, Option<u64>)> = vec![];
+        let mut len = 0;
+
+        for (key, value) in items {
+            if let Some(&(ref last, _, _)) = leaf.last() {
+                if self.order.cmp_keys(last, &*key) != Ordering::Less {
+                    return Ok(Err(BulkLoadError::Unsorted(key)));
+                }
+            }
+
+            if leaf.len() == fanout {
+                let last = &*leaf.last().unwrap().0;
+                let sep = self.order.separator(last, &*key);
+                let next = self.allocate()?;
+                let &(ref lo, pid) = children.last().unwrap();
+                let node = Node {
+                    id: pid,
+                    data: Data::Leaf(leaf),
+                    next: Some(next),
+                    lo: Bound::Inc(lo.clone()),
+                    hi: Bound::Non(sep.clone()),
+                    merging_child: None,
+                    merged: false,
+                    claim: None,
+                };
+                self.write(node, false)?;
+                children.push((sep, next));
+                leaf = vec![];
+            }
+
+            let stored = pin(|scope| {
+                blob::store(self.pages, self.config, value, scope)
+            })?;
+            if let StoredValue::Blob(ref pids) = stored {
+                self.allocated.extend_from_slice(pids);
+            }
+            leaf.push((key, stored, None));
+            len += 1;
+        }
+
+        let &(ref lo, pid) = children.last().unwrap();
+        let node = Node {
+            id: pid,
+            data: Data::Leaf(leaf),
+            next: None,
+            lo: Bound::Inc(lo.clone()),
+            hi: Bound::Inf,
+            merging_child: None,
+            merged: false,
+            claim: None,
+        };
+        self.write(node, false)?;
+        Ok(Ok((children, len)))
+    }
+
+    // writes levels of index nodes over the children until a single
+    // node remains, which becomes the root.
+    fn index(&mut self, mut children: Vec<(Key, PageID)>) -> Result<PageID> {
+        let fanout = self.config.get_blink_fanout();
+        loop {
+            let pids: Vec<PageID> = children
+                .chunks(fanout)
+                .map(|_| self.allocate())
+                .collect::<Result<_>>()?;
+            let is_root = pids.len() == 1;
+
+            let mut parents = vec![];
+            for (i, ptrs) in children.chunks(fanout).enumerate() {
+                let lo = ptrs[0].0.clone();
+                let hi = match children.get((i + 1) * fanout) {
+                    Some(&(ref k, _)) => Bound::Non(k.clone()),
+                    None => Bound::Inf,
+                };
+                let node = Node {
+                    id: pids[i],
+                    data: Data::Index(ptrs.to_vec()),
+                    next: pids.get(i + 1).cloned(),
+                    lo: Bound::Inc(lo.clone()),
+                    hi: hi,
+                    merging_child: None,
+                    merged: false,
+                    claim: None,
+                };
+                self.write(node, is_root)?;
+                parents.push((lo, pids[i]));
+            }
+
+            if is_root {
+                return Ok(pids[0]);
+            }
+            children = parents;
+        }
+    }
+
+    fn allocate(&mut self) -> Result<PageID> {
+        let pid =
+            pin(|scope| self.pages.allocate(scope).map(|(pid, _)| pid))?;
+        self.allocated.push(pid);
+        Ok(pid)
+    }
+
+    // a leaf's pid is needed by its left sibling, so it is allocated
+    // well before the leaf is written. the cas key of a page with no
+    // frags yet is null, so we don't need to hold on to the one that
+    // allocate returned.
+    fn write(&self, node: Node, is_root: bool) -> Result<()> {
+        let pid = node.id;
+        let frag = Frag::Base(node, is_root);
+        pin(|scope| {
+            self.pages
+                .replace(pid, Ptr::null(), frag, scope)?
+                .expect("failed to write a bulk loaded page");
+            Ok(())
+        })
+    }
+}
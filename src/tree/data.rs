@@ -1,9 +1,91 @@
+#[cfg(feature = "key_prefix_metrics")]
+use std::sync::atomic::Ordering::Relaxed;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
 use super::*;
 
-#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+/// The keys and children or values of a `Node`. When serialized, the
+/// prefix shared by all of its keys is only stored once. The first key
+/// of an index node is its low bound, which is empty on the left edge
+/// of the tree, so only leaves and the index nodes to the right of
+/// that edge benefit.
+#[derive(Clone, Debug, PartialEq)]
 pub enum Data {
     Index(Vec<(Key, PageID)>),
-    Leaf(Vec<(Key, Value)>),
+    // each record may carry the time it expires at, in milliseconds
+    // since the UNIX epoch.
+    Leaf(Vec<(Key, StoredValue, Option<u64>)>),
+}
+
+// the serialized forms of Data, which hold the common prefix of the
+// keys followed by the rest of each key.
+#[derive(Serialize)]
+enum PrefixedRef<'a> {
+    Index(&'a [u8], Vec<(&'a [u8], PageID)>),
+    Leaf(&'a [u8], Vec<(&'a [u8], &'a StoredValue, Option<u64>)>),
+}
+
+#[derive(Deserialize)]
+enum Prefixed {
+    Index(Key, Vec<(Key, PageID)>),
+    Leaf(Key, Vec<(Key, StoredValue, Option<u64>)>),
+}
+
+impl Serialize for Data {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+        where S: Serializer
+    {
+        let prefix = self.common_prefix();
+        let n = prefix.len();
+        let prefixed = match *self {
+            Data::Index(ref ptrs) => {
+                let ptrs = ptrs.iter().map(|&(ref k, pid)| (&k[n..], pid));
+                PrefixedRef::Index(prefix, ptrs.collect())
+            }
+            Data::Leaf(ref items) => {
+                let items = items.iter().map(|&(ref k, ref v, expiry)| {
+                    (&k[n..], v, expiry)
+                });
+                PrefixedRef::Leaf(prefix, items.collect())
+            }
+        };
+
+        #[cfg(feature = "key_prefix_metrics")]
+        self.record_key_bytes(n);
+
+        prefixed.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Data {
+    fn deserialize<D>(deserializer: D) -> Result<Data, D::Error>
+        where D: Deserializer<'de>
+    {
+        fn unprefix(prefix: &[u8], rest: Key) -> Key {
+            let mut key = Vec::with_capacity(prefix.len() + rest.len());
+            key.extend_from_slice(prefix);
+            key.extend_from_slice(&*rest);
+            key
+        }
+
+        let data = match Prefixed::deserialize(deserializer)? {
+            Prefixed::Index(prefix, ptrs) => Data::Index(
+                ptrs.into_iter()
+                    .map(|(rest, pid)| (unprefix(&*prefix, rest), pid))
+                    .collect(),
+            ),
+            Prefixed::Leaf(prefix, items) => Data::Leaf(
+                items
+                    .into_iter()
+                    .map(|(rest, v, expiry)| {
+                        (unprefix(&*prefix, rest), v, expiry)
+                    })
+                    .collect(),
+            ),
+        };
+        Ok(data)
+    }
 }
 
 impl Data {
@@ -14,47 +96,120 @@ impl Data {
         }
     }
 
-    pub fn split(&self) -> (Key, Data) {
-        fn split_inner<T>(xs: &[(Key, T)]) -> (Key, Vec<(Key, T)>)
+    /// Splits off the upper half, returning it along with the key that
+    /// separates it from the lower half. Leaves use the shortest such
+    /// key, which keeps the index nodes above them small. Index nodes
+    /// must use their first key, which is already the low bound of the
+    /// child it points to.
+    pub fn split(&self, order: KeyOrder) -> (Key, Data) {
+        fn split_inner<T>(xs: &[T]) -> (&T, Vec<T>)
             where T: Clone + Debug
         {
-            let (_lhs, rhs) = xs.split_at(xs.len() / 2 + 1);
-            let split = rhs.first().unwrap().0.clone();
-
-            (split, rhs.to_vec())
+            let (lhs, rhs) = xs.split_at(xs.len() / 2 + 1);
+            (lhs.last().unwrap(), rhs.to_vec())
         }
 
         match *self {
             Data::Index(ref ptrs) => {
-                let (split, rhs) = split_inner(ptrs);
-                (split, Data::Index(rhs))
+                let (_last_lhs, rhs) = split_inner(ptrs);
+                (rhs[0].0.clone(), Data::Index(rhs))
             }
             Data::Leaf(ref items) => {
-                let (split, rhs) = split_inner(items);
-                (split, Data::Leaf(rhs))
+                let (last_lhs, rhs) = split_inner(items);
+                let separator = order.separator(&*last_lhs.0, &*rhs[0].0);
+                (separator, Data::Leaf(rhs))
             }
         }
     }
 
-    pub fn drop_gte(&mut self, at: &Bound) {
+    pub fn drop_gte(&mut self, at: &Bound, order: KeyOrder) {
         let bound = at.inner().unwrap();
+        let below = |k: &[u8]| order.cmp_keys(k, &*bound) == Ordering::Less;
+        match *self {
+            Data::Index(ref mut ptrs) => ptrs.retain(|&(ref k, _)| below(k)),
+            Data::Leaf(ref mut items) => {
+                items.retain(|&(ref k, _, _)| below(k))
+            }
+        }
+    }
+
+    // the longest prefix of the first key that every other key shares.
+    // keys may be ordered by a custom comparator, so all of them are
+    // compared rather than only the first and last.
+    fn common_prefix(&self) -> &[u8] {
+        fn shared<'a, I>(mut keys: I) -> &'a [u8]
+            where I: Iterator<Item = &'a [u8]>
+        {
+            let first = match keys.next() {
+                Some(first) => first,
+                None => return &[],
+            };
+            let len = keys.fold(first.len(), |len, k| {
+                first[..len].iter().zip(k).take_while(|&(a, b)| a == b).count()
+            });
+            &first[..len]
+        }
+
         match *self {
-            Data::Index(ref mut ptrs) => ptrs.retain(|&(ref k, _)| k < &bound),
-            Data::Leaf(ref mut items) => items.retain(|&(ref k, _)| k < &bound),
+            Data::Index(ref ptrs) => shared(ptrs.iter().map(|p| &*p.0)),
+            Data::Leaf(ref items) => shared(items.iter().map(|i| &*i.0)),
         }
     }
 
-    pub fn leaf(&self) -> Option<Vec<(Key, Value)>> {
+    // counts the key bytes of a node being serialized with a common
+    // prefix of the given length, and the bytes the prefix saved.
+    #[cfg(feature = "key_prefix_metrics")]
+    fn record_key_bytes(&self, prefix_len: usize) {
+        let key_bytes: usize = match *self {
+            Data::Index(ref ptrs) => ptrs.iter().map(|p| p.0.len()).sum(),
+            Data::Leaf(ref items) => items.iter().map(|i| i.0.len()).sum(),
+        };
+        let saved = prefix_len * self.len().saturating_sub(1);
+        M.node_key_bytes.fetch_add(key_bytes, Relaxed);
+        M.node_key_bytes_saved.fetch_add(saved, Relaxed);
+    }
+
+    pub fn leaf(&self) -> Option<Vec<(Key, StoredValue, Option<u64>)>> {
         match *self {
             Data::Index(_) => None,
             Data::Leaf(ref items) => Some(items.clone()),
         }
     }
 
-    pub fn leaf_ref(&self) -> Option<&Vec<(Key, Value)>> {
+    pub fn leaf_ref(&self) -> Option<&Vec<(Key, StoredValue, Option<u64>)>> {
         match *self {
             Data::Index(_) => None,
             Data::Leaf(ref items) => Some(items),
         }
     }
 }
+
+#[test]
+fn test_prefix_compression() {
+    use bincode::{Infinite, deserialize, serialize};
+
+    let key = |i: u8| {
+        let mut key = b"users/1234/posts/".to_vec();
+        key.push(i);
+        key
+    };
+    let items: Vec<_> = (0..10)
+        .map(|i| (key(i), StoredValue::Inline(vec![i]), None))
+        .collect();
+    let uncompressed = serialize(&items, Infinite).unwrap();
+    let leaf = Data::Leaf(items);
+    assert_eq!(leaf.common_prefix(), b"users/1234/posts/");
+
+    let bytes = serialize(&leaf, Infinite).unwrap();
+    assert!(bytes.len() < uncompressed.len() - 8 * 17);
+    assert_eq!(deserialize::<Data>(&*bytes).unwrap(), leaf);
+
+    let index = Data::Index(vec![(vec![], 0), (key(5), 1)]);
+    assert_eq!(index.common_prefix(), b"");
+    let bytes = serialize(&index, Infinite).unwrap();
+    assert_eq!(deserialize::<Data>(&*bytes).unwrap(), index);
+
+    let empty = Data::Leaf(vec![]);
+    let bytes = serialize(&empty, Infinite).unwrap();
+    assert_eq!(deserialize::<Data>(&*bytes).unwrap(), empty);
+}
@@ -0,0 +1,99 @@
+use super::*;
+
+use coco::epoch::pin;
+
+/// A collection of named `Tree`s that share one `PageCache`, and so
+/// one storage file and log.
+///
+/// # Examples
+///
+/// ```
+/// let db = sled::Config::default().db().unwrap();
+/// let users = db.open_tree(b"users".to_vec()).unwrap();
+/// let posts = db.open_tree(b"posts".to_vec()).unwrap();
+///
+/// users.set(b"1".to_vec(), b"alice".to_vec()).unwrap();
+/// posts.set(b"1".to_vec(), b"hello world".to_vec()).unwrap();
+///
+/// assert_eq!(users.get(b"1"), Ok(Some(b"alice".to_vec())));
+/// assert_eq!(posts.get(b"1"), Ok(Some(b"hello world".to_vec())));
+/// assert_eq!(
+///     db.tree_names(),
+///     Ok(vec![b"posts".to_vec(), b"users".to_vec()])
+/// );
+/// ```
+pub struct Db {
+    pub(super) pages: Arc<PageCache<BLinkMaterializer, Frag, Meta>>,
+    pub(super) config: Config,
+    // handles to every opened tree, so that all handles to one
+    // name share a root.
+    trees: Mutex<HashMap<Vec<u8>, Arc<Tree>>>,
+}
+
+unsafe impl Send for Db {}
+unsafe impl Sync for Db {}
+
+impl Db {
+    /// Load existing or create a new `Db`.
+    pub fn new(config: Config) -> Result<Db> {
+        let mut pages = PageCache::new(
+            BLinkMaterializer {
+                merge_operator: config.get_merge_operator(),
+                key_comparator: config
+                    .get_key_comparator()
+                    .map(|(_name, cmp)| cmp),
+            },
+            config.clone(),
+        )?;
+
+        if let Some(meta) = pages.recover()? {
+            debug!("recovered meta {:?} while starting db", meta);
+            if !config.get_read_only() {
+                pin(|scope| blob::reclaim(&pages, scope))?;
+            }
+        } else {
+            pin(|scope| -> Result<()> {
+                let (meta_id, meta_cas_key) = pages.allocate(scope)?;
+                assert_eq!(
+                    meta_id,
+                    meta::META_PID,
+                    "the meta page must be the first page allocated"
+                );
+                let meta = Frag::Meta(Meta::default());
+                pages.replace(meta_id, meta_cas_key, meta, scope)?.unwrap();
+                Ok(())
+            })?
+        }
+
+        Ok(Db {
+            pages: Arc::new(pages),
+            config: config,
+            trees: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Open the `Tree` with the given name, creating it if it does
+    /// not exist yet.
+    pub fn open_tree(&self, name: Vec<u8>) -> Result<Arc<Tree>> {
+        let mut trees = self.trees.lock().unwrap();
+        if let Some(tree) = trees.get(&name) {
+            return Ok(tree.clone());
+        }
+
+        let tree = Arc::new(Tree::open(
+            self.pages.clone(),
+            self.config.clone(),
+            name.clone(),
+        )?);
+        trees.insert(name, tree.clone());
+        Ok(tree)
+    }
+
+    /// Returns the names of every `Tree` that has been created in
+    /// this `Db`, in order.
+    pub fn tree_names(&self) -> Result<Vec<Vec<u8>>> {
+        pin(|scope| {
+            meta::meta(&self.pages, scope).map(|(meta, _)| meta.tree_names())
+        })
+    }
+}
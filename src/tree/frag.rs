@@ -1,22 +1,44 @@
 use super::*;
 
-// TODO
-// Merged
-// LeftMerge(head: Raw, rhs: PageID, hi: Bound)
-// ParentMerge(lhs: PageID, rhs: PageID)
-// TxBegin(TxID), // in-mem
-// TxCommit(TxID), // in-mem
-// TxAbort(TxID), // in-mem
-
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Frag {
-    Set(Key, Value),
+    Set(Key, StoredValue),
+    /// A `Set` of a key that expires at the given time, in milliseconds
+    /// since the UNIX epoch.
+    SetWithTtl(Key, StoredValue, u64),
     Del(Key),
+    /// An operand to be combined with the current value of a key
+    /// by the configured `MergeOperator`.
+    Merge(Key, Value),
     /// The bool in Base means this node has been a root at some point.
     /// This is useful during recovery to figure out the current root.
     Base(Node, bool),
     ChildSplit(ChildSplit),
     ParentSplit(ParentSplit),
+    /// Stops an index node from splitting, or from merging any other
+    /// child, while one of its children is merged away.
+    ParentMergeIntention(PageID),
+    /// Clears a `ParentMergeIntention` if the child could not be merged.
+    ParentMergeCancel(PageID),
+    /// Marks an empty leaf, or an index node that only leads to one,
+    /// as merged into a sibling. No further updates may be linked to it.
+    Merged,
+    /// Extends a node over the key range of its merged right sibling.
+    LeftMerge(LeftMerge),
+    /// Extends a node over the key range of its merged left sibling.
+    RightMerge(RightMerge),
+    /// Removes a merged child from an index node. If it was the first
+    /// child, the next one takes over its low key.
+    ParentMerge(PageID),
+    /// Claims a leaf for the transaction committing as the batch that
+    /// began at the given `Lsn`. Other writers wait for the batch to
+    /// close before linking to the leaf.
+    TxClaim(Lsn),
+    /// The names and roots of every `Tree` in a `Db`.
+    Meta(Meta),
+    /// One chunk of a value stored outside of its leaf. It is the only
+    /// frag of its page.
+    Blob(Value),
 }
 
 impl Frag {
@@ -46,3 +68,16 @@ pub struct ChildSplit {
     pub at: Bound,
     pub to: PageID,
 }
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LeftMerge {
+    pub rhs: PageID,
+    pub hi: Bound,
+    pub next: Option<PageID>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RightMerge {
+    pub lhs: PageID,
+    pub lo: Bound,
+}
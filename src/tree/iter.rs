@@ -2,39 +2,176 @@ use super::*;
 
 use coco::epoch::pin;
 
-/// An iterator over keys and values in a `Tree`.
+/// An iterator over keys and values in a `Tree`. If reading a page
+/// fails, the error is yielded in place of the next item, and the
+/// iterator yields nothing after it.
 pub struct Iter<'a> {
     pub(super) id: PageID,
-    pub(super) inner: &'a PageCache<BLinkMaterializer, Frag, PageID>,
+    pub(super) tree: &'a Tree,
     pub(super) last_key: Bound,
-    // TODO we have to refactor this in light of pages being deleted
+    pub(super) hi: Bound,
+    // yielded before any item, by an iterator over an empty range
+    // that could not be iterated over as asked.
+    pub(super) refused: Option<Error>,
+    // set once an error was yielded, so that loops over the iterator
+    // end instead of hitting the same error again.
+    pub(super) failed: bool,
 }
 
 impl<'a> Iterator for Iter<'a> {
-    type Item = (Vec<u8>, Vec<u8>);
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        if let Some(e) = self.refused.take() {
+            self.failed = true;
+            return Some(Err(e));
+        }
         let start = clock();
-        pin(|scope| {
-            loop {
-                let (frag, _cas_key) = self.inner.get(self.id, scope).unwrap();
-                let (node, _is_root) = frag.base().unwrap();
-                // TODO this could be None if the node was removed since the last
-                // iteration, and we need to just get the inner node again...
-                for (ref k, ref v) in node.data.leaf().unwrap() {
-                    if Bound::Inc(k.clone()) > self.last_key {
+        let now = now_ms();
+        let order = self.tree.order;
+        let ret = pin(|scope| {
+            'read_leaf: loop {
+                let get_node = self.tree
+                    .pages
+                    .get(self.id, scope)?
+                    .and_then(|(frag, _cas_key)| frag.into_base());
+                let node = match get_node {
+                    Some((ref node, _)) if self.covers(node) => node.clone(),
+                    _ => {
+                        // our page was freed, merged or split since we
+                        // last looked at it, so we find the leaf that
+                        // now holds last_key, starting from the root.
+                        let key = self.last_key.inner().unwrap();
+                        let mut path = self.tree.path_for_key(&*key, scope)?;
+                        let (node, _cas_key) = path.pop().unwrap();
+                        self.id = node.id;
+                        node
+                    }
+                };
+
+                for &(ref k, ref v, expiry) in node.data.leaf_ref().unwrap() {
+                    let k_bound = Bound::Inc(k.clone());
+                    if order.lt(&self.last_key, &k_bound) {
+                        if order.lt(&self.hi, &k_bound) {
+                            // we've passed the end of our range
+                            M.tree_scan.measure(clock() - start);
+                            return Ok(None);
+                        }
+                        if is_expired(expiry, now) {
+                            continue;
+                        }
+                        let v = match blob::load(&self.tree.pages, v, scope)? {
+                            Some(v) => v,
+                            None => {
+                                // a write freed the blob after we read
+                                // the leaf, so read it again.
+                                M.tree_looped();
+                                continue 'read_leaf;
+                            }
+                        };
                         self.last_key = Bound::Inc(k.to_vec());
-                        let ret = Some((k.clone(), v.clone()));
+                        let ret = Some((k.clone(), v));
                         M.tree_scan.measure(clock() - start);
-                        return ret;
+                        return Ok(ret);
                     }
                 }
-                if node.next.is_none() {
+                // don't page in the next leaf if this one already
+                // covers the end of our range
+                if node.next.is_none() || order.le(&self.hi, &node.hi) {
                     M.tree_scan.measure(clock() - start);
-                    return None;
+                    return Ok(None);
                 }
+                // we've seen every key below this leaf's hi
+                self.last_key = Bound::Non(node.hi.inner().unwrap());
                 self.id = node.next.unwrap();
             }
-        })
+        }).transpose();
+        if let Some(Err(_)) = ret {
+            self.failed = true;
+        }
+        ret
+    }
+}
+
+impl<'a> Iter<'a> {
+    // whether the keys after last_key would be found in this node.
+    fn covers(&self, node: &Node) -> bool {
+        if node.merged || node.data.leaf_ref().is_none() {
+            return false;
+        }
+        let order = self.tree.order;
+        let lo = node.lo.inner().unwrap();
+        let key = self.last_key.inner().unwrap();
+        order.cmp_keys(&*lo, &*key) != Ordering::Greater &&
+            order.lt(&Bound::Inc(key), &node.hi)
+    }
+}
+
+impl<'a> DoubleEndedIterator for Iter<'a> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        if let Some(e) = self.refused.take() {
+            self.failed = true;
+            return Some(Err(e));
+        }
+        let start = clock();
+        let now = now_ms();
+        let order = self.tree.order;
+        let ret = pin(|scope| {
+            'read_leaf: loop {
+                if order.le(&self.hi, &self.last_key) {
+                    // the two ends of the iterator have met
+                    M.tree_scan.measure(clock() - start);
+                    return Ok(None);
+                }
+
+                // leaves only point forward, so we descend from the
+                // root to find the leaf that precedes our upper bound.
+                let (node, _cas_key) =
+                    self.tree.leaf_for_upper_bound(&self.hi, scope)?;
+
+                let items = node.data.leaf_ref().unwrap();
+                for &(ref k, ref v, expiry) in items.iter().rev() {
+                    let k_bound = Bound::Inc(k.clone());
+                    if order.lt(&self.hi, &k_bound) {
+                        continue;
+                    }
+                    if order.le(&k_bound, &self.last_key) {
+                        M.tree_scan.measure(clock() - start);
+                        return Ok(None);
+                    }
+                    if is_expired(expiry, now) {
+                        continue;
+                    }
+                    let v = match blob::load(&self.tree.pages, v, scope)? {
+                        Some(v) => v,
+                        None => {
+                            M.tree_looped();
+                            continue 'read_leaf;
+                        }
+                    };
+                    self.hi = Bound::Non(k.clone());
+                    M.tree_scan.measure(clock() - start);
+                    return Ok(Some((k.clone(), v)));
+                }
+
+                let lo = node.lo.inner().unwrap();
+                if lo.is_empty() {
+                    // we've exhausted the leftmost leaf
+                    M.tree_scan.measure(clock() - start);
+                    return Ok(None);
+                }
+                self.hi = Bound::Non(lo);
+            }
+        }).transpose();
+        if let Some(Err(_)) = ret {
+            self.failed = true;
+        }
+        ret
     }
 }
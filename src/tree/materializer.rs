@@ -2,22 +2,34 @@ use super::*;
 
 #[derive(Default, Debug)]
 pub struct BLinkMaterializer {
-    // TODO use interval-based tracking to handle race conditions in hoists where higher is not
-    // later.
-    pub(super) roots: Mutex<Vec<PageID>>,
+    pub(super) merge_operator: Option<MergeOperator>,
+    pub(super) key_comparator: Option<KeyComparator>,
 }
 
 impl Materializer for BLinkMaterializer {
     type PageFrag = Frag;
-    type Recovery = PageID;
+    type Recovery = Meta;
 
     fn merge(&self, frags: &[&Frag]) -> Frag {
+        if let Frag::Meta(ref meta) = *frags[0] {
+            // the Meta page is always replaced, never appended to
+            assert_eq!(frags.len(), 1);
+            return Frag::Meta(meta.clone());
+        }
+
+        if let Frag::Blob(ref chunk) = *frags[0] {
+            // blob pages are written once and never appended to
+            assert_eq!(frags.len(), 1);
+            return Frag::Blob(chunk.clone());
+        }
+
         let mut base_node_opt: Option<Node> = None;
         let mut root = false;
 
         for &frag in frags {
             if let Some(ref mut base_node) = base_node_opt {
-                base_node.apply(frag);
+                let order = KeyOrder::new(self.key_comparator);
+                base_node.apply(frag, self.merge_operator, order);
             } else {
                 let (base_node, is_root) = frag.base().unwrap();
                 if is_root {
@@ -31,21 +43,9 @@ impl Materializer for BLinkMaterializer {
         Frag::Base(base_node_opt.unwrap(), root)
     }
 
-    fn recover(&self, frag: &Frag) -> Option<PageID> {
+    fn recover(&self, frag: &Frag) -> Option<Meta> {
         match *frag {
-            Frag::Base(ref node, root) => {
-                if root {
-                    let mut roots = self.roots.lock().unwrap();
-                    if roots.contains(&node.id) {
-                        None
-                    } else {
-                        roots.push(node.id);
-                        Some(node.id)
-                    }
-                } else {
-                    None
-                }
-            }
+            Frag::Meta(ref meta) => Some(meta.clone()),
             _ => None,
         }
     }
@@ -0,0 +1,151 @@
+use std::collections::BTreeSet;
+
+use super::*;
+
+use coco::epoch::Scope;
+
+/// The page holding the `Meta` of a `Db`. It is the first page
+/// allocated in a new `Db`, and is never freed.
+pub(super) const META_PID: PageID = 0;
+
+/// The name of the `Tree` returned by `Tree::new`.
+pub(super) const DEFAULT_TREE_NAME: &'static [u8] = b"__sled__default";
+
+/// Maps the names of the `Tree`s in a `Db` to their current roots,
+/// to the names of the comparators that order their keys, and to the
+/// number of keys they held when it was last recorded. Also names the
+/// `Tree`s that `Tree::merge` or `Tree::set_with_ttl` was called on,
+/// and whether any value was ever stored as a blob.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Meta {
+    roots: BTreeMap<Vec<u8>, PageID>,
+    comparators: BTreeMap<Vec<u8>, String>,
+    approx_lens: BTreeMap<Vec<u8>, u64>,
+    merged: BTreeSet<Vec<u8>>,
+    expiring: BTreeSet<Vec<u8>>,
+    blobs: bool,
+}
+
+impl Meta {
+    /// Returns the root of the named `Tree`, if it exists.
+    pub fn root(&self, name: &[u8]) -> Option<PageID> {
+        self.roots.get(name).cloned()
+    }
+
+    /// Points the named `Tree` at a new root.
+    pub fn set_root(&mut self, name: Vec<u8>, root: PageID) {
+        self.roots.insert(name, root);
+    }
+
+    /// Returns the name of the comparator the named `Tree` was created
+    /// with, or `None` if its keys are in lexicographic order.
+    pub fn comparator(&self, name: &[u8]) -> Option<&str> {
+        self.comparators.get(name).map(|cmp| &**cmp)
+    }
+
+    /// Records the comparator that orders the keys of the named `Tree`.
+    pub fn set_comparator(&mut self, name: Vec<u8>, comparator: Option<&str>) {
+        if let Some(comparator) = comparator {
+            self.comparators.insert(name, comparator.to_owned());
+        } else {
+            self.comparators.remove(&name);
+        }
+    }
+
+    /// Returns `true` if the named `Tree` may hold operands written by
+    /// `Tree::merge`, which can only be read with a merge operator.
+    pub fn merged(&self, name: &[u8]) -> bool {
+        self.merged.contains(name)
+    }
+
+    /// Records that the named `Tree` may hold merge operands.
+    pub fn set_merged(&mut self, name: Vec<u8>) {
+        self.merged.insert(name);
+    }
+
+    /// Returns `true` if the named `Tree` may hold keys that expire,
+    /// which the reaper deletes.
+    pub fn expiring(&self, name: &[u8]) -> bool {
+        self.expiring.contains(name)
+    }
+
+    /// Records that the named `Tree` may hold keys that expire.
+    pub fn set_expiring(&mut self, name: Vec<u8>) {
+        self.expiring.insert(name);
+    }
+
+    /// Returns `true` if a value may have been stored as a blob, whose
+    /// pages must be checked for leaks during recovery.
+    pub fn stored_blobs(&self) -> bool {
+        self.blobs
+    }
+
+    /// Records that a value was stored as a blob.
+    pub fn set_stored_blobs(&mut self) {
+        self.blobs = true;
+    }
+
+    /// Returns the number of keys last recorded for the named `Tree`.
+    pub fn approx_len(&self, name: &[u8]) -> u64 {
+        self.approx_lens.get(name).cloned().unwrap_or(0)
+    }
+
+    /// Records the number of keys in the named `Tree`.
+    pub fn set_approx_len(&mut self, name: Vec<u8>, len: u64) {
+        self.approx_lens.insert(name, len);
+    }
+
+    /// Returns the names of all `Tree`s, in order.
+    pub fn tree_names(&self) -> Vec<Vec<u8>> {
+        self.roots.keys().cloned().collect()
+    }
+}
+
+/// Reads the current `Meta` along with the key needed to replace it.
+pub(super) fn meta<'s>(
+    pages: &PageCache<BLinkMaterializer, Frag, Meta>,
+    scope: &'s Scope,
+) -> Result<(Meta, HPtr<'s, Frag>)> {
+    let (frag, cas_key) = pages.get(META_PID, scope)?.unwrap();
+    match frag {
+        Frag::Meta(meta) => Ok((meta, cas_key)),
+        other => {
+            panic!("expected Meta on page {}, found {:?}", META_PID, other)
+        }
+    }
+}
+
+/// Records the root of a `Tree` in the `Meta`. The root is read from
+/// `root_fn` on every attempt, so that when concurrent root hoists race
+/// to update the `Meta`, the last successful write carries the latest root.
+pub(super) fn set_root<'s, F>(
+    pages: &PageCache<BLinkMaterializer, Frag, Meta>,
+    name: &[u8],
+    root_fn: F,
+    scope: &'s Scope,
+) -> Result<()>
+    where F: Fn() -> PageID
+{
+    update(pages, |meta| meta.set_root(name.to_vec(), root_fn()), scope)
+}
+
+/// Applies `update_fn` to the current `Meta` until the result is
+/// successfully written.
+pub(super) fn update<'s, F>(
+    pages: &PageCache<BLinkMaterializer, Frag, Meta>,
+    update_fn: F,
+    scope: &'s Scope,
+) -> Result<()>
+    where F: Fn(&mut Meta)
+{
+    loop {
+        let (mut meta, cas_key) = meta(pages, scope)?;
+        update_fn(&mut meta);
+        if pages
+            .replace(META_PID, cas_key, Frag::Meta(meta), scope)?
+            .is_ok()
+        {
+            return Ok(());
+        }
+    }
+}
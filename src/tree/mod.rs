@@ -1,22 +1,49 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::{self, Debug};
-use std::sync::Mutex;
-use std::sync::atomic::AtomicUsize;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicIsize, AtomicUsize};
 use std::sync::atomic::Ordering::SeqCst;
 
 use super::*;
 
+mod batch;
+mod blob;
+mod bulk;
 mod bound;
 mod data;
+mod db;
 mod frag;
 mod node;
 mod tree;
+mod tx;
 mod iter;
 mod materializer;
+mod meta;
+mod ordered_key;
+mod reaper;
+mod snapshot;
+mod subscription;
+mod typed;
 
-pub use self::bound::Bound;
-pub use self::frag::{ChildSplit, Frag, ParentSplit};
+pub use self::batch::Batch;
+pub use self::blob::StoredValue;
+pub use self::bulk::BulkLoadError;
+pub use self::bound::{Bound, KeyOrder};
+pub use self::frag::{ChildSplit, Frag, LeftMerge, ParentSplit, RightMerge};
 pub use self::data::Data;
+pub use self::db::Db;
 pub use self::node::Node;
 pub use self::tree::Tree;
+pub use self::tx::Tx;
 pub use self::iter::Iter;
 pub use self::materializer::BLinkMaterializer;
+pub use self::meta::Meta;
+pub use self::ordered_key::OrderedKey;
+pub use self::snapshot::{Snapshot, SnapshotIter};
+pub use self::subscription::{Event, Subscriber};
+pub use self::typed::{TypedIter, TypedTree};
+
+use self::batch::{BatchWrite, OpenBatch};
+use self::reaper::{Reaper, is_expired, now_ms};
+use self::subscription::Subscriptions;
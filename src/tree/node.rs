@@ -7,72 +7,193 @@ pub struct Node {
     pub next: Option<PageID>,
     pub lo: Bound,
     pub hi: Bound,
+    /// The child currently being merged away, if this is an index node.
+    pub merging_child: Option<PageID>,
+    /// Whether this node has been merged into a sibling.
+    pub merged: bool,
+    /// The batch of the last transaction to claim this leaf, which
+    /// only holds the leaf while that batch is being committed.
+    pub claim: Option<Lsn>,
 }
 
 impl Node {
-    pub fn apply(&mut self, frag: &Frag) {
+    pub fn apply(
+        &mut self,
+        frag: &Frag,
+        merge_operator: Option<MergeOperator>,
+        order: KeyOrder,
+    ) {
         use self::Frag::*;
 
         match *frag {
             Set(ref k, ref v) => {
-                if Bound::Inc(k.clone()) < self.hi {
-                    self.set_leaf(k.clone(), v.clone());
+                if order.lt(&Bound::Inc(k.clone()), &self.hi) {
+                    self.set_leaf(k.clone(), v.clone(), None, order);
+                } else {
+                    panic!("tried to consolidate set at key <= hi")
+                }
+            }
+            SetWithTtl(ref k, ref v, expiry) => {
+                if order.lt(&Bound::Inc(k.clone()), &self.hi) {
+                    self.set_leaf(k.clone(), v.clone(), Some(expiry), order);
                 } else {
                     panic!("tried to consolidate set at key <= hi")
                 }
             }
             ChildSplit(ref child_split) => {
-                self.child_split(child_split);
+                self.child_split(child_split, order);
             }
             ParentSplit(ref parent_split) => {
-                self.parent_split(parent_split);
+                self.parent_split(parent_split, order);
             }
             Del(ref k) => {
-                if Bound::Inc(k.clone()) < self.hi {
-                    self.del_leaf(k);
+                if order.lt(&Bound::Inc(k.clone()), &self.hi) {
+                    self.del_leaf(k, order);
                 } else {
                     panic!("tried to consolidate del at key <= hi")
                 }
             }
+            Merge(ref k, ref operand) => {
+                if order.lt(&Bound::Inc(k.clone()), &self.hi) {
+                    let merge_fn = merge_operator.expect(
+                        "must set a merge operator to consolidate merges",
+                    );
+                    self.merge_leaf(k.clone(), operand, merge_fn, order);
+                } else {
+                    panic!("tried to consolidate merge at key <= hi")
+                }
+            }
+            ParentMergeIntention(pid) => {
+                self.merging_child = Some(pid);
+            }
+            ParentMergeCancel(pid) => {
+                if self.merging_child == Some(pid) {
+                    self.merging_child = None;
+                }
+            }
+            Merged => {
+                self.merged = true;
+            }
+            LeftMerge(ref left_merge) => {
+                self.left_merge(left_merge);
+            }
+            RightMerge(ref right_merge) => {
+                self.right_merge(right_merge);
+            }
+            ParentMerge(pid) => {
+                self.parent_merge(pid);
+            }
+            TxClaim(batch_lsn) => {
+                self.claim = Some(batch_lsn);
+            }
             Base(_, _) => panic!("encountered base page in middle of chain"),
+            Meta(_) => panic!("encountered Meta in the chain of a node"),
+            Blob(_) => panic!("encountered Blob in the chain of a node"),
         }
     }
 
-    pub fn set_leaf(&mut self, key: Key, val: Value) {
+    pub fn set_leaf(
+        &mut self,
+        key: Key,
+        val: StoredValue,
+        expiry: Option<u64>,
+        order: KeyOrder,
+    ) {
         if let Data::Leaf(ref mut records) = self.data {
-            let search =
-                records.binary_search_by(|&(ref k, ref _v)| (**k).cmp(&*key));
-            if let Ok(idx) = search {
-                records.push((key, val));
-                records.swap_remove(idx);
-            } else {
-                records.push((key, val));
-                records.sort();
+            let search = records.binary_search_by(|&(ref k, ref _v, _)| {
+                order.cmp_keys(k, &*key)
+            });
+            match search {
+                Ok(idx) => {
+                    records.push((key, val, expiry));
+                    records.swap_remove(idx);
+                }
+                Err(idx) => records.insert(idx, (key, val, expiry)),
             }
         } else {
             panic!("tried to Set a value to an index");
         }
     }
 
-    pub fn child_split(&mut self, cs: &ChildSplit) {
-        self.data.drop_gte(&cs.at);
+    pub fn merge_leaf(
+        &mut self,
+        key: Key,
+        operand: &[u8],
+        merge_fn: MergeOperator,
+        order: KeyOrder,
+    ) {
+        let (new, expiry) = {
+            let records = self.data.leaf_ref().expect(
+                "tried to Merge a value into an index",
+            );
+            let search = records.binary_search_by(|&(ref k, ref _v, _)| {
+                order.cmp_keys(k, &*key)
+            });
+            let existing = search.ok().map(|idx| &records[idx]);
+            let old = existing.map(|r| match r.1 {
+                StoredValue::Inline(ref v) => &**v,
+                // Tree::merge replaces blobs with a Set instead
+                StoredValue::Blob(_) => panic!("tried to Merge into a blob"),
+            });
+            let new = merge_fn(&*key, old, operand);
+            // merging into a key does not change when it expires
+            (new, existing.and_then(|r| r.2))
+        };
+
+        if let Some(new) = new {
+            self.set_leaf(key, StoredValue::Inline(new), expiry, order);
+        } else {
+            self.del_leaf(&*key, order);
+        }
+    }
+
+    pub fn child_split(&mut self, cs: &ChildSplit, order: KeyOrder) {
+        self.data.drop_gte(&cs.at, order);
         self.hi = Bound::Non(cs.at.inner().unwrap());
         self.next = Some(cs.to);
     }
 
-    pub fn parent_split(&mut self, ps: &ParentSplit) {
+    pub fn parent_split(&mut self, ps: &ParentSplit, order: KeyOrder) {
         if let Data::Index(ref mut ptrs) = self.data {
             ptrs.push((ps.at.inner().unwrap(), ps.to));
-            ptrs.sort_by(|a, b| a.0.cmp(&b.0));
+            ptrs.sort_by(|a, b| order.cmp_keys(&a.0, &b.0));
         } else {
             panic!("tried to attach a ParentSplit to a Leaf chain");
         }
     }
 
-    pub fn del_leaf(&mut self, key: KeyRef) {
+    pub fn left_merge(&mut self, lm: &LeftMerge) {
+        assert_eq!(self.next, Some(lm.rhs), "LeftMerge applied to non-sibling");
+        self.hi = lm.hi.clone();
+        self.next = lm.next;
+    }
+
+    pub fn right_merge(&mut self, rm: &RightMerge) {
+        self.lo = rm.lo.clone();
+    }
+
+    pub fn parent_merge(&mut self, pid: PageID) {
+        if let Data::Index(ref mut ptrs) = self.data {
+            if let Some(idx) = ptrs.iter().position(|&(_, ptr)| ptr == pid) {
+                let (sep, _) = ptrs.remove(idx);
+                if idx == 0 && !ptrs.is_empty() {
+                    // the next child was extended down to the separator
+                    ptrs[0].0 = sep;
+                }
+            }
+        } else {
+            panic!("tried to attach a ParentMerge to a Leaf chain");
+        }
+        if self.merging_child == Some(pid) {
+            self.merging_child = None;
+        }
+    }
+
+    pub fn del_leaf(&mut self, key: KeyRef, order: KeyOrder) {
         if let Data::Leaf(ref mut records) = self.data {
-            let search =
-                records.binary_search_by(|&(ref k, ref _v)| (**k).cmp(key));
+            let search = records.binary_search_by(|&(ref k, ref _v, _)| {
+                order.cmp_keys(k, key)
+            });
             if let Ok(idx) = search {
                 records.remove(idx);
             }
@@ -82,17 +203,23 @@ impl Node {
     }
 
     pub fn should_split(&self, fanout: usize) -> bool {
-        self.data.len() > fanout
+        // an index node must keep its merging child away from the
+        // front, so it does not split until the merge completes.
+        self.merging_child.is_none() && !self.merged &&
+            self.data.len() > fanout
     }
 
-    pub fn split(&self, id: PageID) -> Node {
-        let (split, right_data) = self.data.split();
+    pub fn split(&self, id: PageID, order: KeyOrder) -> Node {
+        let (split, right_data) = self.data.split(order);
         Node {
             id: id,
             data: right_data,
             next: self.next,
             lo: Bound::Inc(split),
             hi: self.hi.clone(),
+            merging_child: None,
+            merged: false,
+            claim: None,
         }
     }
 }
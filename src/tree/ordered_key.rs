@@ -0,0 +1,176 @@
+use std::mem;
+
+/// A key type for a `TypedTree`, encoded so that the order of the
+/// encoded bytes matches the order of the keys themselves.
+///
+/// Unsigned integers are stored big-endian, and signed integers have
+/// their sign bit flipped first so that negative numbers sort before
+/// positive ones. Byte strings and `String`s have every zero byte
+/// escaped as `0x00 0xFF` and are terminated with `0x00 0x00`, which
+/// keeps shorter strings before their extensions even when they are
+/// followed by more tuple fields. Tuples concatenate their fields, so
+/// they sort by their first field, then their second, and so on.
+pub trait OrderedKey: Sized {
+    /// Append the encoding of this key to `out`.
+    fn encode_key(&self, out: &mut Vec<u8>);
+
+    /// Decode a key from the front of `input`, advancing it past the
+    /// consumed bytes. Returns `None` if `input` does not start with
+    /// a valid encoding.
+    fn decode_key(input: &mut &[u8]) -> Option<Self>;
+}
+
+macro_rules! impl_unsigned {
+    ($($t:ty),*) => {$(
+        impl OrderedKey for $t {
+            fn encode_key(&self, out: &mut Vec<u8>) {
+                let size = mem::size_of::<$t>();
+                for i in (0..size).rev() {
+                    out.push((*self >> (i * 8)) as u8);
+                }
+            }
+
+            fn decode_key(input: &mut &[u8]) -> Option<$t> {
+                let size = mem::size_of::<$t>();
+                if input.len() < size {
+                    return None;
+                }
+                let mut decoded: $t = 0;
+                for &byte in &input[..size] {
+                    decoded = decoded.wrapping_shl(8) | byte as $t;
+                }
+                *input = &input[size..];
+                Some(decoded)
+            }
+        }
+    )*}
+}
+
+impl_unsigned!(u8, u16, u32, u64);
+
+macro_rules! impl_signed {
+    ($($t:ty => $u:ty),*) => {$(
+        impl OrderedKey for $t {
+            fn encode_key(&self, out: &mut Vec<u8>) {
+                let sign = 1 << (mem::size_of::<$u>() * 8 - 1);
+                (*self as $u ^ sign).encode_key(out)
+            }
+
+            fn decode_key(input: &mut &[u8]) -> Option<$t> {
+                let sign = 1 << (mem::size_of::<$u>() * 8 - 1);
+                <$u>::decode_key(input).map(|u| (u ^ sign) as $t)
+            }
+        }
+    )*}
+}
+
+impl_signed!(i8 => u8, i16 => u16, i32 => u32, i64 => u64);
+
+// pointer-sized integers are stored as 64 bits, so that the encoding
+// does not depend on the platform that wrote it.
+impl OrderedKey for usize {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        (*self as u64).encode_key(out)
+    }
+
+    fn decode_key(input: &mut &[u8]) -> Option<usize> {
+        u64::decode_key(input).map(|u| u as usize)
+    }
+}
+
+impl OrderedKey for isize {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        (*self as i64).encode_key(out)
+    }
+
+    fn decode_key(input: &mut &[u8]) -> Option<isize> {
+        i64::decode_key(input).map(|i| i as isize)
+    }
+}
+
+impl OrderedKey for bool {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        out.push(*self as u8)
+    }
+
+    fn decode_key(input: &mut &[u8]) -> Option<bool> {
+        match u8::decode_key(input) {
+            Some(0) => Some(false),
+            Some(1) => Some(true),
+            _ => None,
+        }
+    }
+}
+
+impl OrderedKey for Vec<u8> {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        for &byte in self {
+            out.push(byte);
+            if byte == 0 {
+                out.push(0xFF);
+            }
+        }
+        out.extend_from_slice(&[0, 0]);
+    }
+
+    fn decode_key(input: &mut &[u8]) -> Option<Vec<u8>> {
+        let mut decoded = vec![];
+        let mut i = 0;
+        loop {
+            match (input.get(i).cloned(), input.get(i + 1).cloned()) {
+                (Some(0), Some(0)) => break,
+                (Some(0), Some(0xFF)) => {
+                    decoded.push(0);
+                    i += 2;
+                }
+                (Some(0), _) | (None, _) => return None,
+                (Some(byte), _) => {
+                    decoded.push(byte);
+                    i += 1;
+                }
+            }
+        }
+        *input = &input[i + 2..];
+        Some(decoded)
+    }
+}
+
+impl OrderedKey for String {
+    fn encode_key(&self, out: &mut Vec<u8>) {
+        // utf8 sorts bytewise in the same order as its code points
+        self.as_bytes().to_vec().encode_key(out)
+    }
+
+    fn decode_key(input: &mut &[u8]) -> Option<String> {
+        Vec::decode_key(input).and_then(|bytes| String::from_utf8(bytes).ok())
+    }
+}
+
+impl OrderedKey for () {
+    fn encode_key(&self, _out: &mut Vec<u8>) {}
+
+    fn decode_key(_input: &mut &[u8]) -> Option<()> {
+        Some(())
+    }
+}
+
+macro_rules! impl_tuple {
+    ($($name:ident),*) => {
+        impl<$($name: OrderedKey),*> OrderedKey for ($($name,)*) {
+            #[allow(non_snake_case)]
+            fn encode_key(&self, out: &mut Vec<u8>) {
+                let ($(ref $name,)*) = *self;
+                $($name.encode_key(out);)*
+            }
+
+            fn decode_key(input: &mut &[u8]) -> Option<($($name,)*)> {
+                Some(($($name::decode_key(input)?,)*))
+            }
+        }
+    }
+}
+
+impl_tuple!(A);
+impl_tuple!(A, B);
+impl_tuple!(A, B, C);
+impl_tuple!(A, B, C, D);
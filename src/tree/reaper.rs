@@ -0,0 +1,59 @@
+use std::sync::atomic::AtomicBool;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::*;
+
+/// Returns the current time in milliseconds since the UNIX epoch,
+/// which is what record expiry times are measured in.
+pub(super) fn now_ms() -> u64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    now.as_secs() * 1000 + u64::from(now.subsec_nanos() / 1_000_000)
+}
+
+/// Whether a record with this expiry time should be hidden at `now`.
+pub(super) fn is_expired(expiry: Option<u64>, now: u64) -> bool {
+    expiry.map(|expiry| expiry <= now).unwrap_or(false)
+}
+
+/// A background thread that deletes expired keys from a `Tree`.
+pub(super) struct Reaper {
+    shutdown: Arc<AtomicBool>,
+    handle: thread::JoinHandle<()>,
+}
+
+impl Reaper {
+    /// Spawns a thread that calls `reap` on `tree` every
+    /// `reap_every_ms` until the `Reaper` is stopped.
+    pub(super) fn start(tree: Tree, reap_every_ms: u64) -> Reaper {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let thread_shutdown = shutdown.clone();
+        let handle = thread::Builder::new()
+            .name("tree reaper".to_owned())
+            .spawn(move || while !thread_shutdown.load(SeqCst) {
+                if let Err(e) = tree.reap() {
+                    error!("failed to reap expired keys: {}", e);
+                }
+
+                // woken early by stop
+                thread::park_timeout(Duration::from_millis(reap_every_ms));
+            })
+            .unwrap();
+
+        Reaper {
+            shutdown: shutdown,
+            handle: handle,
+        }
+    }
+
+    /// Signals the thread to exit, and waits for it to do so. This is
+    /// called while the `Tree` is dropped, so a panic in the thread is
+    /// logged rather than passed on.
+    pub(super) fn stop(self) {
+        self.shutdown.store(true, SeqCst);
+        self.handle.thread().unpark();
+        if let Err(e) = self.handle.join() {
+            error!("tree reaper panicked: {:?}", e);
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use std::ops::RangeBounds;
+
+use super::*;
+
+use coco::epoch::pin;
+
+/// A read-only view of a `Tree` as it was when `Tree::snapshot` was
+/// called. Writes made to the `Tree` afterwards are not visible
+/// through it, and the log keeps the state it needs until it is
+/// dropped, so long-lived snapshots hold on to disk space.
+///
+/// # Examples
+///
+/// ```
+/// use sled::Config;
+/// let t = Config::default().tree().unwrap();
+/// t.set(vec![1], vec![10]).unwrap();
+/// let snapshot = t.snapshot().unwrap();
+/// t.set(vec![1], vec![11]).unwrap();
+/// t.set(vec![2], vec![20]).unwrap();
+/// assert_eq!(snapshot.get(&[1]), Ok(Some(vec![10])));
+/// assert_eq!(snapshot.get(&[2]), Ok(None));
+/// let items: Vec<_> = snapshot.iter().map(|res| res.unwrap()).collect();
+/// assert_eq!(items, vec![(vec![1], vec![10])]);
+/// ```
+pub struct Snapshot {
+    pages: Arc<PageCache<BLinkMaterializer, Frag, Meta>>,
+    lsn: Lsn,
+    root: PageID,
+    order: KeyOrder,
+    // records that expired before the snapshot was taken stay hidden,
+    // while later expiries are ignored.
+    now: u64,
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        self.pages.unpin_lsn(self.lsn);
+    }
+}
+
+impl Snapshot {
+    /// Reads the named `Tree` as of an `Lsn` returned by `pin_lsn`,
+    /// which the `Snapshot` unpins when dropped, or right away if the
+    /// root can't be read.
+    pub(super) fn new(
+        pages: Arc<PageCache<BLinkMaterializer, Frag, Meta>>,
+        name: &[u8],
+        order: KeyOrder,
+        lsn: Lsn,
+    ) -> Result<Snapshot> {
+        let now = now_ms();
+        let root = pin(|scope| {
+            match pages.get_at(meta::META_PID, lsn, scope) {
+                Ok(Some(Frag::Meta(meta))) => Ok(meta.root(name)),
+                Ok(_) => Ok(None),
+                Err(e) => Err(e),
+            }
+        });
+        let root = match root {
+            Ok(root) => root.expect("snapshot of a tree without a root"),
+            Err(e) => {
+                pages.unpin_lsn(lsn);
+                return Err(e);
+            }
+        };
+        Ok(Snapshot {
+            pages: pages,
+            lsn: lsn,
+            root: root,
+            order: order,
+            now: now,
+        })
+    }
+
+    /// Retrieve a value from the `Snapshot` if it exists.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Value>> {
+        let node = self.leaf_for_key(key)?;
+        let items = node.data.leaf_ref().unwrap();
+        let search = items.binary_search_by(|&(ref k, ref _v, _)| {
+            self.order.cmp_keys(k, key)
+        });
+        match search {
+            Ok(idx) if !is_expired(items[idx].2, self.now) => {
+                self.load(&items[idx].1).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Iterate over all keys and values in the `Snapshot`.
+    pub fn iter(&self) -> SnapshotIter {
+        self.range::<&[u8], _>(..)
+    }
+
+    /// Iterate over keys and values in the `Snapshot`, starting at
+    /// the provided key.
+    pub fn scan(&self, key: &[u8]) -> SnapshotIter {
+        self.range(key..)
+    }
+
+    /// Iterate over keys and values in the `Snapshot` within the
+    /// provided range.
+    pub fn range<K, R>(&self, range: R) -> SnapshotIter
+        where K: AsRef<[u8]>,
+              R: RangeBounds<K>
+    {
+        use std::ops::Bound::{Excluded, Included, Unbounded};
+
+        let last_key = match range.start_bound() {
+            Included(k) => Bound::Non(k.as_ref().to_vec()),
+            Excluded(k) => Bound::Inc(k.as_ref().to_vec()),
+            Unbounded => Bound::Non(vec![]),
+        };
+
+        let hi = match range.end_bound() {
+            Included(k) => Bound::Inc(k.as_ref().to_vec()),
+            Excluded(k) => Bound::Non(k.as_ref().to_vec()),
+            Unbounded => Bound::Inf,
+        };
+
+        SnapshotIter {
+            snapshot: self,
+            node: None,
+            started: false,
+            last_key: last_key,
+            hi: hi,
+        }
+    }
+
+    fn node(&self, pid: PageID) -> Result<Node> {
+        pin(|scope| {
+            let node = self.pages
+                .get_at(pid, self.lsn, scope)?
+                .and_then(|frag| frag.into_base())
+                .map(|(node, _is_root)| node)
+                .expect("snapshot lost a page it points to");
+            Ok(node)
+        })
+    }
+
+    // blobs are freed when their key is overwritten, but the log
+    // keeps them readable as of our lsn.
+    fn load(&self, stored: &StoredValue) -> Result<Value> {
+        let pids = match *stored {
+            StoredValue::Inline(ref value) => return Ok(value.clone()),
+            StoredValue::Blob(ref pids) => pids,
+        };
+        let mut value = vec![];
+        pin(|scope| -> Result<()> {
+            for &pid in pids {
+                match self.pages.get_at(pid, self.lsn, scope)? {
+                    Some(Frag::Blob(chunk)) => value.extend_from_slice(&*chunk),
+                    _ => panic!("snapshot lost a blob it points to"),
+                }
+            }
+            Ok(())
+        })?;
+        Ok(value)
+    }
+
+    // the tree can't change under us, so unlike Tree::path_for_key
+    // there are no splits or merges to help along. a leaf that was
+    // merged away is empty, and its next pointer is still intact.
+    fn leaf_for_key(&self, key: &[u8]) -> Result<Node> {
+        let key_bound = Bound::Inc(key.into());
+        let mut cursor = self.root;
+        loop {
+            let node = self.node(cursor)?;
+
+            if self.order.le(&node.hi, &key_bound) {
+                // the split of this node was not yet recorded in
+                // its parent when the snapshot was taken.
+                cursor = node.next.unwrap();
+                continue;
+            }
+
+            match node.data {
+                Data::Index(ref ptrs) => {
+                    for &(ref sep_k, ref ptr) in ptrs {
+                        let sep_cmp = self.order.cmp_keys(sep_k, key);
+                        if sep_cmp != Ordering::Greater {
+                            cursor = *ptr;
+                        } else {
+                            break;
+                        }
+                    }
+                }
+                Data::Leaf(_) => return Ok(node),
+            }
+        }
+    }
+}
+
+/// An iterator over keys and values in a `Snapshot`, which yields
+/// an error in place of an item if a page can't be read.
+pub struct SnapshotIter<'a> {
+    snapshot: &'a Snapshot,
+    // the leaf we are currently reading, or None once we are done.
+    node: Option<Node>,
+    // whether we have found the leaf holding the start of our range.
+    started: bool,
+    last_key: Bound,
+    hi: Bound,
+}
+
+impl<'a> Iterator for SnapshotIter<'a> {
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = clock();
+        let order = self.snapshot.order;
+        if !self.started {
+            let key = self.last_key.inner().unwrap();
+            match self.snapshot.leaf_for_key(&*key) {
+                Ok(node) => self.node = Some(node),
+                Err(e) => return Some(Err(e)),
+            }
+            self.started = true;
+        }
+        loop {
+            let next = match self.node {
+                Some(ref node) => {
+                    for &(ref k, ref v, expiry) in
+                        node.data.leaf_ref().unwrap()
+                    {
+                        let k_bound = Bound::Inc(k.clone());
+                        if order.le(&k_bound, &self.last_key) ||
+                            is_expired(expiry, self.snapshot.now)
+                        {
+                            continue;
+                        }
+                        if order.lt(&self.hi, &k_bound) {
+                            break;
+                        }
+                        let v = match self.snapshot.load(v) {
+                            Ok(v) => v,
+                            Err(e) => return Some(Err(e)),
+                        };
+                        self.last_key = k_bound;
+                        M.tree_scan.measure(clock() - start);
+                        return Some(Ok((k.clone(), v)));
+                    }
+                    if order.le(&self.hi, &node.hi) {
+                        None
+                    } else {
+                        node.next.map(|next| (next, node.hi.clone()))
+                    }
+                }
+                None => None,
+            };
+
+            match next {
+                Some((next, hi)) => {
+                    let node = match self.snapshot.node(next) {
+                        Ok(node) => node,
+                        Err(e) => return Some(Err(e)),
+                    };
+                    // we've seen every key below this leaf's hi
+                    self.last_key = Bound::Non(hi.inner().unwrap());
+                    self.node = Some(node);
+                }
+                None => {
+                    self.node = None;
+                    M.tree_scan.measure(clock() - start);
+                    return None;
+                }
+            }
+        }
+    }
+}
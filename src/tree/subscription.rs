@@ -0,0 +1,122 @@
+use std::sync::mpsc::{Receiver, Sender, channel};
+
+use super::*;
+
+/// A write observed by a `Subscriber`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// The key now has this value.
+    Set(Key, Value),
+    /// The key was deleted.
+    Del(Key),
+}
+
+/// A blocking iterator over the `Event`s for keys that start with a
+/// prefix, created by `Tree::watch_prefix`. Iteration ends once the
+/// `Tree` is dropped, or for a `stable` subscriber, once writing to
+/// the log fails.
+///
+/// Events are sent after their writes have been linked into the
+/// `Tree`, so concurrent writes to one key may be delivered in a
+/// different order than they were applied.
+pub struct Subscriber {
+    rx: Receiver<(Event, Lsn)>,
+    pages: Arc<PageCache<BLinkMaterializer, Frag, Meta>>,
+    stable: bool,
+}
+
+impl Subscriber {
+    /// Only yield each `Event` once its write is stable on disk.
+    pub fn stable(mut self) -> Subscriber {
+        self.stable = true;
+        self
+    }
+}
+
+impl Iterator for Subscriber {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        match self.rx.recv() {
+            Ok((event, lsn)) => {
+                if self.stable {
+                    if let Err(e) = self.pages.make_stable(lsn) {
+                        error!("failed to make event stable: {}", e);
+                        return None;
+                    }
+                }
+                Some(event)
+            }
+            Err(_) => None,
+        }
+    }
+}
+
+#[derive(Default)]
+pub(super) struct Subscriptions {
+    // lets writers skip the lock when nobody is watching.
+    count: AtomicUsize,
+    next_id: AtomicUsize,
+    watchers: Mutex<HashMap<usize, (Vec<u8>, Sender<(Event, Lsn)>)>>,
+}
+
+impl Subscriptions {
+    pub(super) fn register(
+        &self,
+        prefix: Vec<u8>,
+        pages: Arc<PageCache<BLinkMaterializer, Frag, Meta>>,
+    ) -> Subscriber {
+        let (tx, rx) = channel();
+        let id = self.next_id.fetch_add(1, SeqCst);
+        let mut watchers = self.watchers.lock().unwrap();
+        watchers.insert(id, (prefix, tx));
+        self.count.store(watchers.len(), SeqCst);
+        Subscriber {
+            rx: rx,
+            pages: pages,
+            stable: false,
+        }
+    }
+
+    /// Sends the `Event` built by `event_fn`, if it could be built, to
+    /// everyone watching a prefix of `key`, forgetting watchers whose
+    /// `Subscriber` was dropped. `event_fn` may read pages, so it runs
+    /// after the lock on the watchers is released.
+    pub(super) fn notify<F>(&self, key: &[u8], lsn: Lsn, event_fn: F)
+        where F: FnOnce() -> Option<Event>
+    {
+        if self.count.load(SeqCst) == 0 {
+            return;
+        }
+
+        let senders: Vec<(usize, Sender<(Event, Lsn)>)> = self.watchers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|&(_, &(ref prefix, _))| key.starts_with(prefix))
+            .map(|(id, &(_, ref tx))| (*id, tx.clone()))
+            .collect();
+        if senders.is_empty() {
+            return;
+        }
+
+        let event = match event_fn() {
+            Some(event) => event,
+            None => return,
+        };
+        let dropped: Vec<usize> = senders
+            .into_iter()
+            .filter(|&(_, ref tx)| tx.send((event.clone(), lsn)).is_err())
+            .map(|(id, _)| id)
+            .collect();
+        if dropped.is_empty() {
+            return;
+        }
+
+        let mut watchers = self.watchers.lock().unwrap();
+        for id in dropped {
+            watchers.remove(&id);
+        }
+        self.count.store(watchers.len(), SeqCst);
+    }
+}
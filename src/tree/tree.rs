@@ -1,10 +1,18 @@
+use std::iter::Rev;
+use std::ops::RangeBounds;
+use std::thread;
+use std::time::Duration;
+
 use super::*;
 
 use coco::epoch::{Scope, pin};
 
+// the number of expired keys a pass of the reaper collects before
+// deleting them.
+const REAP_BATCH: usize = 1024;
 
 impl<'a> IntoIterator for &'a Tree {
-    type Item = (Vec<u8>, Vec<u8>);
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
     type IntoIter = Iter<'a>;
 
     fn into_iter(self) -> Iter<'a> {
@@ -14,82 +22,181 @@ impl<'a> IntoIterator for &'a Tree {
 
 /// A flash-sympathetic persistent lock-free B+ tree
 pub struct Tree {
-    pages: PageCache<BLinkMaterializer, Frag, PageID>,
-    config: Config,
-    root: AtomicUsize,
+    pub(super) pages: Arc<PageCache<BLinkMaterializer, Frag, Meta>>,
+    pub(super) config: Config,
+    name: Vec<u8>,
+    pub(super) order: KeyOrder,
+    // the fields below are shared with the handle used by the reaper.
+    root: Arc<AtomicUsize>,
+    // the children whose merge a thread has intended and not yet
+    // marked. any other thread may finish a marked merge.
+    merging: Arc<Mutex<HashSet<PageID>>>,
+    // the batches being written. a leaf claimed by one of them only
+    // takes that batch's writes until it is dropped.
+    pub(super) committing: Arc<Mutex<HashSet<Lsn>>>,
+    subscriptions: Arc<Subscriptions>,
+    // the number of records, kept up to date by every write. it is
+    // recorded in the Meta every snapshot_after_ops writes that change
+    // it, and when the tree is dropped.
+    approx_len: Arc<AtomicIsize>,
+    approx_len_writes: Arc<AtomicUsize>,
+    // whether the Meta records that merge was called on this tree.
+    merged: Arc<AtomicBool>,
+    // whether the Meta records that set_with_ttl was called on this tree.
+    expiring: Arc<AtomicBool>,
+    // deletes expired keys every reap_every_ms, unless read-only. it is
+    // only started once the tree may hold keys that expire.
+    reaper: Mutex<Option<Reaper>>,
 }
 
 unsafe impl Send for Tree {}
 unsafe impl Sync for Tree {}
 
+impl Drop for Tree {
+    fn drop(&mut self) {
+        if let Some(reaper) = self.reaper.lock().unwrap().take() {
+            reaper.stop();
+        }
+        if let Err(e) = self.record_len() {
+            error!("failed to record the length of the tree: {}", e);
+        }
+    }
+}
+
 impl Tree {
     /// Load existing or create a new `Tree`.
-    pub fn new(config: Config) -> Tree {
-        let mut pages = PageCache::new(
-            BLinkMaterializer {
-                roots: Mutex::new(vec![]),
-            },
-            config.clone(),
-        );
+    pub fn new(config: Config) -> Result<Tree> {
+        let db = Db::new(config)?;
+        Tree::open(
+            db.pages.clone(),
+            db.config.clone(),
+            meta::DEFAULT_TREE_NAME.to_vec(),
+        )
+    }
 
-        let root_opt = pages.recover();
+    /// Load the named `Tree` from the `Meta` of a `PageCache`, or create
+    /// it if it does not exist yet. Returns `Error::Unsupported` if the
+    /// `Tree` was created with a different key comparator, or if it may
+    /// hold merge operands and no merge operator is configured.
+    pub(super) fn open(
+        pages: Arc<PageCache<BLinkMaterializer, Frag, Meta>>,
+        config: Config,
+        name: Vec<u8>,
+    ) -> Result<Tree> {
+        let comparator = config.get_key_comparator();
+        let comparator_name = comparator.map(|(name, _cmp)| name);
+        let mut approx_len = 0;
+        let mut merged = false;
+        let mut expiring = false;
+        let root_id = pin(|scope| {
+            let (meta, _meta_cas_key) = meta::meta(&pages, scope)?;
+            if let Some(root_id) = meta.root(&*name) {
+                debug!("recovered root {} while starting tree", root_id);
+                // the keys were sorted by the comparator the tree was
+                // created with, and would be out of order under another
+                if meta.comparator(&*name) != comparator_name {
+                    return Err(Error::Unsupported(format!(
+                        "tree was created with key comparator {:?}, \
+                         but opened with {:?}",
+                        meta.comparator(&*name),
+                        comparator_name
+                    )));
+                }
+                // merge operands are combined as leaves are read
+                merged = meta.merged(&*name);
+                if merged && config.get_merge_operator().is_none() {
+                    return Err(Error::Unsupported(
+                        "tree holds values written by Tree::merge, but \
+                         was opened without a merge operator"
+                            .to_owned(),
+                    ));
+                }
+                approx_len = meta.approx_len(&*name) as isize;
+                expiring = meta.expiring(&*name);
+                return Ok(root_id);
+            }
 
-        let root_id = if let Some(root_id) = root_opt {
-            debug!("recovered root {} while starting tree", root_id);
-            root_id
-        } else {
-            pin(|scope| {
-                let (root_id, root_cas_key) = pages.allocate(scope);
-                debug!("allocated pid {} for root of new tree", root_id);
-
-                let (leaf_id, leaf_cas_key) = pages.allocate(scope);
-                trace!("allocated pid {} for leaf in new", leaf_id);
-
-                let leaf = Frag::Base(
-                    Node {
-                        id: leaf_id,
-                        data: Data::Leaf(vec![]),
-                        next: None,
-                        lo: Bound::Inc(vec![]),
-                        hi: Bound::Inf,
-                    },
-                    false,
-                );
+            let (root_id, root_cas_key) = pages.allocate(scope)?;
+            debug!("allocated pid {} for root of new tree", root_id);
+
+            let (leaf_id, leaf_cas_key) = pages.allocate(scope)?;
+            trace!("allocated pid {} for leaf in new", leaf_id);
+
+            let leaf = Frag::Base(
+                Node {
+                    id: leaf_id,
+                    data: Data::Leaf(vec![]),
+                    next: None,
+                    lo: Bound::Inc(vec![]),
+                    hi: Bound::Inf,
+                    merging_child: None,
+                    merged: false,
+                    claim: None,
+                },
+                false,
+            );
 
-                let mut root_index_vec = vec![];
-                root_index_vec.push((vec![], leaf_id));
-
-                let root = Frag::Base(
-                    Node {
-                        id: root_id,
-                        data: Data::Index(root_index_vec),
-                        next: None,
-                        lo: Bound::Inc(vec![]),
-                        hi: Bound::Inf,
-                    },
-                    true,
-                );
+            let mut root_index_vec = vec![];
+            root_index_vec.push((vec![], leaf_id));
+
+            let root = Frag::Base(
+                Node {
+                    id: root_id,
+                    data: Data::Index(root_index_vec),
+                    next: None,
+                    lo: Bound::Inc(vec![]),
+                    hi: Bound::Inf,
+                    merging_child: None,
+                    merged: false,
+                    claim: None,
+                },
+                true,
+            );
 
-                pages.replace(root_id, root_cas_key, root, scope).unwrap();
-                pages.replace(leaf_id, leaf_cas_key, leaf, scope).unwrap();
-                root_id
-            })
-        };
+            pages.replace(root_id, root_cas_key, root, scope)?.unwrap();
+            pages.replace(leaf_id, leaf_cas_key, leaf, scope)?.unwrap();
+
+            // the new pages are unreachable until the Meta points to them
+            meta::update(
+                &pages,
+                |meta| {
+                    meta.set_root(name.clone(), root_id);
+                    meta.set_comparator(name.clone(), comparator_name);
+                },
+                scope,
+            )?;
+            Ok(root_id)
+        })?;
 
-        Tree {
+        let tree = Tree {
             pages: pages,
             config: config,
-            root: AtomicUsize::new(root_id),
+            name: name,
+            order: KeyOrder::new(comparator.map(|(_name, cmp)| cmp)),
+            root: Arc::new(AtomicUsize::new(root_id)),
+            merging: Arc::new(Mutex::new(HashSet::new())),
+            committing: Arc::new(Mutex::new(HashSet::new())),
+            subscriptions: Arc::new(Subscriptions::default()),
+            approx_len: Arc::new(AtomicIsize::new(approx_len)),
+            approx_len_writes: Arc::new(AtomicUsize::new(0)),
+            merged: Arc::new(AtomicBool::new(merged)),
+            expiring: Arc::new(AtomicBool::new(expiring)),
+            reaper: Mutex::new(None),
+        };
+        if expiring {
+            // keys may have expired while we were closed
+            tree.start_reaper();
         }
+        Ok(tree)
     }
 
     /// Retrieve a value from the `Tree` if it exists.
-    pub fn get(&self, key: &[u8]) -> Option<Value> {
+    pub fn get(&self, key: &[u8]) -> Result<Option<Value>> {
         let start = clock();
         pin(|scope| {
-            let (_, ret) = self.get_internal(key, scope);
+            let (_, ret) = self.get_internal(key, scope)?;
             M.tree_get.measure(clock() - start);
-            ret
+            Ok(ret)
         })
     }
 
@@ -97,250 +204,1768 @@ impl Tree {
     /// or deletion. If old is None, this will only set the value if it doesn't
     /// exist yet. If new is None, will delete the value if old is correct.
     /// If both old and new are Some, will modify the value if old is correct.
-    /// If Tree is read-only, will do nothing.
+    /// If old is not correct, the current value is returned in the inner
+    /// `Err`. If Tree is read-only, returns `Error::ReadOnly`.
     ///
     /// # Examples
     ///
     /// ```
-    /// use sled::Config;
-    /// let t = Config::default().tree();
+    /// use sled::{Config, Error};
+    /// let config = Config::default();
+    /// let t = config.tree().unwrap();
     ///
     /// // unique creation
-    /// assert_eq!(t.cas(vec![1], None, Some(vec![1])), Ok(()));
-    /// assert_eq!(t.cas(vec![1], None, Some(vec![1])), Err(Some(vec![1])));
+    /// assert_eq!(t.cas(vec![1], None, Some(vec![1])), Ok(Ok(())));
+    /// assert_eq!(t.cas(vec![1], None, Some(vec![1])), Ok(Err(Some(vec![1]))));
     ///
     /// // conditional modification
-    /// assert_eq!(t.cas(vec![1], Some(vec![1]), Some(vec![2])), Ok(()));
-    /// assert_eq!(t.cas(vec![1], Some(vec![1]), Some(vec![2])), Err(Some(vec![2])));
+    /// assert_eq!(t.cas(vec![1], Some(vec![1]), Some(vec![2])), Ok(Ok(())));
+    /// assert_eq!(
+    ///     t.cas(vec![1], Some(vec![1]), Some(vec![2])),
+    ///     Ok(Err(Some(vec![2])))
+    /// );
     ///
     /// // conditional deletion
-    /// assert_eq!(t.cas(vec![1], Some(vec![2]), None), Ok(()));
-    /// assert_eq!(t.get(&*vec![1]), None);
+    /// assert_eq!(t.cas(vec![1], Some(vec![2]), None), Ok(Ok(())));
+    /// assert_eq!(t.get(&*vec![1]), Ok(None));
     ///
     /// // read-only tree
-    /// let t = Config::default().read_only(true).tree();
-    /// assert_eq!(t.cas(vec![10], Some(vec![2]), None), Err(None));
+    /// drop(t);
+    /// let t = config.read_only(true).tree().unwrap();
+    /// assert_eq!(t.cas(vec![10], Some(vec![2]), None), Err(Error::ReadOnly));
     /// ```
     pub fn cas(
         &self,
         key: Key,
         old: Option<Value>,
         new: Option<Value>,
-    ) -> Result<(), Option<Value>> {
+    ) -> Result<std::result::Result<(), Option<Value>>> {
         if self.config.get_read_only() {
-            return Err(None);
+            return Err(Error::ReadOnly);
         }
         let start = clock();
         // we need to retry caps until old != cur, since just because
         // cap fails it doesn't mean our value was changed.
-        let frag = new.map(|n| Frag::Set(key.clone(), n)).unwrap_or_else(|| {
-            Frag::Del(key.clone())
-        });
-        pin(|scope| loop {
-            let (mut path, cur) = self.get_internal(&*key, scope);
-            if old != cur {
-                M.tree_cas.measure(clock() - start);
-                return Err(cur);
-            }
+        let new_is_some = new.is_some();
+        pin(|scope| {
+            // large values are only written out once old matches.
+            let mut frag = None;
+            loop {
+                let (mut path, cur) = self.get_internal(&*key, scope)?;
+                if old != cur {
+                    if let Some(Frag::Set(_, ref stored)) = frag {
+                        blob::free(&self.pages, stored)?;
+                    }
+                    M.tree_cas.measure(clock() - start);
+                    return Ok(Err(cur));
+                }
 
-            let &mut (ref node, ref cas_key) = path.last_mut().unwrap();
-            if self.pages
-                .link(node.id, cas_key.clone(), frag.clone(), scope)
-                .is_ok()
-            {
-                M.tree_cas.measure(clock() - start);
-                return Ok(());
+                if frag.is_none() {
+                    frag = Some(match new {
+                        Some(ref n) => {
+                            let stored = blob::store(
+                                &self.pages,
+                                &self.config,
+                                n.clone(),
+                                scope,
+                            )?;
+                            Frag::Set(key.clone(), stored)
+                        }
+                        None => Frag::Del(key.clone()),
+                    });
+                }
+
+                if self.wait_for_claim(&path.last().unwrap().0, None) {
+                    M.tree_looped();
+                    continue;
+                }
+                let &mut (ref node, ref cas_key) = path.last_mut().unwrap();
+                let link = self.pages.link(
+                    node.id,
+                    cas_key.clone(),
+                    frag.clone().unwrap(),
+                    scope,
+                )?;
+                if let Ok(new_cas_key) = link {
+                    if let Some(&(_, ref replaced, _)) = self.record(node, &key)
+                    {
+                        blob::free(&self.pages, replaced)?;
+                    }
+                    let delta = new_is_some as isize - cur.is_some() as isize;
+                    self.adjust_len(delta);
+                    let lsn = self.pages.lsn_of(new_cas_key);
+                    self.subscriptions.notify(&*key, lsn, || match new {
+                        Some(ref v) => Some(Event::Set(key.clone(), v.clone())),
+                        None => Some(Event::Del(key.clone())),
+                    });
+                    M.tree_cas.measure(clock() - start);
+                    return Ok(Ok(()));
+                }
+                M.tree_looped();
             }
-            M.tree_looped();
         })
     }
 
-    /// Set a key to a new value.
-    pub fn set(&self, key: Key, value: Value) {
+    /// Atomically update the value of a key using a closure, returning
+    /// the new value. The closure receives the current value, and
+    /// returns the value to store, or `None` to delete the key. It may
+    /// be called several times if other threads write to the key
+    /// concurrently. If the `Tree` is read-only, returns
+    /// `Error::ReadOnly`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::Config;
+    /// let t = Config::default().tree().unwrap();
+    ///
+    /// fn increment(old: Option<&[u8]>) -> Option<Vec<u8>> {
+    ///     let number = match old {
+    ///         Some(bytes) => bytes[0] + 1,
+    ///         None => 0,
+    ///     };
+    ///     Some(vec![number])
+    /// }
+    ///
+    /// let counter = b"counter".to_vec();
+    /// assert_eq!(t.update_and_fetch(counter.clone(), increment), Ok(Some(vec![0])));
+    /// assert_eq!(t.update_and_fetch(counter.clone(), increment), Ok(Some(vec![1])));
+    /// assert_eq!(t.update_and_fetch(counter.clone(), |_| None), Ok(None));
+    /// assert_eq!(t.get(b"counter"), Ok(None));
+    /// ```
+    pub fn update_and_fetch<F>(
+        &self,
+        key: Key,
+        mut f: F,
+    ) -> Result<Option<Value>>
+        where F: FnMut(Option<&[u8]>) -> Option<Value>
+    {
         if self.config.get_read_only() {
-            return;
+            return Err(Error::ReadOnly);
+        }
+        let mut current = self.get(&*key)?;
+        loop {
+            let next = f(current.as_ref().map(|v| &**v));
+            match self.cas(key.clone(), current, next.clone())? {
+                Ok(()) => return Ok(next),
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    /// Atomically update the value of a key using a closure, returning
+    /// the previous value. Behaves like `update_and_fetch` otherwise.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::Config;
+    /// let t = Config::default().tree().unwrap();
+    ///
+    /// fn increment(old: Option<&[u8]>) -> Option<Vec<u8>> {
+    ///     let number = match old {
+    ///         Some(bytes) => bytes[0] + 1,
+    ///         None => 0,
+    ///     };
+    ///     Some(vec![number])
+    /// }
+    ///
+    /// let counter = b"counter".to_vec();
+    /// assert_eq!(t.fetch_and_update(counter.clone(), increment), Ok(None));
+    /// assert_eq!(t.fetch_and_update(counter.clone(), increment), Ok(Some(vec![0])));
+    /// assert_eq!(t.get(b"counter"), Ok(Some(vec![1])));
+    /// ```
+    pub fn fetch_and_update<F>(
+        &self,
+        key: Key,
+        mut f: F,
+    ) -> Result<Option<Value>>
+        where F: FnMut(Option<&[u8]>) -> Option<Value>
+    {
+        if self.config.get_read_only() {
+            return Err(Error::ReadOnly);
+        }
+        let mut current = self.get(&*key)?;
+        loop {
+            let next = f(current.as_ref().map(|v| &**v));
+            match self.cas(key.clone(), current.clone(), next)? {
+                Ok(()) => return Ok(current),
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    /// Set a key to a new value. If the `Tree` is read-only, returns
+    /// `Error::ReadOnly`.
+    pub fn set(&self, key: Key, value: Value) -> Result<()> {
+        if self.config.get_read_only() {
+            return Err(Error::ReadOnly);
         }
+        self.set_inner(key, value, None)
+    }
+
+    pub(super) fn set_inner(
+        &self,
+        key: Key,
+        value: Value,
+        batch: Option<&OpenBatch>,
+    ) -> Result<()> {
         let start = clock();
         // println!("starting set of {:?} -> {:?}", key, value);
-        let frag = Frag::Set(key.clone(), value);
+        let frag = pin(|scope| {
+            blob::store(&self.pages, &self.config, value, scope)
+                .map(|stored| Frag::Set(key.clone(), stored))
+        })?;
+        self.link_to_leaf(&*key, frag, batch)?;
+        M.tree_set.measure(clock() - start);
+        // println!("done set of {:?}", key);
+        Ok(())
+    }
+
+    /// Set a key to a new value that expires after the given duration.
+    /// Expired keys are hidden from reads, and deleted by a background
+    /// thread every `reap_every_ms`. The first call starts the thread,
+    /// and it starts again whenever the `Tree` is reopened. A later
+    /// `set` of the key removes its expiry, while a `merge` into it
+    /// keeps the expiry unchanged. A duration too long to represent
+    /// never expires. If the `Tree` is read-only, returns
+    /// `Error::ReadOnly`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::thread;
+    /// use std::time::Duration;
+    ///
+    /// let t = sled::Config::default().tree().unwrap();
+    /// let ttl = Duration::from_millis(10);
+    /// t.set_with_ttl(b"session".to_vec(), vec![1], ttl).unwrap();
+    /// assert_eq!(t.get(b"session"), Ok(Some(vec![1])));
+    ///
+    /// thread::sleep(Duration::from_millis(20));
+    /// assert_eq!(t.get(b"session"), Ok(None));
+    /// ```
+    pub fn set_with_ttl(
+        &self,
+        key: Key,
+        value: Value,
+        ttl: Duration,
+    ) -> Result<()> {
+        if self.config.get_read_only() {
+            return Err(Error::ReadOnly);
+        }
+        let start = clock();
+        // saturating at u64::MAX, which now_ms never reaches
+        let ttl_ms = ttl.as_secs()
+            .saturating_mul(1000)
+            .saturating_add(u64::from(ttl.subsec_nanos() / 1_000_000));
+        let expiry = now_ms().saturating_add(ttl_ms);
+        if !self.expiring.load(SeqCst) {
+            // the Meta is logged before the key, so a recovered tree
+            // with keys that expire always starts the reaper.
+            pin(|scope| {
+                meta::update(
+                    &self.pages,
+                    |meta| meta.set_expiring(self.name.clone()),
+                    scope,
+                )
+            })?;
+            self.expiring.store(true, SeqCst);
+            self.start_reaper();
+        }
+        let frag = pin(|scope| {
+            blob::store(&self.pages, &self.config, value, scope)
+                .map(|stored| Frag::SetWithTtl(key.clone(), stored, expiry))
+        })?;
+        self.link_to_leaf(&*key, frag, None)?;
+        M.tree_set.measure(clock() - start);
+        Ok(())
+    }
+
+    /// Merge an operand into the value of a key, using the
+    /// `MergeOperator` set on the `Config`. This does not need to read
+    /// the current value first, so concurrent merges into the same key
+    /// never contend the way `cas` loops do. Once merged into, the
+    /// `Tree` can only be reopened with a merge operator. If the `Tree`
+    /// is read-only, returns `Error::ReadOnly`, and if no merge operator
+    /// was configured, returns `Error::Unsupported`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::Config;
+    ///
+    /// fn concatenate_merge(
+    ///   _key: &[u8],               // the key being merged
+    ///   old_value: Option<&[u8]>,  // the previous value, if one existed
+    ///   merged_bytes: &[u8]        // the new bytes being merged in
+    /// ) -> Option<Vec<u8>> {       // set the new value, return None to delete
+    ///   let mut ret = old_value
+    ///     .map(|ov| ov.to_vec())
+    ///     .unwrap_or_else(|| vec![]);
+    ///
+    ///   ret.extend_from_slice(merged_bytes);
+    ///
+    ///   Some(ret)
+    /// }
+    ///
+    /// let config = Config::default().merge_operator(Some(concatenate_merge));
+    /// let tree = config.tree().unwrap();
+    ///
+    /// let k = b"k1".to_vec();
+    ///
+    /// tree.set(k.clone(), vec![0]).unwrap();
+    /// tree.merge(k.clone(), vec![1]).unwrap();
+    /// tree.merge(k.clone(), vec![2]).unwrap();
+    /// assert_eq!(tree.get(&k), Ok(Some(vec![0, 1, 2])));
+    ///
+    /// // sets replace previously merged data,
+    /// // bypassing the merge function.
+    /// tree.set(k.clone(), vec![3]).unwrap();
+    /// assert_eq!(tree.get(&k), Ok(Some(vec![3])));
+    ///
+    /// // merges on non-present values will add them
+    /// tree.del(&k).unwrap();
+    /// tree.merge(k.clone(), vec![4]).unwrap();
+    /// assert_eq!(tree.get(&k), Ok(Some(vec![4])));
+    /// ```
+    pub fn merge(&self, key: Key, operand: Value) -> Result<()> {
+        if self.config.get_read_only() {
+            return Err(Error::ReadOnly);
+        }
+        if self.config.get_merge_operator().is_none() {
+            return Err(Error::Unsupported(
+                "must set a merge operator on the Config to use Tree::merge"
+                    .to_owned(),
+            ));
+        }
+        if !self.merged.load(SeqCst) {
+            // the Meta is logged before the operand, so a recovered
+            // operand is never opened without an operator.
+            pin(|scope| {
+                meta::update(
+                    &self.pages,
+                    |meta| meta.set_merged(self.name.clone()),
+                    scope,
+                )
+            })?;
+            self.merged.store(true, SeqCst);
+        }
+        let start = clock();
+        let frag = Frag::Merge(key.clone(), operand);
+        self.link_to_leaf(&*key, frag, None)?;
+        M.tree_merge.measure(clock() - start);
+        Ok(())
+    }
+
+    // links a Set or Merge to the leaf responsible for the key,
+    // splitting the leaf if it has grown too large.
+    fn link_to_leaf(
+        &self,
+        key: &[u8],
+        frag: Frag,
+        batch: Option<&OpenBatch>,
+    ) -> Result<()> {
         pin(|scope| {
             loop {
-                let mut path = self.path_for_key(&*key, scope);
+                let mut path = self.path_for_key(key, scope)?;
                 let (mut last_node, last_cas_key) = path.pop().unwrap();
+                if self.wait_for_claim(&last_node, batch) {
+                    M.tree_looped();
+                    continue;
+                }
+                let attempt = match self.merge_to_set(
+                    &last_node,
+                    &frag,
+                    scope,
+                )? {
+                    Some(attempt) => attempt,
+                    None => {
+                        M.tree_looped();
+                        continue;
+                    }
+                };
                 // println!("last before: {:?}", last);
-                if let Ok(new_cas_key) = self.pages.link(
+                if let Ok(new_cas_key) = self.link_in(
                     last_node.id,
                     last_cas_key,
-                    frag.clone(),
+                    attempt.clone(),
+                    batch,
                     scope,
-                )
+                )?
                 {
-                    last_node.apply(&frag);
+                    let new = match attempt {
+                        Frag::Set(_, ref stored) |
+                        Frag::SetWithTtl(_, ref stored, _) => Some(stored),
+                        _ => None,
+                    };
+                    let old = self.record(&last_node, key);
+                    self.replaced(key, old, new, batch)?;
+                    let len_before = last_node.data.len() as isize;
+                    last_node.apply(
+                        &attempt,
+                        self.config.get_merge_operator(),
+                        self.order,
+                    );
+                    let len_after = last_node.data.len() as isize;
+                    self.adjust_len(len_after - len_before);
+                    let lsn = self.pages.lsn_of(new_cas_key.clone());
+                    self.subscriptions.notify(key, lsn, || {
+                        // report the value the frag left behind, which
+                        // for a merge is only known after applying it.
+                        let stored = match self.record(&last_node, key) {
+                            Some(&(_, ref stored, _)) => stored,
+                            None => return Some(Event::Del(key.to_vec())),
+                        };
+                        // if a later write freed our blob, we report
+                        // the value it left behind instead.
+                        let value = match blob::load(&self.pages, stored, scope)
+                        {
+                            Ok(None) => {
+                                self.get_internal(key, scope).map(|got| got.1)
+                            }
+                            loaded => loaded,
+                        };
+                        match value {
+                            Ok(Some(value)) => {
+                                Some(Event::Set(key.to_vec(), value))
+                            }
+                            Ok(None) => Some(Event::Del(key.to_vec())),
+                            Err(e) => {
+                                error!("failed to read merged value: {}", e);
+                                None
+                            }
+                        }
+                    });
                     // println!("last after: {:?}", last);
-                    let should_split = last_node.should_split(self.fanout());
+                    // a leaf that an open batch has written to keeps
+                    // its shape until the batch closes.
+                    let settled = !self.pages.in_open_batch(last_node.id);
+                    let should_split =
+                        last_node.should_split(self.fanout()) && settled;
+                    let is_empty = last_node.data.len() == 0 && settled;
                     path.push((last_node.clone(), new_cas_key));
                     // success
                     if should_split {
                         // println!("need to split {:?}", last_node.id);
-                        self.recursive_split(&path, scope);
+                        self.recursive_split(&path, scope)?;
+                    } else if is_empty {
+                        // a merge operator deleted the last key
+                        self.merge_empty_leaf(last_node.id, scope)?;
+                    }
+                    return Ok(());
+                }
+                // a merged value we stored was never linked
+                match (&frag, &attempt) {
+                    (&Frag::Merge(..), &Frag::Set(_, ref stored)) |
+                    (&Frag::Merge(..), &Frag::SetWithTtl(_, ref stored, _)) => {
+                        blob::free(&self.pages, stored)?
                     }
-                    M.tree_set.measure(clock() - start);
-                    return;
+                    _ => {}
                 }
                 M.tree_looped();
             }
         })
-        // println!("done set of {:?}", key);
     }
 
-    /// Delete a value, returning the last result if it existed.
+    // merges that would leave a value above the blob threshold are
+    // applied here and linked as a Set instead, since leaves only
+    // merge into inline values. returns None if the current value is
+    // a blob that was freed after the leaf was read.
+    fn merge_to_set<'s>(
+        &self,
+        leaf: &Node,
+        frag: &Frag,
+        scope: &'s Scope,
+    ) -> Result<Option<Frag>> {
+        let (key, operand) = match *frag {
+            Frag::Merge(ref key, ref operand) => (key, operand),
+            _ => return Ok(Some(frag.clone())),
+        };
+        let (old, expiry) = match self.record(leaf, key) {
+            Some(&(_, ref old, expiry)) => (old, expiry),
+            None => return Ok(Some(frag.clone())),
+        };
+        let is_large = match *old {
+            StoredValue::Inline(ref v) => {
+                v.len() + operand.len() > self.config.get_blob_threshold()
+            }
+            StoredValue::Blob(_) => true,
+        };
+        if !is_large {
+            return Ok(Some(frag.clone()));
+        }
+
+        let old = match blob::load(&self.pages, old, scope)? {
+            Some(old) => old,
+            None => return Ok(None),
+        };
+        let merge_fn = self.config.get_merge_operator().unwrap();
+        let new = match merge_fn(key, Some(&*old), operand) {
+            Some(new) => new,
+            None => return Ok(Some(Frag::Del(key.clone()))),
+        };
+        let stored = blob::store(&self.pages, &self.config, new, scope)?;
+        // merging into a key does not change when it expires
+        Ok(Some(match expiry {
+            Some(expiry) => Frag::SetWithTtl(key.clone(), stored, expiry),
+            None => Frag::Set(key.clone(), stored),
+        }))
+    }
+
+    /// Delete a value, returning the last result if it existed. If the
+    /// `Tree` is read-only, returns `Error::ReadOnly`.
     ///
     /// # Examples
     ///
     /// ```
     /// use sled::Config;
-    /// let t = Config::default().tree();
-    /// t.set(vec![1], vec![1]);
-    /// assert_eq!(t.del(&*vec![1]), Some(vec![1]));
-    /// assert_eq!(t.del(&*vec![1]), None);
+    /// let t = Config::default().tree().unwrap();
+    /// t.set(vec![1], vec![1]).unwrap();
+    /// assert_eq!(t.del(&*vec![1]), Ok(Some(vec![1])));
+    /// assert_eq!(t.del(&*vec![1]), Ok(None));
     /// ```
-    pub fn del(&self, key: &[u8]) -> Option<Value> {
+    pub fn del(&self, key: &[u8]) -> Result<Option<Value>> {
         if self.config.get_read_only() {
-            return None;
+            return Err(Error::ReadOnly);
         }
-        let start = clock();
-        pin(|scope| {
-            let mut ret: Option<Value>;
-            loop {
-                let mut path = self.path_for_key(&*key, scope);
-                let (leaf_node, leaf_cas_key) = path.pop().unwrap();
-                match leaf_node.data {
-                    Data::Leaf(ref items) => {
-                        let search = items.binary_search_by(
-                            |&(ref k, ref _v)| (**k).cmp(key),
-                        );
-                        if let Ok(idx) = search {
-                            ret = Some(items[idx].1.clone());
-                        } else {
-                            ret = None;
-                            break;
-                        }
-                    }
-                    _ => panic!("last node in path is not leaf"),
-                }
-
-                let frag = Frag::Del(key.to_vec());
-                if self.pages
-                    .link(leaf_node.id, leaf_cas_key, frag, scope)
-                    .is_ok()
-                {
-                    // success
-                    break;
-                } else {
-                    // failure, retry
-                }
-                M.tree_looped();
-            }
-            M.tree_del.measure(clock() - start);
-            ret
-        })
+        self.del_inner(key, None)
     }
 
-    /// Iterate over tuples of keys and values, starting at the provided key.
+    /// Delete every key in the `Tree`. See `del_range`.
     ///
     /// # Examples
     ///
     /// ```
     /// use sled::Config;
-    /// let t = Config::default().tree();
-    /// t.set(vec![1], vec![10]);
-    /// t.set(vec![2], vec![20]);
-    /// t.set(vec![3], vec![30]);
-    /// let mut iter = t.scan(&*vec![2]);
-    /// assert_eq!(iter.next(), Some((vec![2], vec![20])));
-    /// assert_eq!(iter.next(), Some((vec![3], vec![30])));
-    /// assert_eq!(iter.next(), None);
+    /// let t = Config::default().tree().unwrap();
+    /// t.set(vec![1], vec![10]).unwrap();
+    /// t.set(vec![2], vec![20]).unwrap();
+    /// t.clear().unwrap();
+    /// assert!(t.iter().next().is_none());
     /// ```
-    pub fn scan(&self, key: &[u8]) -> Iter {
-        pin(|scope| {
-            let (path, _) = self.get_internal(key, scope);
-            let &(ref last_node, ref _last_cas_key) = path.last().unwrap();
-            Iter {
-                id: last_node.id,
-                inner: &self.pages,
-                last_key: Bound::Non(key.to_vec()),
-            }
-        })
+    pub fn clear(&self) -> Result<()> {
+        self.del_range::<&[u8], _>(..)
     }
 
-    /// Iterate over the tuples of keys and values in this tree.
+    /// Delete every key within the provided range. Rather than logging
+    /// a deletion per key, each leaf holding keys in the range is
+    /// rewritten once, and leaves left empty are merged away and their
+    /// pages freed. Concurrent readers may observe the range being
+    /// emptied one leaf at a time. If the `Tree` is read-only, returns
+    /// `Error::ReadOnly`.
     ///
     /// # Examples
     ///
     /// ```
     /// use sled::Config;
-    /// let t = Config::default().tree();
-    /// t.set(vec![1], vec![10]);
-    /// t.set(vec![2], vec![20]);
-    /// t.set(vec![3], vec![30]);
-    /// let mut iter = t.iter();
-    /// assert_eq!(iter.next(), Some((vec![1], vec![10])));
-    /// assert_eq!(iter.next(), Some((vec![2], vec![20])));
-    /// assert_eq!(iter.next(), Some((vec![3], vec![30])));
-    /// assert_eq!(iter.next(), None);
+    /// let t = Config::default().tree().unwrap();
+    /// for i in 0..5 {
+    ///     t.set(vec![i], vec![i]).unwrap();
+    /// }
+    /// t.del_range(vec![1]..vec![3]).unwrap();
+    /// let keys: Vec<_> = t.iter().map(|res| res.unwrap().0).collect();
+    /// assert_eq!(keys, vec![vec![0], vec![3], vec![4]]);
     /// ```
-    pub fn iter(&self) -> Iter {
-        pin(|scope| {
-            let (path, _) = self.get_internal(b"", scope);
-            let &(ref last_node, ref _last_cas_key) = path.last().unwrap();
-            Iter {
-                id: last_node.id,
-                inner: &self.pages,
-                last_key: Bound::Non(vec![]),
+    pub fn del_range<K, R>(&self, range: R) -> Result<()>
+        where K: AsRef<[u8]>,
+              R: RangeBounds<K>
+    {
+        use std::ops::Bound::{Excluded, Included, Unbounded};
+
+        if self.config.get_read_only() {
+            return Err(Error::ReadOnly);
+        }
+        let start = clock();
+
+        // the same bounds that Iter uses to decide which keys to return
+        let (mut key, lo) = match range.start_bound() {
+            Included(k) => (k.as_ref().to_vec(), Bound::Non(k.as_ref().into())),
+            Excluded(k) => (k.as_ref().to_vec(), Bound::Inc(k.as_ref().into())),
+            Unbounded => (vec![], Bound::Non(vec![])),
+        };
+        let hi = match range.end_bound() {
+            Included(k) => Bound::Inc(k.as_ref().to_vec()),
+            Excluded(k) => Bound::Non(k.as_ref().to_vec()),
+            Unbounded => Bound::Inf,
+        };
+
+        pin(|scope| loop {
+            let mut path = self.path_for_key(&*key, scope)?;
+            let (leaf, cas_key) = path.pop().unwrap();
+            if self.wait_for_claim(&leaf, None) {
+                M.tree_looped();
+                continue;
+            }
+            let items = leaf.data.leaf_ref().unwrap();
+            let (removed, kept): (Vec<_>, Vec<_>) =
+                items.iter().cloned().partition(|&(ref k, _, _)| {
+                    let k_bound = Bound::Inc(k.clone());
+                    self.order.lt(&lo, &k_bound) &&
+                        !self.order.lt(&hi, &k_bound)
+                });
+
+            if !removed.is_empty() && self.pages.in_open_batch(leaf.id) {
+                // a leaf holding updates of an open batch can't be
+                // replaced, so its keys are deleted one at a time.
+                for (k, _, _) in removed {
+                    self.del_inner(&*k, None)?;
+                }
+            } else if !removed.is_empty() {
+                let is_empty = kept.is_empty();
+                let mut node = leaf.clone();
+                node.data = Data::Leaf(kept);
+                let frag = Frag::Base(node, false);
+                match self.pages.replace(leaf.id, cas_key, frag, scope)? {
+                    Ok(new_cas_key) => {
+                        let n_removed = removed.len() as isize;
+                        self.adjust_len(-n_removed);
+                        let lsn = self.pages.lsn_of(new_cas_key);
+                        for (k, v, _expiry) in removed {
+                            blob::free(&self.pages, &v)?;
+                            self.subscriptions.notify(&*k, lsn, || {
+                                Some(Event::Del(k.clone()))
+                            });
+                        }
+                        if is_empty {
+                            self.merge_empty_leaf(leaf.id, scope)?;
+                        }
+                    }
+                    Err(_) => {
+                        // the leaf changed under us, so read it again
+                        M.tree_looped();
+                        continue;
+                    }
+                }
+            }
+
+            if self.order.le(&hi, &leaf.hi) {
+                M.tree_del.measure(clock() - start);
+                return Ok(());
             }
+            key = leaf.hi.inner().unwrap();
         })
     }
 
-    fn recursive_split<'s>(
+    pub(super) fn del_inner(
         &self,
-        path: &[(Node, HPtr<'s, Frag>)],
-        scope: &'s Scope,
-    ) {
-        // to split, we pop the path, see if it's in need of split, recurse up
-        // two-phase: (in prep for lock-free, not necessary for single threaded)
-        //  1. half-split: install split on child, P
-        //      a. allocate new right sibling page, Q
-        //      b. locate split point
-        //      c. create new consolidated pages for both sides
-        //      d. add new node to pagetable
-        //      e. merge split delta to original page P with physical pointer to Q
-        //      f. if failed, free the new page
-        //  2. parent update: install new index term on parent
-        //      a. merge "index term delta record" to parent, containing:
-        //          i. new bounds for P & Q
-        //          ii. logical pointer to Q
-        //
-        //      (it's possible parent was merged in the mean-time, so if that's the
-        //      case, we need to go up the path to the grandparent then down again
-        //      or higher until it works)
-        //  3. any traversing nodes that witness #1 but not #2 try to complete it
-        //
-        //  root is special case, where we need to hoist a new root
-
-        let mut all_page_views = path.to_vec();
-        let mut root_and_key = all_page_views.remove(0);
+        key: &[u8],
+        batch: Option<&OpenBatch>,
+    ) -> Result<Option<Value>> {
+        self.del_if(key, |_expiry| true, batch)
+    }
 
-        // println!("before:\n{:?}\n", self);
+    // deletes the key if it is present and should_del accepts its
+    // expiry time, returning the value if it had not expired.
+    fn del_if<F>(
+        &self,
+        key: &[u8],
+        should_del: F,
+        batch: Option<&OpenBatch>,
+    ) -> Result<Option<Value>>
+        where F: Fn(Option<u64>) -> bool
+    {
+        let start = clock();
+        pin(|scope| loop {
+            let mut path = self.path_for_key(&*key, scope)?;
+            let (leaf, cas_key) = path.pop().unwrap();
+            let deleted = self
+                .del_from_leaf(&leaf, cas_key, key, &should_del, batch, scope)?;
+            if let Some(ret) = deleted {
+                M.tree_del.measure(clock() - start);
+                return Ok(ret);
+            }
+            M.tree_looped();
+        })
+    }
 
-        while let Some((node, cas_key)) = all_page_views.pop() {
-            // println!("splitting node {:?}", node);
+    // links a Del of the key by a cas on the given version of its
+    // leaf, as del_if describes. returns None if the leaf has changed
+    // since, or another batch has claimed it, and must be read again.
+    fn del_from_leaf<'s, F>(
+        &self,
+        leaf: &Node,
+        cas_key: HPtr<'s, Frag>,
+        key: &[u8],
+        should_del: &F,
+        batch: Option<&OpenBatch>,
+        scope: &'s Scope,
+    ) -> Result<Option<Option<Value>>>
+        where F: Fn(Option<u64>) -> bool
+    {
+        if self.wait_for_claim(leaf, batch) {
+            return Ok(None);
+        }
+        let ret = match self.record(leaf, key) {
+            Some(&(_, _, expiry)) if !should_del(expiry) => {
+                return Ok(Some(None))
+            }
+            Some(&(_, _, expiry)) if is_expired(expiry, now_ms()) => None,
+            Some(&(_, ref v, _)) => match blob::load(&self.pages, v, scope)? {
+                Some(v) => Some(v),
+                // freed by a later write
+                None => return Ok(None),
+            },
+            None => return Ok(Some(None)),
+        };
+
+        let frag = Frag::Del(key.to_vec());
+        let new_cas_key =
+            match self.link_in(leaf.id, cas_key, frag, batch, scope)? {
+                Ok(new_cas_key) => new_cas_key,
+                Err(_) => return Ok(None),
+            };
+        let old = self.record(leaf, key);
+        self.replaced(key, old, None, batch)?;
+        self.adjust_len(-1);
+        let lsn = self.pages.lsn_of(new_cas_key);
+        self.subscriptions.notify(key, lsn, || Some(Event::Del(key.to_vec())));
+        if leaf.data.len() == 1 && batch.is_none() {
+            // we just emptied this leaf
+            self.merge_empty_leaf(leaf.id, scope)?;
+        }
+        Ok(Some(ret))
+    }
+
+    /// Apply every write in a `Batch`. The writes are recovered
+    /// all-or-nothing after a crash, but concurrent readers may observe
+    /// them being applied one key at a time. If a write fails, the
+    /// writes already applied are undone, unless another thread has
+    /// written to their keys since, and none of the batch is
+    /// recovered. Writes made by other threads in the meantime are
+    /// recovered as usual. If the `Tree` is read-only, returns
+    /// `Error::ReadOnly`.
+    pub fn apply_batch(&self, batch: Batch) -> Result<()> {
+        if self.config.get_read_only() {
+            return Err(Error::ReadOnly);
+        }
+        if batch.is_empty() {
+            return Ok(());
+        }
+        self.write_batch(batch.writes)
+    }
+
+    // links each write as part of a page cache batch, and commits it.
+    fn write_batch(&self, writes: BTreeMap<Key, Option<Value>>) -> Result<()> {
+        let batch = OpenBatch::begin(self)?;
+        self.apply_writes(batch, writes)
+    }
+
+    // a transaction commits as a batch. first it claims every leaf it
+    // read, by linking a TxClaim with a cas on the version it read, and
+    // every leaf it writes to. a claimed leaf only takes the writes of
+    // the batch that claimed it until the batch is dropped, and is not
+    // split or merged before then, so once all of them are claimed the
+    // reads still hold while the writes are applied. returns false if
+    // a leaf had changed or was claimed by another transaction first.
+    pub(super) fn commit_tx<'s>(
+        &self,
+        reads: &HashMap<PageID, HPtr<'s, Frag>>,
+        writes: BTreeMap<Key, Option<Value>>,
+        scope: &'s Scope,
+    ) -> Result<bool> {
+        // undone and aborted if dropped on the way out of a conflict
+        let batch = OpenBatch::begin(self)?;
+        let mut claimed = HashSet::new();
+
+        // in pid order, so that two transactions claiming the same
+        // leaves tend to meet at the first one.
+        let mut pids: Vec<PageID> = reads.keys().cloned().collect();
+        pids.sort();
+        for pid in pids {
+            let observed = reads[&pid].clone();
+            if !self.read_holds(pid, &observed, scope)? ||
+                !self.claim(pid, observed, &batch, scope)?
+            {
+                trace!("tx conflict on pid {}", pid);
+                return Ok(false);
+            }
+            claimed.insert(pid);
+        }
+
+        // leaves that were only written to may have changed since
+        for key in writes.keys() {
+            loop {
+                let (leaf, cas_key) =
+                    self.path_for_key(key, scope)?.pop().unwrap();
+                if claimed.contains(&leaf.id) {
+                    break;
+                }
+                if self.claimed_by_other(&leaf, Some(&batch)) {
+                    trace!("tx conflict on pid {}", leaf.id);
+                    return Ok(false);
+                }
+                if self.claim(leaf.id, cas_key, &batch, scope)? {
+                    claimed.insert(leaf.id);
+                    break;
+                }
+                M.tree_looped();
+            }
+        }
+
+        self.apply_writes(batch, writes)?;
+        Ok(true)
+    }
+
+    // returns true if a page read by a transaction is still at the
+    // version it read, and no other transaction is committing to it.
+    pub(super) fn read_holds<'s>(
+        &self,
+        pid: PageID,
+        observed: &HPtr<'s, Frag>,
+        scope: &'s Scope,
+    ) -> Result<bool> {
+        match self.pages.get(pid, scope)? {
+            Some((frag, ref current))
+                if current.as_raw() == observed.as_raw() => {
+                let leaf = frag.into_base().unwrap().0;
+                Ok(!self.claimed_by_other(&leaf, None))
+            }
+            _ => Ok(false),
+        }
+    }
+
+    // links a TxClaim for the batch to the given version of a leaf,
+    // returning false if the leaf has changed since.
+    fn claim<'s>(
+        &self,
+        pid: PageID,
+        old: HPtr<'s, Frag>,
+        batch: &OpenBatch,
+        scope: &'s Scope,
+    ) -> Result<bool> {
+        let frag = Frag::TxClaim(batch.lsn);
+        self.pages
+            .link_in_batch(pid, old, frag, batch.lsn, scope)
+            .map(|res| res.is_ok())
+    }
+
+    // returns true if a batch other than the given one claimed the
+    // leaf, and is still being written.
+    fn claimed_by_other(&self, leaf: &Node, batch: Option<&OpenBatch>) -> bool {
+        match leaf.claim {
+            Some(lsn) if batch.map(|b| b.lsn) != Some(lsn) => {
+                self.committing.lock().unwrap().contains(&lsn)
+            }
+            _ => false,
+        }
+    }
+
+    // writers that are kept out of a leaf by a claim give way to the
+    // batch that has it, and read the leaf again if this returns true.
+    fn wait_for_claim(&self, leaf: &Node, batch: Option<&OpenBatch>) -> bool {
+        let claimed = self.claimed_by_other(leaf, batch);
+        if claimed {
+            thread::yield_now();
+        }
+        claimed
+    }
+
+    fn apply_writes(
+        &self,
+        mut batch: OpenBatch,
+        writes: BTreeMap<Key, Option<Value>>,
+    ) -> Result<()> {
+        let keys: Vec<Key> = writes.keys().cloned().collect();
+        for (key, value) in writes {
+            match value {
+                Some(value) => self.set_inner(key, value, Some(&batch))?,
+                None => {
+                    self.del_inner(&*key, Some(&batch))?;
+                }
+            }
+        }
+        self.pages.commit_batch(batch.lsn)?;
+        batch.committed = true;
+
+        let replaced = batch.writes.replace(vec![]);
+        // releases the leaves the batch claimed
+        drop(batch);
+        for write in replaced {
+            if let Some((ref stored, _expiry)) = write.old {
+                blob::free(&self.pages, stored)?;
+            }
+        }
+        self.settle(&keys)
+    }
+
+    // undoes the writes of a batch that failed, unless they have been
+    // overwritten since, and aborts it. the undoing frags are part of
+    // the batch, so that neither they nor the writes are recovered,
+    // and no later compaction can persist a write that was not undone.
+    pub(super) fn abort_batch(
+        &self,
+        batch_lsn: Lsn,
+        writes: Vec<BatchWrite>,
+    ) -> Result<()> {
+        let undone = pin(|scope| -> Result<()> {
+            for write in writes.into_iter().rev() {
+                self.undo_write(batch_lsn, write, scope)?;
+            }
+            Ok(())
+        });
+        let aborted = self.pages.abort_batch(batch_lsn);
+        undone.and(aborted)
+    }
+
+    fn undo_write<'s>(
+        &self,
+        batch_lsn: Lsn,
+        write: BatchWrite,
+        scope: &'s Scope,
+    ) -> Result<()> {
+        let key = write.key;
+        loop {
+            let mut path = self.path_for_key(&*key, scope)?;
+            let (leaf, cas_key) = path.pop().unwrap();
+            let ours = match (self.record(&leaf, &*key), &write.new) {
+                (Some(&(_, ref cur, None)), &Some(ref new)) => cur == new,
+                (None, &None) => true,
+                _ => false,
+            };
+            if !ours {
+                // another thread has written to the key since
+                return Ok(());
+            }
+
+            let frag = match write.old {
+                Some((ref stored, Some(expiry))) => {
+                    Frag::SetWithTtl(key.clone(), stored.clone(), expiry)
+                }
+                Some((ref stored, None)) => {
+                    Frag::Set(key.clone(), stored.clone())
+                }
+                None => Frag::Del(key.clone()),
+            };
+            let link = self.pages
+                .link_in_batch(leaf.id, cas_key, frag, batch_lsn, scope)?;
+            if let Ok(new_cas_key) = link {
+                if let Some(ref new) = write.new {
+                    blob::free(&self.pages, new)?;
+                }
+                let delta =
+                    write.old.is_some() as isize - write.new.is_some() as isize;
+                self.adjust_len(delta);
+                let event = match write.old {
+                    Some((ref stored, _)) => {
+                        blob::load(&self.pages, stored, scope)?
+                            .map(|value| Event::Set(key.clone(), value))
+                    }
+                    None => Some(Event::Del(key.clone())),
+                };
+                let lsn = self.pages.lsn_of(new_cas_key);
+                self.subscriptions.notify(&*key, lsn, || event);
+                return Ok(());
+            }
+            M.tree_looped();
+        }
+    }
+
+    // splits and merges the leaves holding the given keys, which was
+    // put off while a batch was writing to them.
+    fn settle(&self, keys: &[Key]) -> Result<()> {
+        pin(|scope| {
+            for key in keys {
+                loop {
+                    let path = self.path_for_key(key, scope)?;
+                    let leaf = path.last().unwrap().0.clone();
+                    if self.pages.in_open_batch(leaf.id) {
+                        // left to the batch that has it
+                        break;
+                    }
+                    if leaf.should_split(self.fanout()) {
+                        self.recursive_split(&path, scope)?;
+                        continue;
+                    }
+                    if leaf.data.len() == 0 {
+                        self.merge_empty_leaf(leaf.id, scope)?;
+                    }
+                    break;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    // links a frag to a page, as part of the batch if one is given.
+    fn link_in<'s>(
+        &self,
+        pid: PageID,
+        old: HPtr<'s, Frag>,
+        frag: Frag,
+        batch: Option<&OpenBatch>,
+        scope: &'s Scope,
+    ) -> Result<CasResult<'s, Frag>> {
+        match batch {
+            Some(batch) => {
+                self.pages.link_in_batch(pid, old, frag, batch.lsn, scope)
+            }
+            None => self.pages.link(pid, old, frag, scope),
+        }
+    }
+
+    // frees the value of the record a write replaced, unless the write
+    // belongs to a batch, which remembers the record until it closes.
+    fn replaced(
+        &self,
+        key: &[u8],
+        old: Option<&(Key, StoredValue, Option<u64>)>,
+        new: Option<&StoredValue>,
+        batch: Option<&OpenBatch>,
+    ) -> Result<()> {
+        if let Some(batch) = batch {
+            batch.writes.borrow_mut().push(BatchWrite {
+                key: key.to_vec(),
+                old: old.map(|&(_, ref v, expiry)| (v.clone(), expiry)),
+                new: new.cloned(),
+            });
+            return Ok(());
+        }
+        match old {
+            Some(&(_, ref stored, _)) => blob::free(&self.pages, stored),
+            None => Ok(()),
+        }
+    }
+
+    /// Fill an empty `Tree` from an iterator of keys and values in
+    /// ascending order. Rather than splitting leaves as keys arrive,
+    /// full leaves and the index nodes above them are written once
+    /// each, and the new pages replace the empty tree when the input
+    /// is exhausted. Until then the `Tree` stays empty and writable,
+    /// but if any key was written in the meantime, the loaded pages
+    /// are discarded and `BulkLoadError::NotEmpty` is returned.
+    /// Subscribers are not notified of loaded keys. If the `Tree` is
+    /// read-only, returns `Error::ReadOnly`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::{BulkLoadError, Config};
+    /// let t = Config::default().tree().unwrap();
+    /// let items = (0..100u8).map(|i| (vec![i], vec![i]));
+    /// assert_eq!(t.bulk_load(items), Ok(Ok(())));
+    /// assert_eq!(t.get(&[42]), Ok(Some(vec![42])));
+    /// assert_eq!(t.len(), Ok(100));
+    ///
+    /// let t = Config::default().tree().unwrap();
+    /// let items = vec![(vec![2], vec![]), (vec![1], vec![])];
+    /// assert_eq!(
+    ///     t.bulk_load(items),
+    ///     Ok(Err(BulkLoadError::Unsorted(vec![1])))
+    /// );
+    /// assert_eq!(t.is_empty(), Ok(true));
+    /// ```
+    pub fn bulk_load<I>(
+        &self,
+        items: I,
+    ) -> Result<std::result::Result<(), BulkLoadError>>
+        where I: IntoIterator<Item = (Key, Value)>
+    {
+        if self.config.get_read_only() {
+            return Err(Error::ReadOnly);
+        }
+        if !self.is_empty()? {
+            return Ok(Err(BulkLoadError::NotEmpty));
+        }
+
+        let (root_id, len) =
+            match bulk::build(&self.pages, &self.config, self.order, items)? {
+                Ok(built) => built,
+                Err(e) => return Ok(Err(e)),
+            };
+
+        // claim the leaves of the empty tree, so that no write lands in
+        // them while the loaded pages take their place. writers waiting
+        // on them find them freed, and start over from the new root.
+        let mut batch = OpenBatch::begin(self)?;
+        if !self.claim_empty(&batch)? {
+            self.free_tree(root_id)?;
+            return Ok(Err(BulkLoadError::NotEmpty));
+        }
+        let recorded = pin(|scope| {
+            meta::set_root(&self.pages, &*self.name, || root_id, scope)
+        });
+        if let Err(e) = recorded {
+            let _ = self.free_tree(root_id);
+            return Err(e);
+        }
+        let old_root = self.root.swap(root_id, SeqCst);
+        // expired keys may remain in the old leaves
+        let expired = self.free_tree(old_root)?;
+        self.adjust_len(len as isize - expired as isize);
+        self.pages.commit_batch(batch.lsn)?;
+        batch.committed = true;
+        Ok(Ok(()))
+    }
+
+    // claims every leaf for the batch, in key order, returning false
+    // if one of them holds a key that has not expired.
+    fn claim_empty(&self, batch: &OpenBatch) -> Result<bool> {
+        let now = now_ms();
+        pin(|scope| {
+            let mut lo = vec![];
+            loop {
+                let (leaf, cas_key) =
+                    self.path_for_key(&*lo, scope)?.pop().unwrap();
+                if self.wait_for_claim(&leaf, Some(batch)) {
+                    M.tree_looped();
+                    continue;
+                }
+                let items = leaf.data.leaf_ref().unwrap();
+                if items.iter().any(|r| !is_expired(r.2, now)) {
+                    return Ok(false);
+                }
+                if !self.claim(leaf.id, cas_key, batch, scope)? {
+                    M.tree_looped();
+                    continue;
+                }
+                match leaf.hi {
+                    Bound::Inf => return Ok(true),
+                    ref hi => lo = hi.inner().unwrap(),
+                }
+            }
+        })
+    }
+
+    /// Run a serializable transaction. The closure may be called
+    /// several times, as the transaction is retried until none of the
+    /// pages it read were changed by the time it commits. Writes made
+    /// through the `Tx` are applied atomically, and are recovered
+    /// all-or-nothing after a crash. While a transaction commits, only
+    /// writes to the leaves it read or wrote wait for it to finish. If
+    /// the closure returns an error, the transaction is abandoned and
+    /// the error is returned. If the `Tree` is read-only, committing
+    /// writes returns `Error::ReadOnly`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::Config;
+    /// let t = Config::default().tree().unwrap();
+    /// t.set(b"alice".to_vec(), vec![10]).unwrap();
+    /// t.set(b"bob".to_vec(), vec![0]).unwrap();
+    ///
+    /// // move 3 from alice to bob
+    /// let moved = t.tx(|tx| {
+    ///     let alice = tx.get(b"alice")?.unwrap()[0];
+    ///     let bob = tx.get(b"bob")?.unwrap()[0];
+    ///     if alice < 3 {
+    ///         return Ok(false);
+    ///     }
+    ///     tx.set(b"alice".to_vec(), vec![alice - 3]);
+    ///     tx.set(b"bob".to_vec(), vec![bob + 3]);
+    ///     Ok(true)
+    /// });
+    ///
+    /// assert_eq!(moved, Ok(true));
+    /// assert_eq!(t.get(b"alice"), Ok(Some(vec![7])));
+    /// assert_eq!(t.get(b"bob"), Ok(Some(vec![3])));
+    /// ```
+    pub fn tx<F, R>(&self, mut f: F) -> Result<R>
+        where F: FnMut(&mut Tx) -> Result<R>
+    {
+        let start = clock();
+        pin(|scope| loop {
+            let mut tx = Tx::new(self, scope);
+            let ret = f(&mut tx)?;
+            if tx.commit()? {
+                M.tree_tx.measure(clock() - start);
+                return Ok(ret);
+            }
+            M.tree_looped();
+        })
+    }
+
+    /// Iterate over tuples of keys and values, starting at the provided key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::Config;
+    /// let t = Config::default().tree().unwrap();
+    /// t.set(vec![1], vec![10]).unwrap();
+    /// t.set(vec![2], vec![20]).unwrap();
+    /// t.set(vec![3], vec![30]).unwrap();
+    /// let mut iter = t.scan(&*vec![2]);
+    /// assert_eq!(iter.next(), Some(Ok((vec![2], vec![20]))));
+    /// assert_eq!(iter.next(), Some(Ok((vec![3], vec![30]))));
+    /// assert_eq!(iter.next(), None);
+    /// ```
+    pub fn scan(&self, key: &[u8]) -> Iter {
+        self.range(key..)
+    }
+
+    /// Iterate over the tuples of keys and values in this tree.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::Config;
+    /// let t = Config::default().tree().unwrap();
+    /// t.set(vec![1], vec![10]).unwrap();
+    /// t.set(vec![2], vec![20]).unwrap();
+    /// t.set(vec![3], vec![30]).unwrap();
+    /// let mut iter = t.iter();
+    /// assert_eq!(iter.next(), Some(Ok((vec![1], vec![10]))));
+    /// assert_eq!(iter.next(), Some(Ok((vec![2], vec![20]))));
+    /// assert_eq!(iter.next(), Some(Ok((vec![3], vec![30]))));
+    /// assert_eq!(iter.next(), None);
+    /// ```
+    pub fn iter(&self) -> Iter {
+        self.range::<&[u8], _>(..)
+    }
+
+    /// Iterate over tuples of keys and values that start with
+    /// the provided prefix. A `KeyComparator` need not keep the keys
+    /// that share a prefix together, so if one is configured, the
+    /// iterator only yields `Error::Unsupported`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::Config;
+    /// let t = Config::default().tree().unwrap();
+    /// t.set(vec![0, 0], vec![1]).unwrap();
+    /// t.set(vec![0, 255], vec![2]).unwrap();
+    /// t.set(vec![0, 255, 255], vec![3]).unwrap();
+    /// t.set(vec![1, 0], vec![4]).unwrap();
+    /// let mut iter = t.scan_prefix(&*vec![0, 255]);
+    /// assert_eq!(iter.next(), Some(Ok((vec![0, 255], vec![2]))));
+    /// assert_eq!(iter.next(), Some(Ok((vec![0, 255, 255], vec![3]))));
+    /// assert_eq!(iter.next(), None);
+    /// ```
+    pub fn scan_prefix(&self, prefix: &[u8]) -> Iter {
+        if !self.order.is_lexicographic() {
+            let mut iter = self.range(..vec![]);
+            iter.refused = Some(Error::Unsupported(
+                "scan_prefix needs keys in lexicographic order, \
+                 but a key comparator is configured"
+                    .to_owned(),
+            ));
+            return iter;
+        }
+        if let Some(upper) = prefix_successor(prefix) {
+            self.range(prefix..&*upper)
+        } else {
+            self.range(prefix..)
+        }
+    }
+
+    /// Subscribe to the writes to keys that start with the provided
+    /// prefix. Writes made before this call are not reported.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::{Config, Event};
+    /// let t = Config::default().tree().unwrap();
+    /// let mut subscriber = t.watch_prefix(vec![0]);
+    /// t.set(vec![0, 1], vec![1]).unwrap();
+    /// t.set(vec![1, 1], vec![2]).unwrap();
+    /// t.del(&*vec![0, 1]).unwrap();
+    /// assert_eq!(subscriber.next(), Some(Event::Set(vec![0, 1], vec![1])));
+    /// assert_eq!(subscriber.next(), Some(Event::Del(vec![0, 1])));
+    ///
+    /// // only yield events once they are durable
+    /// let mut subscriber = t.watch_prefix(vec![]).stable();
+    /// t.set(vec![2], vec![3]).unwrap();
+    /// assert_eq!(subscriber.next(), Some(Event::Set(vec![2], vec![3])));
+    /// ```
+    pub fn watch_prefix(&self, prefix: Vec<u8>) -> Subscriber {
+        self.subscriptions.register(prefix, self.pages.clone())
+    }
+
+    /// Take a consistent, read-only view of the `Tree` as it is now.
+    /// Reads through the returned `Snapshot` are unaffected by
+    /// writes that happen after this call.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::Config;
+    /// let t = Config::default().tree().unwrap();
+    /// t.set(vec![1], vec![10]).unwrap();
+    /// let snapshot = t.snapshot().unwrap();
+    /// t.del(&*vec![1]).unwrap();
+    /// assert_eq!(t.get(&[1]), Ok(None));
+    /// assert_eq!(snapshot.get(&[1]), Ok(Some(vec![10])));
+    /// ```
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        let lsn = self.pages.pin_lsn()?;
+        Snapshot::new(self.pages.clone(), &*self.name, self.order, lsn)
+    }
+
+    /// Iterate over tuples of keys and values in descending order,
+    /// starting at the provided key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::Config;
+    /// let t = Config::default().tree().unwrap();
+    /// t.set(vec![1], vec![10]).unwrap();
+    /// t.set(vec![2], vec![20]).unwrap();
+    /// t.set(vec![3], vec![30]).unwrap();
+    /// let mut iter = t.scan_rev(&*vec![2]);
+    /// assert_eq!(iter.next(), Some(Ok((vec![2], vec![20]))));
+    /// assert_eq!(iter.next(), Some(Ok((vec![1], vec![10]))));
+    /// assert_eq!(iter.next(), None);
+    /// ```
+    pub fn scan_rev(&self, key: &[u8]) -> Rev<Iter> {
+        self.range(..=key).rev()
+    }
+
+    /// Iterate over tuples of keys and values within the provided range.
+    /// Iteration stops at the end of the range without paging in any
+    /// leaves that lie entirely beyond it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::Config;
+    /// let t = Config::default().tree().unwrap();
+    /// t.set(vec![1], vec![10]).unwrap();
+    /// t.set(vec![2], vec![20]).unwrap();
+    /// t.set(vec![3], vec![30]).unwrap();
+    /// t.set(vec![4], vec![40]).unwrap();
+    ///
+    /// let mut iter = t.range(vec![2]..vec![4]);
+    /// assert_eq!(iter.next(), Some(Ok((vec![2], vec![20]))));
+    /// assert_eq!(iter.next(), Some(Ok((vec![3], vec![30]))));
+    /// assert_eq!(iter.next(), None);
+    ///
+    /// let mut iter = t.range(vec![2]..=vec![3]);
+    /// assert_eq!(iter.next(), Some(Ok((vec![2], vec![20]))));
+    /// assert_eq!(iter.next(), Some(Ok((vec![3], vec![30]))));
+    /// assert_eq!(iter.next(), None);
+    /// ```
+    pub fn range<K, R>(&self, range: R) -> Iter
+        where K: AsRef<[u8]>,
+              R: RangeBounds<K>
+    {
+        use std::ops::Bound::{Excluded, Included, Unbounded};
+
+        let last_key = match range.start_bound() {
+            Included(k) => Bound::Non(k.as_ref().to_vec()),
+            Excluded(k) => Bound::Inc(k.as_ref().to_vec()),
+            Unbounded => Bound::Non(vec![]),
+        };
+
+        let hi = match range.end_bound() {
+            Included(k) => Bound::Inc(k.as_ref().to_vec()),
+            Excluded(k) => Bound::Non(k.as_ref().to_vec()),
+            Unbounded => Bound::Inf,
+        };
+
+        Iter {
+            // the root is not a leaf, so the first call to next
+            // descends to the leaf holding the start of the range.
+            id: self.root.load(SeqCst),
+            tree: self,
+            last_key: last_key,
+            hi: hi,
+            refused: None,
+            failed: false,
+        }
+    }
+
+    /// Returns the entry with the lowest key, if the `Tree` is not empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::Config;
+    /// let t = Config::default().tree().unwrap();
+    /// assert_eq!(t.first(), Ok(None));
+    /// t.set(vec![2], vec![20]).unwrap();
+    /// t.set(vec![1], vec![10]).unwrap();
+    /// assert_eq!(t.first(), Ok(Some((vec![1], vec![10]))));
+    /// ```
+    pub fn first(&self) -> Result<Option<(Key, Value)>> {
+        self.iter().next().transpose()
+    }
+
+    /// Returns the entry with the highest key, if the `Tree` is not empty.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::Config;
+    /// let t = Config::default().tree().unwrap();
+    /// assert_eq!(t.last(), Ok(None));
+    /// t.set(vec![2], vec![20]).unwrap();
+    /// t.set(vec![1], vec![10]).unwrap();
+    /// assert_eq!(t.last(), Ok(Some((vec![2], vec![20]))));
+    /// ```
+    pub fn last(&self) -> Result<Option<(Key, Value)>> {
+        self.last_below(Bound::Inf)
+    }
+
+    /// Returns the entry with the highest key that is lower than the
+    /// provided key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::Config;
+    /// let t = Config::default().tree().unwrap();
+    /// t.set(vec![1], vec![10]).unwrap();
+    /// t.set(vec![3], vec![30]).unwrap();
+    /// assert_eq!(t.get_lt(&[3]), Ok(Some((vec![1], vec![10]))));
+    /// assert_eq!(t.get_lt(&[2]), Ok(Some((vec![1], vec![10]))));
+    /// assert_eq!(t.get_lt(&[1]), Ok(None));
+    /// ```
+    pub fn get_lt(&self, key: &[u8]) -> Result<Option<(Key, Value)>> {
+        self.last_below(Bound::Non(key.to_vec()))
+    }
+
+    /// Returns the entry with the lowest key that is higher than the
+    /// provided key.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::Config;
+    /// let t = Config::default().tree().unwrap();
+    /// t.set(vec![1], vec![10]).unwrap();
+    /// t.set(vec![3], vec![30]).unwrap();
+    /// assert_eq!(t.get_gt(&[1]), Ok(Some((vec![3], vec![30]))));
+    /// assert_eq!(t.get_gt(&[2]), Ok(Some((vec![3], vec![30]))));
+    /// assert_eq!(t.get_gt(&[3]), Ok(None));
+    /// ```
+    pub fn get_gt(&self, key: &[u8]) -> Result<Option<(Key, Value)>> {
+        use std::ops::Bound::{Excluded, Unbounded};
+        self.range::<&[u8], _>((Excluded(key), Unbounded))
+            .next()
+            .transpose()
+    }
+
+    // reads backwards from hi, which descends straight to the leaf
+    // holding the answer rather than starting from the lowest key.
+    fn last_below(&self, hi: Bound) -> Result<Option<(Key, Value)>> {
+        let mut iter = Iter {
+            // next_back finds its own leaf
+            id: self.root.load(SeqCst),
+            tree: self,
+            last_key: Bound::Non(vec![]),
+            hi: hi,
+            refused: None,
+            failed: false,
+        };
+        iter.next_back().transpose()
+    }
+
+    /// Returns the number of keys in the `Tree`, counted by reading
+    /// every leaf. See `estimated_len` for a cheaper alternative.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::Config;
+    /// let t = Config::default().tree().unwrap();
+    /// t.set(vec![1], vec![10]).unwrap();
+    /// t.set(vec![2], vec![20]).unwrap();
+    /// t.set(vec![1], vec![11]).unwrap();
+    /// assert_eq!(t.len(), Ok(2));
+    /// ```
+    pub fn len(&self) -> Result<usize> {
+        let now = now_ms();
+        let mut len = 0;
+        pin(|scope| -> Result<()> {
+            let mut key = vec![];
+            loop {
+                let mut path = self.path_for_key(&*key, scope)?;
+                let (leaf, _cas_key) = path.pop().unwrap();
+                for &(ref k, _, expiry) in leaf.data.leaf_ref().unwrap() {
+                    let unseen =
+                        self.order.cmp_keys(k, &*key) != Ordering::Less;
+                    if unseen && !is_expired(expiry, now) {
+                        len += 1;
+                    }
+                }
+                match leaf.hi {
+                    Bound::Inf => return Ok(()),
+                    ref hi => key = hi.inner().unwrap(),
+                }
+            }
+        })?;
+        Ok(len)
+    }
+
+    /// Returns `true` if the `Tree` contains no keys.
+    pub fn is_empty(&self) -> Result<bool> {
+        self.first().map(|first| first.is_none())
+    }
+
+    /// Returns the number of keys in the `Tree` without reading it.
+    /// Every write adjusts a counter as it changes a leaf, so splits
+    /// and merges leave it untouched, but it includes expired keys
+    /// until they are reaped. The counter is recovered along with the
+    /// `Tree`, but it is only recorded every `snapshot_after_ops`
+    /// writes and when the `Tree` is dropped, so after a crash it may
+    /// miss the writes made since.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::Config;
+    /// let t = Config::default().tree().unwrap();
+    /// t.set(vec![1], vec![10]).unwrap();
+    /// t.set(vec![2], vec![20]).unwrap();
+    /// t.del(&[1]).unwrap();
+    /// assert_eq!(t.estimated_len(), Ok(1));
+    /// ```
+    pub fn estimated_len(&self) -> Result<usize> {
+        Ok(self.approx_len.load(SeqCst).max(0) as usize)
+    }
+
+    // adjusts the number of records after a write, and records it
+    // every snapshot_after_ops writes.
+    fn adjust_len(&self, delta: isize) {
+        self.approx_len.fetch_add(delta, SeqCst);
+        let count = self.approx_len_writes.fetch_add(1, SeqCst) + 1;
+        if count % self.config.get_snapshot_after_ops() == 0 {
+            // the write itself succeeded, and a later record catches up
+            if let Err(e) = self.record_len() {
+                error!("failed to record the length of the tree: {}", e);
+            }
+        }
+    }
+
+    fn record_len(&self) -> Result<()> {
+        if self.config.get_read_only() {
+            return Ok(());
+        }
+        pin(|scope| {
+            meta::update(
+                &self.pages,
+                |meta| {
+                    let len = self.approx_len.load(SeqCst).max(0) as u64;
+                    meta.set_approx_len(self.name.clone(), len);
+                },
+                scope,
+            )
+        })
+    }
+
+    /// Returns the number of bytes of storage in use. This covers
+    /// every `Tree` in the same `Db`, as they share one log, and
+    /// leaves out segments that are free to be reused.
+    pub fn size_on_disk(&self) -> u64 {
+        self.pages.size_on_disk()
+    }
+
+    /// Atomically remove and return the entry with the lowest key, if
+    /// the `Tree` is not empty. Concurrent callers never receive the
+    /// same entry, and no lower key written before the call is passed
+    /// over, so a `Tree` can serve as a durable priority queue. If the
+    /// `Tree` is read-only, returns `Error::ReadOnly`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::Config;
+    /// let t = Config::default().tree().unwrap();
+    /// t.set(vec![2], vec![20]).unwrap();
+    /// t.set(vec![1], vec![10]).unwrap();
+    /// assert_eq!(t.pop_min(), Ok(Some((vec![1], vec![10]))));
+    /// assert_eq!(t.pop_min(), Ok(Some((vec![2], vec![20]))));
+    /// assert_eq!(t.pop_min(), Ok(None));
+    /// ```
+    pub fn pop_min(&self) -> Result<Option<(Key, Value)>> {
+        self.pop(|scope| self.find_min(scope))
+    }
+
+    /// Atomically remove and return the entry with the highest key, if
+    /// the `Tree` is not empty, with the same guarantees as `pop_min`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sled::Config;
+    /// let t = Config::default().tree().unwrap();
+    /// t.set(vec![2], vec![20]).unwrap();
+    /// t.set(vec![1], vec![10]).unwrap();
+    /// assert_eq!(t.pop_max(), Ok(Some((vec![2], vec![20]))));
+    /// assert_eq!(t.pop_max(), Ok(Some((vec![1], vec![10]))));
+    /// assert_eq!(t.pop_max(), Ok(None));
+    /// ```
+    pub fn pop_max(&self) -> Result<Option<(Key, Value)>> {
+        self.pop(|scope| self.find_max(scope))
+    }
+
+    // the finder reports every leaf it read on the way to its answer,
+    // the last of which holds the key it found. the Del is linked by a
+    // cas on that leaf, after checking that the leaves before it are
+    // still empty, and the search starts over if either has changed.
+    fn pop<F>(&self, find: F) -> Result<Option<(Key, Value)>>
+        where F: for<'s> Fn(&'s Scope) -> Result<
+            (Vec<(Node, HPtr<'s, Frag>)>, Option<Key>),
+        >
+    {
+        if self.config.get_read_only() {
+            return Err(Error::ReadOnly);
+        }
+
+        let start = clock();
+        pin(|scope| loop {
+            let (mut reads, found) = find(scope)?;
+            let key = match found {
+                Some(key) => key,
+                None if self.unchanged(&*reads, scope)? => {
+                    M.tree_del.measure(clock() - start);
+                    return Ok(None);
+                }
+                None => {
+                    M.tree_looped();
+                    continue;
+                }
+            };
+
+            let (leaf, cas_key) = reads.pop().unwrap();
+            if self.unchanged(&*reads, scope)? {
+                let del = |_expiry| true;
+                let deleted = self
+                    .del_from_leaf(&leaf, cas_key, &*key, &del, None, scope)?;
+                // an expired key was not popped, so we look again
+                if let Some(Some(value)) = deleted {
+                    M.tree_del.measure(clock() - start);
+                    return Ok(Some((key, value)));
+                }
+            }
+            M.tree_looped();
+        })
+    }
+
+    fn unchanged<'s>(
+        &self,
+        reads: &[(Node, HPtr<'s, Frag>)],
+        scope: &'s Scope,
+    ) -> Result<bool> {
+        for &(ref node, ref observed) in reads {
+            match self.pages.get(node.id, scope)? {
+                Some((_, ref current))
+                    if current.as_raw() == observed.as_raw() => {}
+                _ => return Ok(false),
+            }
+        }
+        Ok(true)
+    }
+
+    // walks leaves upwards from the empty key until one holds a live
+    // entry. keys below the bound we resumed from were already seen
+    // as absent in an earlier leaf.
+    fn find_min<'s>(
+        &self,
+        scope: &'s Scope,
+    ) -> Result<(Vec<(Node, HPtr<'s, Frag>)>, Option<Key>)> {
+        let now = now_ms();
+        let mut reads = vec![];
+        let mut lo = vec![];
+        loop {
+            let (leaf, cas_key) =
+                self.path_for_key(&*lo, scope)?.pop().unwrap();
+
+            let found = leaf.data.leaf_ref().unwrap().iter().find(
+                |&&(ref k, _, expiry)| {
+                    self.order.cmp_keys(k, &*lo) != Ordering::Less &&
+                        !is_expired(expiry, now)
+                },
+            ).map(|&(ref k, _, _)| k.clone());
+            let hi = leaf.hi.clone();
+            reads.push((leaf, cas_key));
+            if found.is_some() {
+                return Ok((reads, found));
+            }
+
+            match hi {
+                Bound::Inf => return Ok((reads, None)),
+                hi => lo = hi.inner().unwrap(),
+            }
+        }
+    }
+
+    // the mirror image of find_min, walking leaves downwards from the
+    // top of the keyspace.
+    fn find_max<'s>(
+        &self,
+        scope: &'s Scope,
+    ) -> Result<(Vec<(Node, HPtr<'s, Frag>)>, Option<Key>)> {
+        let now = now_ms();
+        let mut reads = vec![];
+        let mut hi = Bound::Inf;
+        loop {
+            let (leaf, cas_key) = self.leaf_for_upper_bound(&hi, scope)?;
+
+            let found = leaf.data.leaf_ref().unwrap().iter().rev().find(
+                |&&(ref k, _, expiry)| {
+                    self.order.lt(&Bound::Inc(k.clone()), &hi) &&
+                        !is_expired(expiry, now)
+                },
+            ).map(|&(ref k, _, _)| k.clone());
+            let lo = leaf.lo.inner().unwrap();
+            reads.push((leaf, cas_key));
+            if found.is_some() {
+                return Ok((reads, found));
+            }
+
+            if lo.is_empty() {
+                return Ok((reads, None));
+            }
+            hi = Bound::Non(lo);
+        }
+    }
+
+    fn recursive_split<'s>(
+        &self,
+        path: &[(Node, HPtr<'s, Frag>)],
+        scope: &'s Scope,
+    ) -> Result<()> {
+        // to split, we pop the path, see if it's in need of split, recurse up
+        // two-phase: (in prep for lock-free, not necessary for single threaded)
+        //  1. half-split: install split on child, P
+        //      a. allocate new right sibling page, Q
+        //      b. locate split point
+        //      c. create new consolidated pages for both sides
+        //      d. add new node to pagetable
+        //      e. merge split delta to original page P with physical pointer to Q
+        //      f. if failed, free the new page
+        //  2. parent update: install new index term on parent
+        //      a. merge "index term delta record" to parent, containing:
+        //          i. new bounds for P & Q
+        //          ii. logical pointer to Q
+        //
+        //      (it's possible parent was merged in the mean-time, so if that's the
+        //      case, we need to go up the path to the grandparent then down again
+        //      or higher until it works)
+        //  3. any traversing nodes that witness #1 but not #2 try to complete it
+        //
+        //  root is special case, where we need to hoist a new root
+
+        // a parent does not split while it intends to merge a child,
+        // so a merge interrupted by a crash is finished or cancelled.
+        for &(ref node, _) in path {
+            if let Some(child) = node.merging_child {
+                if node.data.len() > self.fanout() {
+                    self.resolve_merge(child, scope)?;
+                }
+            }
+        }
+
+        let mut all_page_views = path.to_vec();
+        let mut root_and_key = all_page_views.remove(0);
+
+        // println!("before:\n{:?}\n", self);
+
+        while let Some((node, cas_key)) = all_page_views.pop() {
+            // println!("splitting node {:?}", node);
             if node.should_split(self.fanout()) {
                 // try to child split
-                if let Ok(parent_split) = self.child_split(
+                if let Some(parent_split) = self.child_split(
                     &node,
                     cas_key,
                     scope,
-                )
+                )?
                 {
                     // now try to parent split
                     let &mut (ref mut parent_node, ref mut parent_cas_key) =
@@ -351,10 +1976,14 @@ impl Tree {
                         parent_cas_key.clone(),
                         parent_split.clone(),
                         scope,
-                    );
+                    )?;
 
                     if let Ok(res) = res {
-                        parent_node.apply(&Frag::ParentSplit(parent_split));
+                        parent_node.apply(
+                            &Frag::ParentSplit(parent_split),
+                            None,
+                            self.order,
+                        );
                         *parent_cas_key = res;
                     } else {
                         continue;
@@ -365,168 +1994,854 @@ impl Tree {
             }
         }
 
-        let (root_node, root_cas_key) = root_and_key;
+        let (root_node, root_cas_key) = root_and_key;
+
+        if root_node.should_split(self.fanout()) {
+            // println!("{}: hoisting root {}", name, root_frag.node.id);
+            if let Some(parent_split) = self.child_split(
+                &root_node,
+                root_cas_key,
+                scope,
+            )?
+            {
+                self.root_hoist(
+                    root_node.id,
+                    parent_split.to,
+                    parent_split.at.inner().unwrap(),
+                    scope,
+                )?;
+            }
+        }
+        // println!("after:\n{:?}\n", self);
+        Ok(())
+    }
+
+    // returns None if the node changed before the split was linked.
+    fn child_split<'s>(
+        &self,
+        node: &Node,
+        node_cas_key: HPtr<'s, Frag>,
+        scope: &'s Scope,
+    ) -> Result<Option<ParentSplit>> {
+        let (new_pid, new_cas_key) = self.pages.allocate(scope)?;
+        trace!("allocated pid {} in child_split", new_pid);
+
+        // split the node in half
+        let rhs = node.split(new_pid, self.order);
+
+        let child_split = Frag::ChildSplit(ChildSplit {
+            at: rhs.lo.clone(),
+            to: new_pid,
+        });
+
+        let parent_split = ParentSplit {
+            at: rhs.lo.clone(),
+            to: new_pid,
+        };
+
+        // install the new right side
+        self.pages
+            .replace(new_pid, new_cas_key, Frag::Base(rhs, false), scope)?
+            .expect("failed to initialize child split");
+
+        // try to install a child split on the left side
+        if self.pages
+            .link(node.id, node_cas_key, child_split, scope)?
+            .is_err()
+        {
+            // if we failed, don't follow through with the parent split
+            // println!("{}: {}|{} @ {:?} -", tn(), node.id, new_pid, parent_split.at);
+            self.pages.free(new_pid)?;
+            return Ok(None);
+        }
+
+        Ok(Some(parent_split))
+    }
+
+    fn parent_split<'s>(
+        &self,
+        parent_node: Node,
+        parent_cas_key: HPtr<'s, Frag>,
+        parent_split: ParentSplit,
+        scope: &'s Scope,
+    ) -> Result<CasResult<'s, Frag>> {
+        // install parent split
+        let res = self.pages.link(
+            parent_node.id,
+            parent_cas_key,
+            Frag::ParentSplit(parent_split.clone()),
+            scope,
+        )?;
+
+        if res.is_err() {
+            // println!("{}: {} <- {:?}|{} -", tn(), parent_node.id, parent_split.at, parent_split.to);
+        }
+
+        Ok(res)
+    }
+
+    fn root_hoist<'s>(
+        &self,
+        from: PageID,
+        to: PageID,
+        at: Key,
+        scope: &'s Scope,
+    ) -> Result<()> {
+        // hoist new root, pointing to lhs & rhs
+        let (new_root_pid, new_root_cas_key) = self.pages.allocate(scope)?;
+        debug!("allocated pid {} in root_hoist", new_root_pid);
+
+        let mut new_root_vec = vec![];
+        new_root_vec.push((vec![], from));
+        new_root_vec.push((at, to));
+        let new_root = Frag::Base(
+            Node {
+                id: new_root_pid,
+                data: Data::Index(new_root_vec),
+                next: None,
+                lo: Bound::Inc(vec![]),
+                hi: Bound::Inf,
+                merging_child: None,
+                merged: false,
+                claim: None,
+            },
+            true,
+        );
+        // println!("split is {:?}", parent_split);
+        // println!("trying to cas root at {:?} with real value {:?}", path.first().unwrap().pid, self.root.load(SeqCst));
+        // println!("root_id is {}", root_id);
+        debug_delay();
+        let cas = self.root.compare_and_swap(from, new_root_pid, SeqCst);
+        if cas == from {
+            // TODO think about the racyness of this
+            self.pages
+                .replace(new_root_pid, new_root_cas_key, new_root, scope)?
+                .unwrap();
+            // recovery finds the new root through the Meta. concurrent
+            // hoists may finish out of order, so each records whatever
+            // the root is at the time of its write.
+            meta::set_root(
+                &self.pages,
+                &*self.name,
+                || self.root.load(SeqCst),
+                scope,
+            )?;
+            debug!(
+                "{}: root hoist from {} to {} successful",
+                tn(),
+                from,
+                new_root_pid
+            );
+        } else {
+            debug!("root hoist from {} to {} failed", from, new_root_pid);
+            self.pages.free(new_root_pid)?;
+        }
+        Ok(())
+    }
+
+    // frees every page reachable from a root that is no longer in
+    // use, along with the values of the records in its leaves, and
+    // returns the number of records. readers still traversing it
+    // restart from the current root when they find a page missing.
+    fn free_tree(&self, root: PageID) -> Result<usize> {
+        let mut records = 0;
+        let mut pids = vec![root];
+        let mut seen = HashSet::new();
+        pin(|scope| -> Result<()> {
+            while let Some(pid) = pids.pop() {
+                if !seen.insert(pid) {
+                    continue;
+                }
+                let node = match self.pages.get(pid, scope)? {
+                    Some((frag, _cas_key)) => frag.into_base().unwrap().0,
+                    None => continue,
+                };
+                // an incomplete split is only reachable through next
+                pids.extend(node.next);
+                match node.data {
+                    Data::Index(ref ptrs) => {
+                        pids.extend(ptrs.iter().map(|&(_, ptr)| ptr))
+                    }
+                    Data::Leaf(ref items) => {
+                        for &(_, ref v, _) in items {
+                            blob::free(&self.pages, v)?;
+                        }
+                        records += items.len();
+                    }
+                }
+                self.pages.free(pid)?;
+            }
+            Ok(())
+        })?;
+        Ok(records)
+    }
+
+    // merging is also multi-phase, and needs no lock:
+    //  1. mark the parent with a ParentMergeIntention naming the child
+    //     to remove, which stops the parent from splitting or merging
+    //     any other child. the child is the highest node above the
+    //     empty leaf that holds nothing else.
+    //  2. mark the empty leaf as Merged, which fails if it was written
+    //     to, then every index node between it and the child
+    //  3. LeftMerge the key range of each marked node into its left
+    //     neighbor. the first child of a parent can only be a leaf,
+    //     whose right sibling takes over its range with a RightMerge.
+    //  4. ParentMerge to remove the child from the parent, then free it
+    //     along with the nodes below it
+    //  any traversing nodes that witness #2 try to complete #3 and #4
+    fn merge_empty_leaf<'s>(
+        &self,
+        pid: PageID,
+        scope: &'s Scope,
+    ) -> Result<()> {
+        loop {
+            let (leaf_frag, leaf_cas_key) = match self.pages.get(pid, scope)? {
+                Some(got) => got,
+                None => return Ok(()),
+            };
+            let (leaf, _is_root) = leaf_frag.into_base().unwrap();
+            if leaf.merged || leaf.data.len() != 0 {
+                return Ok(());
+            }
+            if self.pages.in_open_batch(pid) {
+                // the leaf may only be empty until the batch is abandoned
+                return Ok(());
+            }
+
+            let key = leaf.lo.inner().unwrap();
+            let mut path = match self.path_to(pid, &key, scope)? {
+                Some(path) => path,
+                None => return Ok(()),
+            };
+
+            let mut child = leaf.clone();
+            let (parent, parent_cas_key) = loop {
+                let (node, cas_key) = path.pop().unwrap();
+                if node.data.len() > 1 || node.hi != child.hi ||
+                    path.is_empty()
+                {
+                    break (node, cas_key);
+                }
+                child = node;
+            };
+
+            if let Some(other) = parent.merging_child {
+                // we wait for the other merge to be marked, and then
+                // finish or cancel it before trying again.
+                if !self.resolve_merge(other, scope)? {
+                    thread::yield_now();
+                }
+                M.tree_looped();
+                continue;
+            }
+
+            if let Data::Index(ref ptrs) = parent.data {
+                let right_mergeable = child.id == pid && ptrs.len() > 1 &&
+                    leaf.next == Some(ptrs[1].1);
+                if ptrs[0].1 == child.id && !right_mergeable {
+                    // a first child has no left sibling under its
+                    // parent, and only a leaf that has not split since
+                    // the parent last did is merged to the right.
+                    return Ok(());
+                }
+            }
+
+            if !self.merging.lock().unwrap().insert(child.id) {
+                // another thread is starting the same merge
+                return Ok(());
+            }
+            let marked = self.mark_merge(
+                &leaf,
+                leaf_cas_key,
+                child.id,
+                &parent,
+                parent_cas_key,
+                scope,
+            );
+            self.merging.lock().unwrap().remove(&child.id);
+
+            match marked? {
+                Some(true) => return self.complete_merge(pid, scope),
+                Some(false) => return Ok(()),
+                None => M.tree_looped(),
+            }
+        }
+    }
+
+    // links the intention to merge the child on the parent, and marks
+    // the leaf below the child. returns None if the parent changed
+    // first, and Some(false) if the leaf did.
+    fn mark_merge<'s>(
+        &self,
+        leaf: &Node,
+        leaf_cas_key: HPtr<'s, Frag>,
+        child: PageID,
+        parent: &Node,
+        parent_cas_key: HPtr<'s, Frag>,
+        scope: &'s Scope,
+    ) -> Result<Option<bool>> {
+        let intention = Frag::ParentMergeIntention(child);
+        if self.pages
+            .link(parent.id, parent_cas_key, intention, scope)?
+            .is_err()
+        {
+            return Ok(None);
+        }
+
+        if self.pages
+            .link(leaf.id, leaf_cas_key, Frag::Merged, scope)?
+            .is_ok()
+        {
+            return Ok(Some(true));
+        }
+
+        // the leaf was written to, so we back out
+        let key = leaf.lo.inner().unwrap();
+        self.cancel_merge(child, &key, scope)?;
+        Ok(Some(false))
+    }
+
+    // finishes the merge of the given child if the leaf below it was
+    // marked, or cancels it if it never will be. returns false while
+    // the thread that intended the merge may still mark it.
+    fn resolve_merge<'s>(
+        &self,
+        child: PageID,
+        scope: &'s Scope,
+    ) -> Result<bool> {
+        if self.merging.lock().unwrap().contains(&child) {
+            return Ok(false);
+        }
+
+        let mut key = None;
+        let mut cursor = child;
+        loop {
+            let node = match self.pages.get(cursor, scope)? {
+                Some((frag, _cas_key)) => frag.into_base().unwrap().0,
+                None => return Ok(true),
+            };
+            if node.merged {
+                self.complete_merge(cursor, scope)?;
+                return Ok(true);
+            }
+            if key.is_none() {
+                key = node.lo.inner();
+            }
+            match node.data {
+                Data::Index(ref ptrs) => cursor = ptrs[0].1,
+                Data::Leaf(_) => break,
+            }
+        }
+
+        // a crash interrupted the merge before the leaf was marked
+        self.cancel_merge(child, &key.unwrap(), scope)?;
+        Ok(true)
+    }
+
+    fn cancel_merge<'s>(
+        &self,
+        child: PageID,
+        key: &[u8],
+        scope: &'s Scope,
+    ) -> Result<()> {
+        loop {
+            let (parent, parent_cas_key) =
+                match self.parent_of(child, key, scope)? {
+                    Some(parent) => parent,
+                    None => return Ok(()),
+                };
+            if parent.merging_child != Some(child) {
+                return Ok(());
+            }
+            let cancel = Frag::ParentMergeCancel(child);
+            if self.pages
+                .link(parent.id, parent_cas_key, cancel, scope)?
+                .is_ok()
+            {
+                return Ok(());
+            }
+            M.tree_looped();
+        }
+    }
+
+    fn complete_merge<'s>(&self, pid: PageID, scope: &'s Scope) -> Result<()> {
+        'retry: loop {
+            let node = match self.pages.get(pid, scope)? {
+                Some((frag, _cas_key)) => frag.into_base().unwrap().0,
+                None => return Ok(()),
+            };
+            if !node.merged {
+                return Ok(());
+            }
+            let key = node.lo.inner().unwrap();
+
+            // find the child being removed, which is either this node
+            // or the highest of the nodes that only lead to it.
+            let mut path = match self.path_to(pid, &key, scope)? {
+                Some(path) => path,
+                None => return Ok(()),
+            };
+            let mut child = pid;
+            let parent = loop {
+                let (ancestor, _cas_key) = match path.pop() {
+                    Some(ancestor) => ancestor,
+                    None => return Ok(()),
+                };
+                if ancestor.merging_child == Some(child) {
+                    break ancestor;
+                }
+                if ancestor.data.len() > 1 {
+                    return Ok(());
+                }
+                child = ancestor.id;
+            };
+
+            // the nodes from the child down to its leaf
+            let mut chain = vec![];
+            let mut cursor = child;
+            loop {
+                let (frag, cas_key) = match self.pages.get(cursor, scope)? {
+                    Some(got) => got,
+                    None => return Ok(()),
+                };
+                let (node, _is_root) = frag.into_base().unwrap();
+                let below = match node.data {
+                    Data::Index(ref ptrs) => Some(ptrs[0].1),
+                    Data::Leaf(_) => None,
+                };
+                chain.push((node, cas_key));
+                match below {
+                    Some(below) => cursor = below,
+                    None => break,
+                }
+            }
+
+            // the leaf was marked first, and the nodes above it
+            // follow from the bottom up.
+            for &(ref node, cas_key) in chain.iter().rev() {
+                if node.merged {
+                    continue;
+                }
+                if self.pages
+                    .link(node.id, cas_key, Frag::Merged, scope)?
+                    .is_err()
+                {
+                    M.tree_looped();
+                    continue 'retry;
+                }
+            }
+
+            let first = match parent.data {
+                Data::Index(ref ptrs) => ptrs[0].1 == child,
+                Data::Leaf(_) => unreachable!(),
+            };
+            if first {
+                self.merge_right(&chain[0].0, scope)?;
+            } else {
+                for &(ref node, _) in chain.iter().rev() {
+                    self.merge_left(node, scope)?;
+                }
+            }
+
+            // remove the child from its parent. whoever succeeds
+            // is responsible for freeing it and the nodes below it.
+            loop {
+                let (parent, parent_cas_key) =
+                    match self.parent_of(child, &key, scope)? {
+                        Some(parent) => parent,
+                        None => return Ok(()),
+                    };
+                let parent_merge = Frag::ParentMerge(child);
+                if self.pages
+                    .link(parent.id, parent_cas_key, parent_merge, scope)?
+                    .is_err()
+                {
+                    M.tree_looped();
+                    continue;
+                }
+
+                trace!("merged pid {} into its sibling", child);
+                for &(ref node, _) in &chain {
+                    self.pages.free(node.id)?;
+                }
+
+                // a parent left with one child may now only lead to
+                // an empty leaf itself.
+                if parent.data.len() == 2 {
+                    let lo = parent.lo.inner().unwrap();
+                    let (leaf, _cas_key) =
+                        self.path_for_key(&lo, scope)?.pop().unwrap();
+                    return self.merge_empty_leaf(leaf.id, scope);
+                }
+                return Ok(());
+            }
+        }
+    }
+
+    // points the left neighbor of a merged node past it, unless that
+    // has already happened.
+    fn merge_left<'s>(&self, node: &Node, scope: &'s Scope) -> Result<()> {
+        loop {
+            let (left, left_cas_key) =
+                match self.left_neighbor(node, scope)? {
+                    Some(left) => left,
+                    None => return Ok(()),
+                };
+            let left_merge = Frag::LeftMerge(LeftMerge {
+                rhs: node.id,
+                hi: node.hi.clone(),
+                next: node.next,
+            });
+            if self.pages
+                .link(left.id, left_cas_key, left_merge, scope)?
+                .is_ok()
+            {
+                return Ok(());
+            }
+            M.tree_looped();
+        }
+    }
 
-        if root_node.should_split(self.fanout()) {
-            // println!("{}: hoisting root {}", name, root_frag.node.id);
-            if let Ok(parent_split) = self.child_split(
-                &root_node,
-                root_cas_key,
-                scope,
-            )
+    // extends the right sibling of a merged leaf down over its key
+    // range, then points the leaf's left neighbor at that sibling.
+    fn merge_right<'s>(&self, leaf: &Node, scope: &'s Scope) -> Result<()> {
+        let rhs = leaf.next.unwrap();
+        loop {
+            let (frag, cas_key) = match self.pages.get(rhs, scope)? {
+                Some(got) => got,
+                None => return Ok(()),
+            };
+            let (right, _is_root) = frag.into_base().unwrap();
+            if right.lo == leaf.lo {
+                break;
+            }
+            let right_merge = Frag::RightMerge(RightMerge {
+                lhs: leaf.id,
+                lo: leaf.lo.clone(),
+            });
+            if self.pages.link(rhs, cas_key, right_merge, scope)?.is_ok() {
+                break;
+            }
+            M.tree_looped();
+        }
+
+        if leaf.lo == Bound::Inc(vec![]) {
+            // the leftmost leaf has no left neighbor
+            return Ok(());
+        }
+        loop {
+            let (left, left_cas_key) =
+                match self.left_neighbor(leaf, scope)? {
+                    Some(left) => left,
+                    None => return Ok(()),
+                };
+            let left_merge = Frag::LeftMerge(LeftMerge {
+                rhs: leaf.id,
+                hi: left.hi.clone(),
+                next: Some(rhs),
+            });
+            if self.pages
+                .link(left.id, left_cas_key, left_merge, scope)?
+                .is_ok()
             {
-                self.root_hoist(
-                    root_node.id,
-                    parent_split.to,
-                    parent_split.at.inner().unwrap(),
-                    scope,
-                );
+                return Ok(());
             }
+            M.tree_looped();
         }
-        // println!("after:\n{:?}\n", self);
     }
 
-    fn child_split<'s>(
+    // finds the node on the same level as the given one whose next
+    // pointer leads to it, if any still does. a merged neighbor is
+    // first helped out of the way.
+    fn left_neighbor<'s>(
         &self,
         node: &Node,
-        node_cas_key: HPtr<'s, Frag>,
         scope: &'s Scope,
-    ) -> Result<ParentSplit, ()> {
-        let (new_pid, new_cas_key) = self.pages.allocate(scope);
-        trace!("allocated pid {} in child_split", new_pid);
-
-        // split the node in half
-        let rhs = node.split(new_pid);
-
-        let child_split = Frag::ChildSplit(ChildSplit {
-            at: rhs.lo.clone(),
-            to: new_pid,
-        });
+    ) -> Result<Option<(Node, HPtr<'s, Frag>)>> {
+        let hi = Bound::Non(node.lo.inner().unwrap());
+        let mut cursor = self.root.load(SeqCst);
+        loop {
+            let get_cursor = self.pages.get(cursor, scope)?;
+            if get_cursor.is_none() {
+                cursor = self.root.load(SeqCst);
+                continue;
+            }
+            let (frag, cas_key) = get_cursor.unwrap();
+            let (left, _is_root) = frag.into_base().unwrap();
 
-        let parent_split = ParentSplit {
-            at: rhs.lo.clone(),
-            to: new_pid,
-        };
+            if left.next == Some(node.id) {
+                if left.merged {
+                    self.complete_merge(left.id, scope)?;
+                    cursor = self.root.load(SeqCst);
+                    continue;
+                }
+                return Ok(Some((left, cas_key)));
+            }
 
-        // install the new right side
-        self.pages
-            .replace(new_pid, new_cas_key, Frag::Base(rhs, false), scope)
-            .expect("failed to initialize child split");
+            if self.order.lt(&left.hi, &hi) {
+                cursor = left.next.unwrap();
+                continue;
+            }
 
-        // try to install a child split on the left side
-        if self.pages
-            .link(node.id, node_cas_key, child_split, scope)
-            .is_err()
-        {
-            // if we failed, don't follow through with the parent split
-            // println!("{}: {}|{} @ {:?} -", tn(), node.id, new_pid, parent_split.at);
-            self.pages.free(new_pid);
-            return Err(());
+            match left.data {
+                Data::Index(ref ptrs) => {
+                    for &(ref sep_k, ref ptr) in ptrs {
+                        if self.order.lt(&Bound::Inc(sep_k.clone()), &hi) {
+                            cursor = *ptr;
+                        } else {
+                            break;
+                        }
+                    }
+                }
+                Data::Leaf(_) => return Ok(None),
+            }
         }
+    }
 
-        Ok(parent_split)
+    // finds the index node that points to the given child, by
+    // descending towards the child's low key.
+    fn parent_of<'s>(
+        &self,
+        pid: PageID,
+        key: &[u8],
+        scope: &'s Scope,
+    ) -> Result<Option<(Node, HPtr<'s, Frag>)>> {
+        Ok(self.path_to(pid, key, scope)?.and_then(|mut path| path.pop()))
     }
 
-    fn parent_split<'s>(
+    // finds the index nodes from the root down to the parent of the
+    // given node, by descending towards the node's low key.
+    fn path_to<'s>(
         &self,
-        parent_node: Node,
-        parent_cas_key: HPtr<'s, Frag>,
-        parent_split: ParentSplit,
+        pid: PageID,
+        key: &[u8],
         scope: &'s Scope,
-    ) -> Result<HPtr<'s, Frag>, Option<HPtr<'s, Frag>>> {
-        // install parent split
-        let res = self.pages.link(
-            parent_node.id,
-            parent_cas_key,
-            Frag::ParentSplit(parent_split.clone()),
-            scope,
-        );
+    ) -> Result<Option<Vec<(Node, HPtr<'s, Frag>)>>> {
+        let key_bound = Bound::Inc(key.into());
+        let mut cursor = self.root.load(SeqCst);
+        let mut path = vec![];
+        loop {
+            let get_cursor = self.pages.get(cursor, scope)?;
+            if get_cursor.is_none() {
+                cursor = self.root.load(SeqCst);
+                path.clear();
+                continue;
+            }
+            let (frag, cas_key) = get_cursor.unwrap();
+            let (node, _is_root) = frag.into_base().unwrap();
 
-        if res.is_err() {
-            // println!("{}: {} <- {:?}|{} -", tn(), parent_node.id, parent_split.at, parent_split.to);
+            if self.order.le(&node.hi, &key_bound) {
+                cursor = node.next.unwrap();
+                continue;
+            }
+
+            match node.data {
+                Data::Index(ref ptrs) => {
+                    let is_parent = ptrs.iter().any(|&(_, ptr)| ptr == pid);
+                    for &(ref sep_k, ref ptr) in ptrs {
+                        let sep_cmp = self.order.cmp_keys(sep_k, key);
+                        if sep_cmp != Ordering::Greater {
+                            cursor = *ptr;
+                        } else {
+                            break;
+                        }
+                    }
+                    path.push((node.clone(), cas_key));
+                    if is_parent {
+                        return Ok(Some(path));
+                    }
+                }
+                Data::Leaf(_) => return Ok(None),
+            }
+        }
+    }
+
+    // starts the reaper thread, unless it is already running.
+    fn start_reaper(&self) {
+        let reap_every_ms = match self.config.get_reap_every_ms() {
+            Some(ms) if !self.config.get_read_only() => ms,
+            _ => return,
+        };
+        let mut reaper = self.reaper.lock().unwrap();
+        if reaper.is_some() {
+            return;
         }
 
-        res
+        let handle = Tree {
+            pages: self.pages.clone(),
+            config: self.config.clone(),
+            name: self.name.clone(),
+            order: self.order,
+            root: self.root.clone(),
+            merging: self.merging.clone(),
+            committing: self.committing.clone(),
+            subscriptions: self.subscriptions.clone(),
+            approx_len: self.approx_len.clone(),
+            approx_len_writes: self.approx_len_writes.clone(),
+            merged: self.merged.clone(),
+            expiring: self.expiring.clone(),
+            reaper: Mutex::new(None),
+        };
+        *reaper = Some(Reaper::start(handle, reap_every_ms));
     }
 
-    fn root_hoist<'s>(
-        &self,
-        from: PageID,
-        to: PageID,
-        at: Key,
-        scope: &'s Scope,
-    ) {
-        // hoist new root, pointing to lhs & rhs
-        let (new_root_pid, new_root_cas_key) = self.pages.allocate(scope);
-        debug!("allocated pid {} in root_hoist", new_root_pid);
+    // deletes the keys that had expired when the scan started, so that
+    // the segment cleaner can reclaim their space. keys are deleted
+    // every REAP_BATCH or so, rather than gathered for the whole tree.
+    pub(super) fn reap(&self) -> Result<()> {
+        let now = now_ms();
+        let mut key = vec![];
+        loop {
+            let mut expired = vec![];
+            let done = pin(|scope| -> Result<bool> {
+                loop {
+                    let mut path = self.path_for_key(&*key, scope)?;
+                    let (leaf, _cas_key) = path.pop().unwrap();
+                    for &(ref k, _, expiry) in leaf.data.leaf_ref().unwrap() {
+                        let unseen =
+                            self.order.cmp_keys(k, &*key) != Ordering::Less;
+                        if unseen && is_expired(expiry, now) {
+                            expired.push(k.clone());
+                        }
+                    }
+                    match leaf.hi {
+                        Bound::Inf => return Ok(true),
+                        ref hi => key = hi.inner().unwrap(),
+                    }
+                    if expired.len() >= REAP_BATCH {
+                        return Ok(false);
+                    }
+                }
+            })?;
 
-        let mut new_root_vec = vec![];
-        new_root_vec.push((vec![], from));
-        new_root_vec.push((at, to));
-        let new_root = Frag::Base(
-            Node {
-                id: new_root_pid,
-                data: Data::Index(new_root_vec),
-                next: None,
-                lo: Bound::Inc(vec![]),
-                hi: Bound::Inf,
-            },
-            true,
-        );
-        // println!("split is {:?}", parent_split);
-        // println!("trying to cas root at {:?} with real value {:?}", path.first().unwrap().pid, self.root.load(SeqCst));
-        // println!("root_id is {}", root_id);
-        debug_delay();
-        let cas = self.root.compare_and_swap(from, new_root_pid, SeqCst);
-        if cas == from {
-            // TODO think about the racyness of this
-            self.pages
-                .replace(new_root_pid, new_root_cas_key, new_root, scope)
-                .unwrap();
-            debug!(
-                "{}: root hoist from {} to {} successful",
-                tn(),
-                from,
-                new_root_pid
-            );
-        } else {
-            debug!("root hoist from {} to {} failed", from, new_root_pid);
-            self.pages.free(new_root_pid);
+            for key in expired {
+                // a concurrent set may have given the key a new expiry
+                self.del_if(&*key, |expiry| is_expired(expiry, now), None)?;
+            }
+            if done {
+                return Ok(());
+            }
         }
     }
 
-    fn get_internal<'s>(
+    /// Returns the number of pages used by the `Db` this `Tree` belongs to.
+    #[doc(hidden)]
+    pub fn page_count(&self) -> usize {
+        self.pages.page_count()
+    }
+
+    pub(super) fn get_internal<'s>(
         &self,
         key: &[u8],
         scope: &'s Scope,
-    ) -> (Vec<(Node, HPtr<'s, Frag>)>, Option<Value>) {
-        let path = self.path_for_key(&*key, scope);
-
-        let ret = path.last().and_then(|&(ref last_node, ref _last_cas_key)| {
-            let data = &last_node.data;
-            let items = data.leaf_ref().unwrap();
-            let search =
-                items.binary_search_by(|&(ref k, ref _v)| (**k).cmp(key));
-            if let Ok(idx) = search {
+    ) -> Result<(Vec<(Node, HPtr<'s, Frag>)>, Option<Value>)> {
+        loop {
+            let path = self.path_for_key(&*key, scope)?;
+
+            let stored = match self.record(&path.last().unwrap().0, key) {
+                Some(&(_, _, expiry)) if is_expired(expiry, now_ms()) => None,
                 // cap a del frag below
-                Some(items[idx].1.clone())
-            } else {
+                Some(&(_, ref stored, _)) => Some(stored),
                 // key does not exist
-                None
+                None => None,
+            };
+
+            let stored = match stored {
+                Some(stored) => stored,
+                None => return Ok((path, None)),
+            };
+            match blob::load(&self.pages, stored, scope)? {
+                Some(value) => return Ok((path, Some(value))),
+                // the blob was freed by a write after we read the leaf
+                None => M.tree_looped(),
             }
-        });
+        }
+    }
 
-        (path, ret)
+    // the record of a key in a leaf, even if it has expired.
+    fn record<'n>(
+        &self,
+        leaf: &'n Node,
+        key: &[u8],
+    ) -> Option<&'n (Key, StoredValue, Option<u64>)> {
+        let items = leaf.data.leaf_ref().unwrap();
+        let search = items.binary_search_by(|&(ref k, ref _v, _)| {
+            self.order.cmp_keys(k, key)
+        });
+        search.ok().map(|idx| &items[idx])
     }
 
     fn fanout(&self) -> usize {
         self.config.get_blink_fanout()
     }
 
+    /// returns the leaf that would contain the highest key
+    /// falling under the provided upper bound. Leaves only
+    /// link to their right siblings, so this is how reverse
+    /// iteration finds predecessors.
+    pub(super) fn leaf_for_upper_bound<'s>(
+        &self,
+        hi: &Bound,
+        scope: &'s Scope,
+    ) -> Result<(Node, HPtr<'s, Frag>)> {
+        if let Bound::Inc(ref key) = *hi {
+            return Ok(self.path_for_key(key, scope)?.pop().unwrap());
+        }
+
+        let mut cursor = self.root.load(SeqCst);
+        let mut not_found_loops = 0;
+        loop {
+            let get_cursor = self.pages.get(cursor, scope)?;
+            if get_cursor.is_none() {
+                // restart search from the tree's root
+                not_found_loops += 1;
+                debug_assert_ne!(
+                    not_found_loops,
+                    10_000,
+                    "cannot find pid {} in leaf_for_upper_bound",
+                    cursor
+                );
+                cursor = self.root.load(SeqCst);
+                continue;
+            }
+            let (frag, cas_key) = get_cursor.unwrap();
+            let (node, _is_root) = frag.into_base().unwrap();
+
+            if self.order.lt(&node.hi, hi) {
+                // we have encountered a child split, and the
+                // keys we want live to the right.
+                cursor = node.next.unwrap();
+                continue;
+            }
+
+            match node.data {
+                Data::Index(ref ptrs) => {
+                    let old_cursor = cursor;
+                    for &(ref sep_k, ref ptr) in ptrs {
+                        if self.order.lt(&Bound::Inc(sep_k.clone()), hi) {
+                            cursor = *ptr;
+                        } else {
+                            break; // we've found our next cursor
+                        }
+                    }
+                    if cursor == old_cursor {
+                        panic!("stuck in page traversal loop");
+                    }
+                }
+                Data::Leaf(_) => {
+                    return Ok((node, cas_key));
+                }
+            }
+        }
+    }
+
     #[doc(hidden)]
     pub fn key_debug_str(&self, key: &[u8]) -> String {
         pin(|scope| {
-            let path = self.path_for_key(key, scope);
+            let path = match self.path_for_key(key, scope) {
+                Ok(path) => path,
+                Err(e) => return format!("failed to read path: {}", e),
+            };
             let mut ret = String::new();
             for &(ref node, _) in &path {
                 ret.push_str(&*format!("\n{:?}", node));
@@ -536,12 +2851,13 @@ impl Tree {
     }
 
     /// returns the traversal path, completing any observed
-    /// partially complete splits or merges along the way.
-    fn path_for_key<'s>(
+    /// partially complete splits or merges along the way,
+    /// unless the tree is read-only.
+    pub(super) fn path_for_key<'s>(
         &self,
         key: &[u8],
         scope: &'s Scope,
-    ) -> Vec<(Node, HPtr<'s, Frag>)> {
+    ) -> Result<Vec<(Node, HPtr<'s, Frag>)>> {
         let key_bound = Bound::Inc(key.into());
         let mut cursor = self.root.load(SeqCst);
         let mut path: Vec<(Node, HPtr<'s, Frag>)> = vec![];
@@ -552,7 +2868,7 @@ impl Tree {
 
         let mut not_found_loops = 0;
         loop {
-            let get_cursor = self.pages.get(cursor, scope);
+            let get_cursor = self.pages.get(cursor, scope)?;
             if get_cursor.is_none() {
                 // restart search from the tree's root
                 not_found_loops += 1;
@@ -568,16 +2884,30 @@ impl Tree {
             let (frag, cas_key) = get_cursor.unwrap();
             let (node, _is_root) = frag.into_base().unwrap();
 
-            // TODO this may need to change when handling (half) merges
-            assert!(node.lo <= key_bound, "overshot key somehow");
+            if node.merged && !self.config.get_read_only() {
+                // the keys of this node now belong to a sibling, so
+                // we help finish the merge and then start over.
+                self.complete_merge(node.id, scope)?;
+                cursor = self.root.load(SeqCst);
+                path.clear();
+                unsplit_parent = None;
+                continue;
+            }
+
+            assert!(
+                self.order.le(&node.lo, &key_bound),
+                "overshot key somehow"
+            );
 
             // half-complete split detect & completion
-            if node.hi <= key_bound {
+            if self.order.le(&node.hi, &key_bound) {
                 // println!("{:?} is hi, looking for {:?}", page_view.node.hi, key);
                 // we have encountered a child split, without
                 // having hit the parent split above.
                 cursor = node.next.unwrap();
-                if unsplit_parent.is_none() && !path.is_empty() {
+                if unsplit_parent.is_none() && !path.is_empty() &&
+                    !self.config.get_read_only()
+                {
                     unsplit_parent = Some(path.len() - 1);
                 }
                 continue;
@@ -597,7 +2927,7 @@ impl Tree {
                     parent_cas_key.clone(),
                     ps,
                     scope,
-                );
+                )?;
                 // println!("trying to fix incomplete parent split: {:?}", res);
                 // println!("after: {:?}", self);
             }
@@ -608,7 +2938,8 @@ impl Tree {
                 Data::Index(ref ptrs) => {
                     let old_cursor = cursor;
                     for &(ref sep_k, ref ptr) in ptrs {
-                        if &**sep_k <= key {
+                        let sep_cmp = self.order.cmp_keys(sep_k, key);
+                        if sep_cmp != Ordering::Greater {
                             cursor = *ptr;
                         } else {
                             break; // we've found our next cursor
@@ -624,8 +2955,23 @@ impl Tree {
             }
         }
 
-        path
+        Ok(path)
+    }
+}
+
+/// returns the lowest key that is greater than every key
+/// starting with the provided prefix, or `None` if no such
+/// key exists (the prefix is empty or made entirely of 0xFF).
+/// This holds in lexicographic order only.
+fn prefix_successor(prefix: &[u8]) -> Option<Key> {
+    let mut successor = prefix.to_vec();
+    while let Some(last) = successor.pop() {
+        if last != u8::max_value() {
+            successor.push(last + 1);
+            return Some(successor);
+        }
     }
+    None
 }
 
 impl Debug for Tree {
@@ -640,7 +2986,11 @@ impl Debug for Tree {
 
         pin(|scope| {
             loop {
-                let (frag, _cas_key) = self.pages.get(pid, scope).unwrap();
+                let (frag, _cas_key) = match self.pages.get(pid, scope) {
+                    Ok(Some(got)) => got,
+                    Ok(None) => panic!("tree points to missing pid {}", pid),
+                    Err(_) => return Err(fmt::Error),
+                };
                 let (node, _is_root) = frag.base().unwrap();
 
                 f.write_str("\t\t").unwrap();
@@ -652,7 +3002,16 @@ impl Debug for Tree {
                 } else {
                     // we've traversed our level, time to bump down
                     let (left_frag, _left_cas_key) =
-                        self.pages.get(left_most, scope).unwrap();
+                        match self.pages.get(left_most, scope) {
+                            Ok(Some(got)) => got,
+                            Ok(None) => {
+                                panic!(
+                                    "tree points to missing pid {}",
+                                    left_most
+                                )
+                            }
+                            Err(_) => return Err(fmt::Error),
+                        };
                     let (left_node, _is_root) = left_frag.base().unwrap();
 
                     match left_node.data {
@@ -680,9 +3039,7 @@ impl Debug for Tree {
                     }
                 }
             }
-        });
-
-
-        Ok(())
+            Ok(())
+        })
     }
 }
@@ -0,0 +1,74 @@
+use std::collections::{BTreeMap, HashMap};
+
+use coco::epoch::Scope;
+
+use super::*;
+
+/// A transaction over a `Tree`, passed to the closure given to
+/// `Tree::tx`. Each read remembers the version of the leaf page that
+/// served it, and writes are buffered until the transaction commits.
+pub struct Tx<'a, 's> {
+    tree: &'a Tree,
+    scope: &'s Scope,
+    reads: HashMap<PageID, HPtr<'s, Frag>>,
+    writes: BTreeMap<Key, Option<Value>>,
+}
+
+impl<'a, 's> Tx<'a, 's> {
+    pub(super) fn new(tree: &'a Tree, scope: &'s Scope) -> Tx<'a, 's> {
+        Tx {
+            tree: tree,
+            scope: scope,
+            reads: HashMap::new(),
+            writes: BTreeMap::new(),
+        }
+    }
+
+    /// Retrieve a value, seeing any writes made earlier in this
+    /// transaction.
+    pub fn get(&mut self, key: &[u8]) -> Result<Option<Value>> {
+        if let Some(write) = self.writes.get(key) {
+            return Ok(write.clone());
+        }
+
+        let (mut path, ret) = self.tree.get_internal(key, self.scope)?;
+        let (leaf, cas_key) = path.pop().unwrap();
+
+        // if we have already read from this page, keep the earlier
+        // version, so that a change in between causes a conflict.
+        self.reads.entry(leaf.id).or_insert(cas_key);
+
+        Ok(ret)
+    }
+
+    /// Set a key to a new value when the transaction commits.
+    pub fn set(&mut self, key: Key, value: Value) {
+        self.writes.insert(key, Some(value));
+    }
+
+    /// Delete a key when the transaction commits.
+    pub fn del(&mut self, key: &[u8]) {
+        self.writes.insert(key.to_vec(), None);
+    }
+
+    /// Returns `false` if a page read by this transaction has changed,
+    /// or another transaction is committing to it.
+    pub(super) fn commit(self) -> Result<bool> {
+        if !self.writes.is_empty() {
+            if self.tree.config.get_read_only() {
+                return Err(Error::ReadOnly);
+            }
+            return self.tree.commit_tx(&self.reads, self.writes, self.scope);
+        }
+
+        // without writes, the reads only need to hold when we check
+        // them, as each one held from when it was read until then.
+        for (&pid, observed) in &self.reads {
+            if !self.tree.read_holds(pid, observed, self.scope)? {
+                trace!("tx conflict on pid {}", pid);
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
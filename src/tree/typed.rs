@@ -0,0 +1,200 @@
+use std::marker::PhantomData;
+use std::ops::RangeBounds;
+
+use bincode::{Infinite, deserialize, serialize};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+use super::*;
+
+/// A `Tree` whose keys are `OrderedKey`s and whose values are
+/// serialized with `bincode`. Iteration visits keys in the order of
+/// `K` itself, including for signed integers and strings.
+///
+/// # Examples
+///
+/// ```
+/// use sled::{Config, TypedTree};
+/// let t: TypedTree<(String, i64), Vec<u32>> =
+///     TypedTree::new(Config::default().tree().unwrap());
+///
+/// t.set(&("a".to_owned(), 5), &vec![1]).unwrap();
+/// t.set(&("a".to_owned(), -5), &vec![2, 3]).unwrap();
+/// t.set(&("".to_owned(), 0), &vec![]).unwrap();
+/// assert_eq!(t.get(&("a".to_owned(), -5)), Ok(Some(vec![2, 3])));
+///
+/// let keys: Vec<_> = t.iter().map(|res| res.unwrap().0).collect();
+/// assert_eq!(
+///     keys,
+///     vec![("".to_owned(), 0), ("a".to_owned(), -5), ("a".to_owned(), 5)]
+/// );
+/// ```
+pub struct TypedTree<K, V> {
+    tree: Tree,
+    // fn() keeps us Send and Sync regardless of K and V, since we
+    // only ever hold them in their encoded form.
+    _marker: PhantomData<fn() -> (K, V)>,
+}
+
+impl<K, V> TypedTree<K, V>
+    where K: OrderedKey,
+          V: Serialize + DeserializeOwned
+{
+    /// Wrap a `Tree`, which should only be written through
+    /// `TypedTree`s with the same `K` and `V`. Reading a key or value
+    /// that was written otherwise returns `Error::Unsupported`.
+    pub fn new(tree: Tree) -> TypedTree<K, V> {
+        TypedTree {
+            tree: tree,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the underlying `Tree`.
+    pub fn tree(&self) -> &Tree {
+        &self.tree
+    }
+
+    /// Retrieve a value from the `TypedTree` if it exists.
+    pub fn get(&self, key: &K) -> Result<Option<V>> {
+        let got = self.tree.get(&*encode_key(key))?;
+        got.map(|v| decode_value(&*v)).transpose()
+    }
+
+    /// Set a key to a new value.
+    pub fn set(&self, key: &K, value: &V) -> Result<()> {
+        self.tree.set(encode_key(key), encode_value(value))
+    }
+
+    /// Delete a value, returning the last result if it existed.
+    pub fn del(&self, key: &K) -> Result<Option<V>> {
+        let deleted = self.tree.del(&*encode_key(key))?;
+        deleted.map(|v| decode_value(&*v)).transpose()
+    }
+
+    /// Compare and swap, with the same semantics as `Tree::cas`.
+    /// Values are compared in their serialized form.
+    pub fn cas(
+        &self,
+        key: &K,
+        old: Option<&V>,
+        new: Option<&V>,
+    ) -> Result<std::result::Result<(), Option<V>>> {
+        let old = old.map(encode_value);
+        let new = new.map(encode_value);
+        match self.tree.cas(encode_key(key), old, new)? {
+            Ok(()) => Ok(Ok(())),
+            Err(cur) => {
+                let cur = cur.map(|v| decode_value(&*v)).transpose()?;
+                Ok(Err(cur))
+            }
+        }
+    }
+
+    /// Iterate over all keys and values in order.
+    pub fn iter(&self) -> TypedIter<K, V> {
+        TypedIter::new(self.tree.iter())
+    }
+
+    /// Iterate over keys and values, starting at the provided key.
+    pub fn scan(&self, key: &K) -> TypedIter<K, V> {
+        TypedIter::new(self.tree.scan(&*encode_key(key)))
+    }
+
+    /// Iterate over keys and values within the provided range.
+    pub fn range<R>(&self, range: R) -> TypedIter<K, V>
+        where R: RangeBounds<K>
+    {
+        use std::ops::Bound::{Excluded, Included, Unbounded};
+
+        let encode_bound = |bound| match bound {
+            Included(k) => Included(encode_key(k)),
+            Excluded(k) => Excluded(encode_key(k)),
+            Unbounded => Unbounded,
+        };
+        let start = encode_bound(range.start_bound());
+        let end = encode_bound(range.end_bound());
+
+        TypedIter::new(self.tree.range::<Vec<u8>, _>((start, end)))
+    }
+}
+
+/// An iterator over keys and values in a `TypedTree`, which yields
+/// the errors of the underlying `Iter`, and those of decoding what
+/// it returns.
+pub struct TypedIter<'a, K, V> {
+    inner: Iter<'a>,
+    _marker: PhantomData<fn() -> (K, V)>,
+}
+
+impl<'a, K, V> TypedIter<'a, K, V> {
+    fn new(inner: Iter<'a>) -> TypedIter<'a, K, V> {
+        TypedIter {
+            inner: inner,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for TypedIter<'a, K, V>
+    where K: OrderedKey,
+          V: DeserializeOwned
+{
+    type Item = Result<(K, V)>;
+
+    fn next(&mut self) -> Option<Result<(K, V)>> {
+        self.inner.next().map(|res| res.and_then(decode_pair))
+    }
+}
+
+impl<'a, K, V> DoubleEndedIterator for TypedIter<'a, K, V>
+    where K: OrderedKey,
+          V: DeserializeOwned
+{
+    fn next_back(&mut self) -> Option<Result<(K, V)>> {
+        self.inner.next_back().map(|res| res.and_then(decode_pair))
+    }
+}
+
+fn encode_key<K: OrderedKey>(key: &K) -> Key {
+    let mut encoded = vec![];
+    key.encode_key(&mut encoded);
+    encoded
+}
+
+fn decode_key<K: OrderedKey>(bytes: &[u8]) -> Result<K> {
+    let mut encoded = bytes;
+    match K::decode_key(&mut encoded) {
+        Some(key) => {
+            if encoded.is_empty() {
+                Ok(key)
+            } else {
+                Err(Error::Unsupported(format!(
+                    "TypedTree found trailing bytes {:?} after key {:?}",
+                    encoded,
+                    bytes
+                )))
+            }
+        }
+        None => Err(Error::Unsupported(
+            format!("TypedTree can't decode key {:?}", bytes),
+        )),
+    }
+}
+
+fn encode_value<V: Serialize>(value: &V) -> Value {
+    serialize(value, Infinite).unwrap()
+}
+
+fn decode_value<V: DeserializeOwned>(encoded: &[u8]) -> Result<V> {
+    deserialize(encoded).map_err(|e| {
+        Error::Unsupported(format!("TypedTree can't decode value: {}", e))
+    })
+}
+
+fn decode_pair<K, V>((k, v): (Key, Value)) -> Result<(K, V)>
+    where K: OrderedKey,
+          V: DeserializeOwned
+{
+    Ok((decode_key(&*k)?, decode_value(&*v)?))
+}
@@ -25,12 +25,13 @@ type LogID = u64;
 #[ignore]
 fn more_log_reservations_than_buffers() {
     let config = Config::default();
-    let log = config.log();
+    let log = config.log().unwrap();
     let mut reservations = vec![];
     for _ in 0..config.get_io_bufs() + 1 {
-        reservations.push(log.reserve(
-            vec![0; config.get_io_buf_size() - MSG_HEADER_LEN],
-        ))
+        reservations.push(
+            log.reserve(vec![0; config.get_io_buf_size() - MSG_HEADER_LEN])
+                .unwrap(),
+        )
     }
     for res in reservations.into_iter().rev() {
         // abort in reverse order
@@ -41,24 +42,24 @@ fn more_log_reservations_than_buffers() {
 #[test]
 fn non_contiguous_log_flush() {
     let conf = Config::default().io_buf_size(1000);
-    let log = conf.log();
+    let log = conf.log().unwrap();
 
     let overhead = MSG_HEADER_LEN + SEG_HEADER_LEN + SEG_TRAILER_LEN;
     let buf_len = conf.get_io_buf_size() - overhead;
-    let res1 = log.reserve(vec![0; buf_len]);
-    let res2 = log.reserve(vec![0; buf_len]);
+    let res1 = log.reserve(vec![0; buf_len]).unwrap();
+    let res2 = log.reserve(vec![0; buf_len]).unwrap();
     let id = res2.lid();
     let lsn = res2.lsn();
     res2.abort();
     res1.abort();
-    log.make_stable(lsn);
+    log.make_stable(lsn).unwrap();
 }
 
 #[test]
 fn concurrent_logging() {
     // TODO linearize res bufs, verify they are correct
     let conf = Config::default().io_buf_size(1000).flush_every_ms(Some(50));
-    let log = Arc::new(conf.log());
+    let log = Arc::new(conf.log().unwrap());
     let iobs2 = log.clone();
     let iobs3 = log.clone();
     let iobs4 = log.clone();
@@ -70,7 +71,7 @@ fn concurrent_logging() {
         .name("c1".to_string())
         .spawn(move || for i in 0..1_000 {
             let buf = vec![1; i % 896];
-            log.write(buf);
+            log.write(buf).unwrap();
         })
         .unwrap();
 
@@ -78,7 +79,7 @@ fn concurrent_logging() {
         .name("c2".to_string())
         .spawn(move || for i in 0..1_000 {
             let buf = vec![2; i % 896];
-            iobs2.write(buf);
+            iobs2.write(buf).unwrap();
         })
         .unwrap();
 
@@ -86,7 +87,7 @@ fn concurrent_logging() {
         .name("c3".to_string())
         .spawn(move || for i in 0..1_000 {
             let buf = vec![3; i % 896];
-            iobs3.write(buf);
+            iobs3.write(buf).unwrap();
         })
         .unwrap();
 
@@ -94,7 +95,7 @@ fn concurrent_logging() {
         .name("c4".to_string())
         .spawn(move || for i in 0..1_000 {
             let buf = vec![4; i % 896];
-            iobs4.write(buf);
+            iobs4.write(buf).unwrap();
         })
         .unwrap();
 
@@ -102,7 +103,7 @@ fn concurrent_logging() {
         .name("c5".to_string())
         .spawn(move || for i in 0..1_000 {
             let buf = vec![5; i % 896];
-            iobs5.write(buf);
+            iobs5.write(buf).unwrap();
         })
         .unwrap();
 
@@ -110,9 +111,9 @@ fn concurrent_logging() {
         .name("c6".to_string())
         .spawn(move || for i in 0..1_000 {
             let buf = vec![6; i % 896];
-            let (lsn, _lid) = iobs6.write(buf);
+            let (lsn, _lid) = iobs6.write(buf).unwrap();
             // println!("+");
-            iobs6.make_stable(lsn);
+            iobs6.make_stable(lsn).unwrap();
             // println!("-");
         })
         .unwrap();
@@ -127,13 +128,13 @@ fn concurrent_logging() {
 
 fn write(log: &Log) {
     let data_bytes = b"yoyoyoyo";
-    let (lsn, lid) = log.write(data_bytes.to_vec());
+    let (lsn, lid) = log.write(data_bytes.to_vec()).unwrap();
     let (_, read_buf, _) = log.read(lsn, lid).unwrap().unwrap();
     assert_eq!(read_buf, data_bytes);
 }
 
 fn abort(log: &Log) {
-    let res = log.reserve(vec![0; 5]);
+    let res = log.reserve(vec![0; 5]).unwrap();
     let (lsn, lid) = res.abort();
     match log.read(lsn, lid) {
         Ok(LogRead::Flush(_, _, _)) => {
@@ -145,7 +146,7 @@ fn abort(log: &Log) {
 
 #[test]
 fn log_aborts() {
-    let log = Config::default().log();
+    let log = Config::default().log().unwrap();
     write(&log);
     abort(&log);
     write(&log);
@@ -157,26 +158,26 @@ fn log_aborts() {
 #[test]
 fn log_iterator() {
     let conf = Config::default().io_buf_size(1000);
-    let log = conf.log();
-    let (first_lsn, _) = log.write(b"".to_vec());
-    log.write(b"1".to_vec());
-    log.write(b"22".to_vec());
-    log.write(b"333".to_vec());
+    let log = conf.log().unwrap();
+    let (first_lsn, _) = log.write(b"".to_vec()).unwrap();
+    log.write(b"1".to_vec()).unwrap();
+    log.write(b"22".to_vec()).unwrap();
+    log.write(b"333".to_vec()).unwrap();
 
     // stick an abort in the middle, which should not be
     // returned
     {
-        let res = log.reserve(b"never_gonna_hit_disk".to_vec());
+        let res = log.reserve(b"never_gonna_hit_disk".to_vec()).unwrap();
         res.abort();
     }
 
-    log.write(b"4444".to_vec());
-    let (last_lsn, _) = log.write(b"55555".to_vec());
-    log.make_stable(last_lsn);
+    log.write(b"4444".to_vec()).unwrap();
+    let (last_lsn, _) = log.write(b"55555".to_vec()).unwrap();
+    log.make_stable(last_lsn).unwrap();
 
     drop(log);
 
-    let log = conf.log();
+    let log = conf.log().unwrap();
 
     let mut iter = log.iter_from(first_lsn);
     assert_eq!(iter.next().unwrap().2, b"".to_vec());
@@ -245,12 +246,12 @@ fn snapshot_with_out_of_order_buffers() {
     let len = conf.get_io_buf_size() - SEG_HEADER_LEN - SEG_TRAILER_LEN -
         MSG_HEADER_LEN;
 
-    let log = conf.log();
+    let log = conf.log().unwrap();
 
     for i in 0..4 {
         let buf = vec![i as u8; len];
-        let (lsn, _lid) = log.write(buf);
-        log.make_stable(lsn);
+        let (lsn, _lid) = log.write(buf).unwrap();
+        log.make_stable(lsn).unwrap();
     }
 
     {
@@ -260,7 +261,7 @@ fn snapshot_with_out_of_order_buffers() {
 
     drop(log);
 
-    let log = conf.log();
+    let log = conf.log().unwrap();
 
     // start iterating just past the first segment header
     let mut iter = log.iter_from(SEG_HEADER_LEN as Lsn);
@@ -279,16 +280,16 @@ fn multi_segment_log_iteration() {
     let conf = Config::default().io_buf_size(100);
     let len = conf.get_io_buf_size() - SEG_HEADER_LEN - SEG_TRAILER_LEN -
         MSG_HEADER_LEN;
-    let log = conf.log();
+    let log = conf.log().unwrap();
 
     for i in 0..conf.get_io_bufs() * 2 {
         let buf = vec![i as u8; len];
-        log.write(buf);
+        log.write(buf).unwrap();
     }
 
     drop(log);
 
-    let log = conf.log();
+    let log = conf.log().unwrap();
 
     // start iterating just past the first segment header
     let mut iter = log.iter_from(SEG_HEADER_LEN as Lsn);
@@ -360,7 +361,7 @@ fn prop_log_works(ops: OpVec) -> bool {
     // println!("testing {:?}", ops);
 
     let mut tip = 0;
-    let mut log = config.log();
+    let mut log = config.log().unwrap();
     let mut reference: Vec<(Lsn, LogID, Option<Vec<u8>>, usize)> = vec![];
 
     for op in ops.ops.into_iter() {
@@ -382,19 +383,19 @@ fn prop_log_works(ops: OpVec) -> bool {
             }
             Write(buf) => {
                 let len = buf.len();
-                let (lsn, lid) = log.write(buf.clone());
+                let (lsn, lid) = log.write(buf.clone()).unwrap();
                 tip = lid as usize + len + MSG_HEADER_LEN;
                 reference.push((lsn, lid, Some(buf), len));
             }
             WriteReservation(buf) => {
                 let len = buf.len();
-                let (lsn, lid) = log.write(buf.clone());
+                let (lsn, lid) = log.write(buf.clone()).unwrap();
                 tip = lid as usize + len + MSG_HEADER_LEN;
                 reference.push((lsn, lid, Some(buf), len));
             }
             AbortReservation(buf) => {
                 let len = buf.len();
-                let res = log.reserve(buf.clone());
+                let res = log.reserve(buf.clone()).unwrap();
                 let lsn = res.lsn();
                 let lid = res.lid();
                 res.abort();
@@ -417,7 +418,7 @@ fn prop_log_works(ops: OpVec) -> bool {
                         break;
                     }
                 }
-                log = config.log();
+                log = config.log().unwrap();
             }
             Truncate(new_len) => {
                 #[cfg(target_os = "linux")]
@@ -454,7 +455,7 @@ fn prop_log_works(ops: OpVec) -> bool {
                     use std::os::unix::io::AsRawFd;
 
                     {
-                        let cached_f = config.cached_file();
+                        let cached_f = config.cached_file().unwrap();
                         let f = cached_f.borrow_mut();
                         use libc::ftruncate;
                         let fd = f.as_raw_fd();
@@ -464,7 +465,7 @@ fn prop_log_works(ops: OpVec) -> bool {
                         }
                     }
 
-                    log = config.log();
+                    log = config.log().unwrap();
                 }
             }
         }
@@ -44,22 +44,22 @@ fn pagecache_caching() {
         .snapshot_after_ops(1_000_000)
         .io_buf_size(5000);
 
-    let mut pc = PageCache::new(TestMaterializer, conf.clone());
-    pc.recover();
+    let mut pc = PageCache::new(TestMaterializer, conf.clone()).unwrap();
+    pc.recover().unwrap();
 
     pin(|scope| {
         let mut keys = HashMap::new();
 
         for _ in 0..2 {
-            let (id, key) = pc.allocate(scope);
-            let key = pc.replace(id, key, vec![0], scope).unwrap();
+            let (id, key) = pc.allocate(scope).unwrap();
+            let key = pc.replace(id, key, vec![0], scope).unwrap().unwrap();
             keys.insert(id, key);
         }
 
         for i in 0..1000 {
             let id = i as usize % 2;
-            let (_, key) = pc.get(id, scope).unwrap();
-            let key = pc.link(id, key, vec![i], scope).unwrap();
+            let (_, key) = pc.get(id, scope).unwrap().unwrap();
+            let key = pc.link(id, key, vec![i], scope).unwrap().unwrap();
             keys.insert(id, key);
         }
     });
@@ -75,28 +75,28 @@ fn pagecache_strange_crash_1() {
         .io_buf_size(5000);
 
     {
-        let mut pc = PageCache::new(TestMaterializer, conf.clone());
-        pc.recover();
+        let mut pc = PageCache::new(TestMaterializer, conf.clone()).unwrap();
+        pc.recover().unwrap();
 
         pin(|scope| {
             let mut keys = HashMap::new();
             for _ in 0..2 {
-                let (id, key) = pc.allocate(scope);
-                let key = pc.replace(id, key, vec![0], scope).unwrap();
+                let (id, key) = pc.allocate(scope).unwrap();
+                let key = pc.replace(id, key, vec![0], scope).unwrap().unwrap();
                 keys.insert(id, key);
             }
 
             for i in 0..1000 {
                 let id = i as usize % 2;
-                let (_, key) = pc.get(id, scope).unwrap();
-                let key = pc.link(id, key, vec![i], scope).unwrap();
+                let (_, key) = pc.get(id, scope).unwrap().unwrap();
+                let key = pc.link(id, key, vec![i], scope).unwrap().unwrap();
                 keys.insert(id, key);
             }
         });
     }
     println!("!!!!!!!!!!!!!!!!!!!!! recovering !!!!!!!!!!!!!!!!!!!!!!");
-    let mut pc = PageCache::new(TestMaterializer, conf.clone());
-    pc.recover();
+    let mut pc = PageCache::new(TestMaterializer, conf.clone()).unwrap();
+    pc.recover().unwrap();
     // TODO test no eaten lsn's on recovery
     // TODO test that we don't skip multiple segments ahead on recovery (confusing Lsn & Lid)
 }
@@ -113,23 +113,24 @@ fn pagecache_strange_crash_2() {
                 .io_buf_size(5000);
 
             println!("!!!!!!!!!!!!!!!!!!!!! {} !!!!!!!!!!!!!!!!!!!!!!", x);
-            let mut pc = PageCache::new(TestMaterializer, conf.clone());
-            pc.recover();
+            let mut pc =
+                PageCache::new(TestMaterializer, conf.clone()).unwrap();
+            pc.recover().unwrap();
 
             let mut keys = HashMap::new();
             for _ in 0..2 {
-                let (id, key) = pc.allocate(scope);
-                let key = pc.replace(id, key, vec![0], scope).unwrap();
+                let (id, key) = pc.allocate(scope).unwrap();
+                let key = pc.replace(id, key, vec![0], scope).unwrap().unwrap();
                 keys.insert(id, key);
             }
 
             for i in 0..1000 {
                 let id = i as usize % 2;
                 println!("------ beginning op on pid {} ------", id);
-                let (_, key) = pc.get(id, scope).unwrap();
+                let (_, key) = pc.get(id, scope).unwrap().unwrap();
                 println!("got key {:?} for pid {}", key, id);
                 assert!(!key.is_null());
-                let key_res = pc.link(id, key, vec![i], scope);
+                let key_res = pc.link(id, key, vec![i], scope).unwrap();
                 if key_res.is_err() {
                     println!("failed linking pid {}", id);
                 }
@@ -140,44 +141,237 @@ fn pagecache_strange_crash_2() {
     }
 }
 
+#[test]
+fn pagecache_get_at() {
+    let conf = Config::default().flush_every_ms(None).io_buf_size(200);
+
+    let mut pc = PageCache::new(TestMaterializer, conf.clone()).unwrap();
+    pc.recover().unwrap();
+
+    pin(|scope| {
+        let (id, key) = pc.allocate(scope).unwrap();
+        let key = pc.replace(id, key, vec![1], scope).unwrap().unwrap();
+        let key = pc.link(id, key, vec![2], scope).unwrap().unwrap();
+
+        let lsn = pc.pin_lsn().unwrap();
+        let key = pc.link(id, key, vec![3], scope).unwrap().unwrap();
+        assert_eq!(pc.get_at(id, lsn, scope).unwrap(), Some(vec![1, 2]));
+
+        // versions replaced while the lsn is pinned are kept around
+        let _key = pc.replace(id, key, vec![4], scope).unwrap().unwrap();
+        assert_eq!(pc.get_at(id, lsn, scope).unwrap(), Some(vec![1, 2]));
+        pc.free(id).unwrap();
+        assert_eq!(pc.get_at(id, lsn, scope).unwrap(), Some(vec![1, 2]));
+        assert!(pc.get(id, scope).unwrap().is_none());
+        pc.unpin_lsn(lsn);
+    });
+}
+
+#[test]
+fn pagecache_get_at_bounds_history() {
+    let conf = Config::default().flush_every_ms(None).io_buf_size(200);
+
+    let mut pc = PageCache::new(TestMaterializer, conf.clone()).unwrap();
+    pc.recover().unwrap();
+
+    pin(|scope| {
+        let (id, key) = pc.allocate(scope).unwrap();
+        let mut key = pc.replace(id, key, vec![1], scope).unwrap().unwrap();
+
+        let lsn = pc.pin_lsn().unwrap();
+        let (new_id, mut new_key) = pc.allocate(scope).unwrap();
+        for i in 2..100 {
+            key = pc.replace(id, key, vec![i], scope).unwrap().unwrap();
+            new_key =
+                pc.replace(new_id, new_key, vec![i], scope).unwrap().unwrap();
+        }
+
+        // only the version of the first page at the pinned lsn is kept
+        assert_eq!(pc.history_len(), 1);
+        assert_eq!(pc.get_at(id, lsn, scope).unwrap(), Some(vec![1]));
+        assert_eq!(pc.get_at(new_id, lsn, scope).unwrap(), None);
+
+        let later = pc.pin_lsn().unwrap();
+        key = pc.replace(id, key, vec![100], scope).unwrap().unwrap();
+        assert_eq!(pc.history_len(), 2);
+        pc.unpin_lsn(lsn);
+        assert_eq!(pc.history_len(), 1);
+        assert_eq!(pc.get_at(id, later, scope).unwrap(), Some(vec![99]));
+
+        let _key = pc.replace(id, key, vec![101], scope).unwrap().unwrap();
+        assert_eq!(pc.history_len(), 1);
+        pc.unpin_lsn(later);
+        assert_eq!(pc.history_len(), 0);
+    });
+}
+
 #[test]
 fn basic_pagecache_recovery() {
     let conf = Config::default().flush_every_ms(None).io_buf_size(200);
 
-    let mut pc = PageCache::new(TestMaterializer, conf.clone());
+    let mut pc = PageCache::new(TestMaterializer, conf.clone()).unwrap();
 
     pin(|scope| {
-        pc.recover();
-        let (id, key) = pc.allocate(scope);
-        let key = pc.replace(id, key, vec![1], scope).unwrap();
-        let key = pc.link(id, key, vec![2], scope).unwrap();
-        let _key = pc.link(id, key, vec![3], scope).unwrap();
-        let (consolidated, _) = pc.get(id, scope).unwrap();
+        pc.recover().unwrap();
+        let (id, key) = pc.allocate(scope).unwrap();
+        let key = pc.replace(id, key, vec![1], scope).unwrap().unwrap();
+        let key = pc.link(id, key, vec![2], scope).unwrap().unwrap();
+        let _key = pc.link(id, key, vec![3], scope).unwrap().unwrap();
+        let (consolidated, _) = pc.get(id, scope).unwrap().unwrap();
         assert_eq!(consolidated, vec![1, 2, 3]);
         drop(pc);
 
-        let mut pc2 = PageCache::new(TestMaterializer, conf.clone());
-        pc2.recover();
-        let (consolidated2, key) = pc2.get(id, scope).unwrap();
+        let mut pc2 = PageCache::new(TestMaterializer, conf.clone()).unwrap();
+        pc2.recover().unwrap();
+        let (consolidated2, key) = pc2.get(id, scope).unwrap().unwrap();
         assert_eq!(consolidated, consolidated2);
 
-        pc2.link(id, key, vec![4], scope).unwrap();
+        pc2.link(id, key, vec![4], scope).unwrap().unwrap();
         drop(pc2);
 
-        let mut pc3 = PageCache::new(TestMaterializer, conf.clone());
-        pc3.recover();
-        let (consolidated3, _key) = pc3.get(id, scope).unwrap();
+        let mut pc3 = PageCache::new(TestMaterializer, conf.clone()).unwrap();
+        pc3.recover().unwrap();
+        let (consolidated3, _key) = pc3.get(id, scope).unwrap().unwrap();
         assert_eq!(consolidated3, vec![1, 2, 3, 4]);
-        pc3.free(id);
+        pc3.free(id).unwrap();
         drop(pc3);
 
-        let mut pc4 = PageCache::new(TestMaterializer, conf.clone());
-        pc4.recover();
-        let res = pc4.get(id, scope);
+        let mut pc4 = PageCache::new(TestMaterializer, conf.clone()).unwrap();
+        pc4.recover().unwrap();
+        let res = pc4.get(id, scope).unwrap();
         assert!(res.is_none());
     })
 }
 
+#[test]
+fn pagecache_read_only() {
+    let conf = Config::default().flush_every_ms(None).io_buf_size(200);
+
+    let mut pc = PageCache::new(TestMaterializer, conf.clone()).unwrap();
+    pc.recover().unwrap();
+    let id = pin(|scope| {
+        let (id, key) = pc.allocate(scope).unwrap();
+        let key = pc.replace(id, key, vec![1], scope).unwrap().unwrap();
+        pc.link(id, key, vec![2], scope).unwrap().unwrap();
+        id
+    });
+    drop(pc);
+
+    let conf = conf.read_only(true);
+    let mut pc = PageCache::new(TestMaterializer, conf.clone()).unwrap();
+    pc.recover().unwrap();
+    pin(|scope| {
+        let (consolidated, key) = pc.get(id, scope).unwrap().unwrap();
+        assert_eq!(consolidated, vec![1, 2]);
+
+        let lsn = pc.pin_lsn().unwrap();
+        assert_eq!(pc.get_at(id, lsn, scope).unwrap(), Some(vec![1, 2]));
+        pc.unpin_lsn(lsn);
+
+        assert_eq!(
+            pc.link(id, key.clone(), vec![3], scope).err(),
+            Some(Error::ReadOnly)
+        );
+        assert_eq!(
+            pc.replace(id, key, vec![3], scope).err(),
+            Some(Error::ReadOnly)
+        );
+        assert_eq!(pc.allocate(scope).err(), Some(Error::ReadOnly));
+        assert_eq!(pc.free(id), Err(Error::ReadOnly));
+        let (consolidated, _key) = pc.get(id, scope).unwrap().unwrap();
+        assert_eq!(consolidated, vec![1, 2]);
+    });
+}
+
+#[test]
+fn pagecache_read_only_consolidation() {
+    let conf = Config::default()
+        .flush_every_ms(None)
+        .io_buf_size(200)
+        .page_consolidation_threshold(4);
+
+    let mut pc = PageCache::new(TestMaterializer, conf.clone()).unwrap();
+    pc.recover().unwrap();
+    let id = pin(|scope| {
+        let (id, mut key) = pc.allocate(scope).unwrap();
+        key = pc.replace(id, key, vec![0], scope).unwrap().unwrap();
+        for i in 1..10 {
+            key = pc.link(id, key, vec![i], scope).unwrap().unwrap();
+        }
+        id
+    });
+    drop(pc);
+
+    // the page has more frags than the threshold, but a read-only
+    // page cache can only merge them in memory.
+    let conf = conf.read_only(true);
+    let mut pc = PageCache::new(TestMaterializer, conf.clone()).unwrap();
+    pc.recover().unwrap();
+    pin(|scope| {
+        let expected: Vec<usize> = (0..10).collect();
+        let (consolidated, _key) = pc.get(id, scope).unwrap().unwrap();
+        assert_eq!(consolidated, expected);
+        let (consolidated, _key) = pc.get(id, scope).unwrap().unwrap();
+        assert_eq!(consolidated, expected);
+    });
+}
+
+#[test]
+fn pagecache_batch_recovery() {
+    let conf = Config::default().flush_every_ms(None).io_buf_size(200);
+
+    let mut pc = PageCache::new(TestMaterializer, conf.clone()).unwrap();
+
+    pin(|scope| {
+        pc.recover().unwrap();
+        let (id, key) = pc.allocate(scope).unwrap();
+        let key = pc.replace(id, key, vec![1], scope).unwrap().unwrap();
+
+        // a committed batch is recovered
+        let batch = pc.begin_batch().unwrap();
+        let key =
+            pc.link_in_batch(id, key, vec![2], batch, scope).unwrap().unwrap();
+        let key =
+            pc.link_in_batch(id, key, vec![3], batch, scope).unwrap().unwrap();
+        pc.commit_batch(batch).unwrap();
+
+        // an aborted batch is not
+        let batch = pc.begin_batch().unwrap();
+        let key =
+            pc.link_in_batch(id, key, vec![4], batch, scope).unwrap().unwrap();
+        pc.abort_batch(batch).unwrap();
+
+        // nor is a batch that is never closed, but the updates that
+        // are logged while it is open still are
+        let batch = pc.begin_batch().unwrap();
+        let key =
+            pc.link_in_batch(id, key, vec![5], batch, scope).unwrap().unwrap();
+        let (other_id, other_key) = pc.allocate(scope).unwrap();
+        pc.replace(other_id, other_key, vec![6], scope).unwrap().unwrap();
+        let key = pc.link(id, key, vec![7], scope).unwrap().unwrap();
+        let _key =
+            pc.link_in_batch(id, key, vec![8], batch, scope).unwrap().unwrap();
+        drop(pc);
+
+        let mut pc2 = PageCache::new(TestMaterializer, conf.clone()).unwrap();
+        pc2.recover().unwrap();
+        let (consolidated, key) = pc2.get(id, scope).unwrap().unwrap();
+        assert_eq!(consolidated, vec![1, 2, 3, 7]);
+        let (other, _key) = pc2.get(other_id, scope).unwrap().unwrap();
+        assert_eq!(other, vec![6]);
+
+        // the abandoned batch stays abandoned after more writes and
+        // restarts
+        pc2.link(id, key, vec![9], scope).unwrap().unwrap();
+        drop(pc2);
+
+        let mut pc3 = PageCache::new(TestMaterializer, conf.clone()).unwrap();
+        pc3.recover().unwrap();
+        let (consolidated, _key) = pc3.get(id, scope).unwrap().unwrap();
+        assert_eq!(consolidated, vec![1, 2, 3, 7, 9]);
+    })
+}
+
 #[derive(Debug, Clone)]
 enum Op {
     Replace(PageID, usize),
@@ -270,8 +464,8 @@ fn prop_pagecache_works(ops: OpVec, cache_fixup_threshold: u8) -> bool {
         .cache_capacity(40)
         .cache_fixup_threshold(cache_fixup_threshold as usize);
 
-    let mut pc = PageCache::new(TestMaterializer, config.clone());
-    pc.recover();
+    let mut pc = PageCache::new(TestMaterializer, config.clone()).unwrap();
+    pc.recover().unwrap();
 
     let mut reference: HashMap<PageID, Vec<usize>> = HashMap::new();
 
@@ -289,11 +483,13 @@ fn prop_pagecache_works(ops: OpVec, cache_fixup_threshold: u8) -> bool {
                         let old_key = if existing.is_empty() {
                             Ptr::null().into()
                         } else {
-                            let (_, old_key) = pc.get(pid, scope).unwrap();
+                            let (_, old_key) =
+                                pc.get(pid, scope).unwrap().unwrap();
                             old_key
                         };
 
                         pc.replace(pid, old_key.clone(), vec![c], scope)
+                            .unwrap()
                             .unwrap();
                     });
                     existing.clear();
@@ -302,7 +498,7 @@ fn prop_pagecache_works(ops: OpVec, cache_fixup_threshold: u8) -> bool {
                     pin(|scope| {
                         let res =
                             pc.replace(pid, bad_ptr.into(), vec![c], scope);
-                        assert!(res.unwrap_err().is_none());
+                        assert!(res.unwrap().unwrap_err().is_none());
                     })
                 }
             }
@@ -315,24 +511,27 @@ fn prop_pagecache_works(ops: OpVec, cache_fixup_threshold: u8) -> bool {
                         let old_key = if existing.is_empty() {
                             Ptr::null().into()
                         } else {
-                            let (_, old_key) = pc.get(pid, scope).unwrap();
+                            let (_, old_key) =
+                                pc.get(pid, scope).unwrap().unwrap();
                             old_key
                         };
 
-                        pc.link(pid, old_key.clone(), vec![c], scope).unwrap();
+                        pc.link(pid, old_key.clone(), vec![c], scope)
+                            .unwrap()
+                            .unwrap();
                     });
                     existing.push(c);
                 } else {
                     pin(|scope| {
                         let res = pc.link(pid, bad_ptr, vec![c], scope);
-                        assert!(res.unwrap_err().is_none());
+                        assert!(res.unwrap().unwrap_err().is_none());
                     });
                 }
             }
             Get(pid) => {
                 pin(|scope| {
                     let r = reference.get(&pid).cloned();
-                    let a = pc.get(pid, scope).map(|(a, _)| a);
+                    let a = pc.get(pid, scope).unwrap().map(|(a, _)| a);
                     if let Some(ref s) = r {
                         if s.is_empty() {
                             assert_eq!(a, None);
@@ -354,19 +553,19 @@ fn prop_pagecache_works(ops: OpVec, cache_fixup_threshold: u8) -> bool {
                 });
             }
             Free(pid) => {
-                pc.free(pid);
+                pc.free(pid).unwrap();
                 reference.remove(&pid);
             }
             Allocate => {
                 pin(|scope| {
-                    let (pid, _key) = pc.allocate(scope);
+                    let (pid, _key) = pc.allocate(scope).unwrap();
                     reference.insert(pid, vec![]);
                 });
             }
             Restart => {
                 drop(pc);
-                pc = PageCache::new(TestMaterializer, config.clone());
-                pc.recover();
+                pc = PageCache::new(TestMaterializer, config.clone()).unwrap();
+                pc.recover().unwrap();
             }
         }
     }
@@ -2,6 +2,7 @@ extern crate quickcheck;
 extern crate rand;
 extern crate sled;
 
+use std::cmp::Ordering;
 use std::collections::BTreeMap;
 use std::thread;
 use std::sync::Arc;
@@ -49,16 +50,16 @@ fn parallel_tree_ops() {
 
     println!("========== initial sets ==========");
     let conf = Config::default().blink_fanout(2);
-    let t = Arc::new(conf.tree());
+    let t = Arc::new(conf.tree().unwrap());
     par!{t, |tree: &Tree, k: Vec<u8>| {
-        assert_eq!(tree.get(&*k), None);
-        tree.set(k.clone(), k.clone());
-        assert_eq!(tree.get(&*k), Some(k));
+        assert_eq!(tree.get(&*k).unwrap(), None);
+        tree.set(k.clone(), k.clone()).unwrap();
+        assert_eq!(tree.get(&*k).unwrap(), Some(k));
     }};
 
     println!("========== reading sets ==========");
     par!{t, |tree: &Tree, k: Vec<u8>| {
-        if tree.get(&*k.clone()) != Some(k.clone()) {
+        if tree.get(&*k.clone()).unwrap() != Some(k.clone()) {
             println!("{}", tree.key_debug_str(&*k.clone()));
             panic!("expected key {:?} not found", k);
         }
@@ -69,23 +70,23 @@ fn parallel_tree_ops() {
         let k1 = k.clone();
         let mut k2 = k.clone();
         k2.reverse();
-        tree.cas(k1.clone(), Some(k1), Some(k2)).unwrap();
+        tree.cas(k1.clone(), Some(k1), Some(k2)).unwrap().unwrap();
     }};
 
     par!{t, |tree: &Tree, k: Vec<u8>| {
         let k1 = k.clone();
         let mut k2 = k.clone();
         k2.reverse();
-        assert_eq!(tree.get(&*k1), Some(k2));
+        assert_eq!(tree.get(&*k1).unwrap(), Some(k2));
     }};
 
     println!("========== deleting ==========");
     par!{t, |tree: &Tree, k: Vec<u8>| {
-        tree.del(&*k);
+        tree.del(&*k).unwrap();
     }};
 
     par!{t, |tree: &Tree, k: Vec<u8>| {
-        assert_eq!(tree.get(&*k), None);
+        assert_eq!(tree.get(&*k).unwrap(), None);
     }};
 }
 
@@ -93,17 +94,17 @@ fn parallel_tree_ops() {
 fn tree_subdir() {
     let t = sled::Config::default()
         .path("test_tree_subdir/test.db".to_owned())
-        .tree();
+        .tree().unwrap();
 
-    t.set(vec![1], vec![1]);
+    t.set(vec![1], vec![1]).unwrap();
 
     drop(t);
 
     let t = sled::Config::default()
         .path("test_tree_subdir/test.db".to_owned())
-        .tree();
+        .tree().unwrap();
 
-    let res = t.get(&*vec![1]);
+    let res = t.get(&*vec![1]).unwrap();
 
     drop(t);
 
@@ -118,19 +119,19 @@ fn tree_iterator() {
     let t = Config::default()
         .blink_fanout(2)
         .flush_every_ms(None)
-        .tree();
+        .tree().unwrap();
     for i in 0..N_PER_THREAD {
         let k = kv(i);
-        t.set(k.clone(), k);
+        t.set(k.clone(), k).unwrap();
     }
 
-    for (i, (k, v)) in t.iter().enumerate() {
+    for (i, (k, v)) in t.iter().map(Result::unwrap).enumerate() {
         let should_be = kv(i);
         assert_eq!(should_be, k);
         assert_eq!(should_be, v);
     }
 
-    for (i, (k, v)) in t.scan(b"").enumerate() {
+    for (i, (k, v)) in t.scan(b"").map(Result::unwrap).enumerate() {
         let should_be = kv(i);
         assert_eq!(should_be, k);
         assert_eq!(should_be, v);
@@ -138,19 +139,1519 @@ fn tree_iterator() {
 
     let half_way = N_PER_THREAD / 2;
     let half_key = kv(half_way);
-    let mut tree_scan = t.scan(&*half_key);
+    let mut tree_scan = t.scan(&*half_key).map(Result::unwrap);
     assert_eq!(tree_scan.next(), Some((half_key.clone(), half_key)));
 
     let first_key = kv(0);
-    let mut tree_scan = t.scan(&*first_key);
+    let mut tree_scan = t.scan(&*first_key).map(Result::unwrap);
     assert_eq!(tree_scan.next(), Some((first_key.clone(), first_key)));
 
     let last_key = kv(N_PER_THREAD - 1);
-    let mut tree_scan = t.scan(&*last_key);
+    let mut tree_scan = t.scan(&*last_key).map(Result::unwrap);
     assert_eq!(tree_scan.next(), Some((last_key.clone(), last_key)));
     assert_eq!(tree_scan.next(), None);
 }
 
+#[test]
+fn tree_range() {
+    println!("========== range ==========");
+    let t = Config::default()
+        .blink_fanout(2)
+        .flush_every_ms(None)
+        .tree().unwrap();
+    for i in 0..N_PER_THREAD {
+        let k = kv(i);
+        t.set(k.clone(), k).unwrap();
+    }
+
+    let lo = 10;
+    let hi = N_PER_THREAD - 10;
+
+    let exclusive: Vec<_> =
+        t.range(kv(lo)..kv(hi)).map(|res| res.unwrap().0).collect();
+    let expected: Vec<_> = (lo..hi).map(kv).collect();
+    assert_eq!(exclusive, expected);
+
+    let inclusive: Vec<_> =
+        t.range(kv(lo)..=kv(hi)).map(|res| res.unwrap().0).collect();
+    let expected: Vec<_> = (lo..hi + 1).map(kv).collect();
+    assert_eq!(inclusive, expected);
+
+    let to: Vec<_> = t.range(..kv(lo)).map(|res| res.unwrap().0).collect();
+    let expected: Vec<_> = (0..lo).map(kv).collect();
+    assert_eq!(to, expected);
+
+    let from: Vec<_> = t.range(kv(hi)..).map(|res| res.unwrap().0).collect();
+    let expected: Vec<_> = (hi..N_PER_THREAD).map(kv).collect();
+    assert_eq!(from, expected);
+
+    let mut empty = t.range(kv(lo)..kv(lo)).map(Result::unwrap);
+    assert_eq!(empty.next(), None);
+}
+
+#[test]
+fn tree_reverse_iterator() {
+    println!("========== reverse iterator ==========");
+    let t = Config::default()
+        .blink_fanout(2)
+        .flush_every_ms(None)
+        .tree().unwrap();
+    for i in 0..N_PER_THREAD {
+        let k = kv(i);
+        t.set(k.clone(), k).unwrap();
+    }
+
+    let all: Vec<_> = t.iter().rev().map(|res| res.unwrap().0).collect();
+    let expected: Vec<_> = (0..N_PER_THREAD).rev().map(kv).collect();
+    assert_eq!(all, expected);
+
+    let half_way = N_PER_THREAD / 2;
+    let from_half: Vec<_> =
+        t.scan_rev(&*kv(half_way)).map(|res| res.unwrap().0).collect();
+    let expected: Vec<_> = (0..half_way + 1).rev().map(kv).collect();
+    assert_eq!(from_half, expected);
+
+    let ranged: Vec<_> =
+        t.range(kv(10)..kv(20)).rev().map(|res| res.unwrap().0).collect();
+    let expected: Vec<_> = (10..20).rev().map(kv).collect();
+    assert_eq!(ranged, expected);
+
+    // both ends of the iterator should meet in the middle
+    let mut iter = t.range(kv(10)..kv(14)).map(Result::unwrap);
+    assert_eq!(iter.next().map(|(k, _)| k), Some(kv(10)));
+    assert_eq!(iter.next_back().map(|(k, _)| k), Some(kv(13)));
+    assert_eq!(iter.next().map(|(k, _)| k), Some(kv(11)));
+    assert_eq!(iter.next_back().map(|(k, _)| k), Some(kv(12)));
+    assert_eq!(iter.next(), None);
+    assert_eq!(iter.next_back(), None);
+
+    // deleted keys should be skipped over, including whole leaves
+    for i in 100..200 {
+        t.del(&*kv(i)).unwrap();
+    }
+    let gapped: Vec<_> =
+        t.range(kv(50)..kv(250)).rev().map(|res| res.unwrap().0).collect();
+    let expected: Vec<_> =
+        (50..100).chain(200..250).rev().map(kv).collect();
+    assert_eq!(gapped, expected);
+}
+
+#[test]
+fn tree_scan_prefix() {
+    let t = Config::default()
+        .blink_fanout(2)
+        .flush_every_ms(None)
+        .tree().unwrap();
+
+    let prefixes: Vec<Vec<u8>> =
+        vec![vec![], vec![0], vec![1], vec![1, 255], vec![255], vec![255, 255]];
+    for prefix in &prefixes {
+        for suffix in 0..20u8 {
+            let mut k = prefix.clone();
+            k.push(suffix);
+            t.set(k.clone(), k).unwrap();
+            let mut k = prefix.clone();
+            k.push(255);
+            k.push(suffix);
+            t.set(k.clone(), k).unwrap();
+        }
+    }
+
+    let keys: Vec<Vec<u8>> = t.iter().map(|res| res.unwrap().0).collect();
+    for prefix in &prefixes {
+        let scanned: Vec<Vec<u8>> =
+            t.scan_prefix(prefix).map(|res| res.unwrap().0).collect();
+        let expected: Vec<Vec<u8>> = keys.iter()
+            .filter(|k| k.starts_with(prefix))
+            .cloned()
+            .collect();
+        assert!(!scanned.is_empty());
+        assert_eq!(scanned, expected);
+    }
+
+    assert_eq!(t.scan_prefix(&[30]).next(), None);
+}
+
+#[test]
+fn tree_batch() {
+    let conf = Config::default()
+        .blink_fanout(2)
+        .io_buf_size(1000)
+        .flush_every_ms(None)
+        .snapshot_after_ops(100);
+    let t = conf.tree().unwrap();
+    for i in 0..N_PER_THREAD {
+        t.set(kv(i), kv(i)).unwrap();
+    }
+
+    let mut batch = Batch::default();
+    for i in 0..N_PER_THREAD {
+        if i % 2 == 0 {
+            batch.del(kv(i));
+        } else {
+            batch.set(kv(i), kv(i + 1));
+        }
+    }
+    t.apply_batch(batch).unwrap();
+    drop(t);
+
+    let t = conf.tree().unwrap();
+    for i in 0..N_PER_THREAD {
+        if i % 2 == 0 {
+            assert_eq!(t.get(&*kv(i)).unwrap(), None);
+        } else {
+            assert_eq!(t.get(&*kv(i)).unwrap(), Some(kv(i + 1)));
+        }
+    }
+}
+
+const POISON: &[u8] = &[255, 255, 255];
+
+// panics when it meets the poisoned key, so that a batch writing it is
+// left open partway through.
+fn poisoned(a: &[u8], b: &[u8]) -> Ordering {
+    if a == POISON || b == POISON {
+        panic!("compared the poisoned key");
+    }
+    a.cmp(b)
+}
+
+#[test]
+fn tree_batch_abandoned() {
+    let conf = Config::default()
+        .blink_fanout(2)
+        .flush_every_ms(None)
+        .key_comparator(Some(("poisoned", poisoned)));
+    let t = Arc::new(conf.tree().unwrap());
+    for i in 0..N_PER_THREAD {
+        t.set(kv(i), kv(i)).unwrap();
+    }
+
+    // every key but the last is written before the batch panics
+    let mut batch = Batch::default();
+    for i in 0..N_PER_THREAD {
+        batch.set(kv(i), vec![]);
+    }
+    batch.set(POISON.to_vec(), vec![]);
+    let t2 = t.clone();
+    assert!(thread::spawn(move || t2.apply_batch(batch)).join().is_err());
+
+    // writes from other threads are recovered, and the batch is not
+    let mut threads = vec![];
+    for tn in 0..N_THREADS {
+        let t = t.clone();
+        threads.push(thread::spawn(move || for i in 0..N_PER_THREAD {
+            if i % N_THREADS == tn && i % 2 == 0 {
+                t.set(kv(i), vec![1]).unwrap();
+            }
+        }));
+    }
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    drop(t);
+
+    let t = conf.tree().unwrap();
+    for i in 0..N_PER_THREAD {
+        let expected = if i % 2 == 0 { vec![1] } else { kv(i) };
+        assert_eq!(t.get(&*kv(i)).unwrap(), Some(expected));
+    }
+}
+
+#[test]
+fn tree_batch_failed() {
+    let conf = Config::default()
+        .blink_fanout(2)
+        .flush_every_ms(None)
+        .key_comparator(Some(("poisoned", poisoned)));
+    let t = Arc::new(conf.tree().unwrap());
+    for i in 0..N_PER_THREAD {
+        t.set(kv(i * 2), vec![]).unwrap();
+    }
+    let before = t.page_count();
+
+    let mut batch = Batch::default();
+    for i in 0..N_PER_THREAD {
+        batch.set(kv(i * 2), vec![1]);
+    }
+    batch.set(POISON.to_vec(), vec![1]);
+    let t2 = t.clone();
+    assert!(thread::spawn(move || t2.apply_batch(batch)).join().is_err());
+
+    // the writes of the failed batch were undone
+    for i in 0..N_PER_THREAD {
+        assert_eq!(t.get(&*kv(i * 2)).unwrap(), Some(vec![]));
+    }
+
+    // and it was aborted, so it no longer keeps the leaves it wrote to
+    // from splitting
+    for i in 0..N_PER_THREAD {
+        t.set(kv(i * 2 + 1), vec![2]).unwrap();
+    }
+    let after = t.page_count();
+    assert!(
+        after > before + N_PER_THREAD / 4,
+        "expected leaves to split after the batch failed, but the tree \
+         only grew from {} to {} pages",
+        before,
+        after
+    );
+
+    let mut batch = Batch::default();
+    batch.set(kv(0), vec![3]);
+    t.apply_batch(batch).unwrap();
+    drop(t);
+
+    // splitting did not persist any of the failed batch's writes
+    let t = conf.tree().unwrap();
+    assert_eq!(t.get(&*kv(0)).unwrap(), Some(vec![3]));
+    for i in 1..N_PER_THREAD {
+        assert_eq!(t.get(&*kv(i * 2)).unwrap(), Some(vec![]));
+    }
+    for i in 0..N_PER_THREAD {
+        assert_eq!(t.get(&*kv(i * 2 + 1)).unwrap(), Some(vec![2]));
+    }
+}
+
+#[test]
+fn tree_tx() {
+    let t = Arc::new(
+        Config::default().blink_fanout(2).flush_every_ms(None).tree().unwrap(),
+    );
+    for i in 0..N_PER_THREAD {
+        t.set(kv(i), vec![0]).unwrap();
+    }
+
+    // each thread moves one unit from every key to its successor,
+    // so the total must be unchanged if transactions are serializable.
+    let mut threads = vec![];
+    for _ in 0..N_THREADS {
+        let t = t.clone();
+        threads.push(thread::spawn(move || for i in 0..N_PER_THREAD {
+            let j = (i + 1) % N_PER_THREAD;
+            t.tx(|tx| {
+                let from = tx.get(&*kv(i))?.unwrap()[0];
+                let to = tx.get(&*kv(j))?.unwrap()[0];
+                tx.set(kv(i), vec![from.wrapping_sub(1)]);
+                tx.set(kv(j), vec![to.wrapping_add(1)]);
+                Ok(())
+            }).unwrap();
+        }));
+    }
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    let sum = t.iter()
+        .map(Result::unwrap)
+        .fold(0u8, |acc, (_, v)| acc.wrapping_add(v[0]));
+    assert_eq!(sum, 0);
+
+    // reads observe earlier writes in the same transaction
+    t.tx(|tx| {
+        tx.set(vec![1, 2, 3], vec![4]);
+        assert_eq!(tx.get(&[1, 2, 3]), Ok(Some(vec![4])));
+        tx.del(&[1, 2, 3]);
+        assert_eq!(tx.get(&[1, 2, 3]), Ok(None));
+        Ok(())
+    }).unwrap();
+    assert_eq!(t.get(&[1, 2, 3]).unwrap(), None);
+}
+
+#[test]
+fn tree_tx_with_writers() {
+    let conf = Config::default()
+        .blink_fanout(2)
+        .flush_every_ms(None)
+        .snapshot_after_ops(100);
+    let t = Arc::new(conf.tree().unwrap());
+    // the accounts are the even keys, and plain writers count up
+    // the odd keys in between, in the same leaves.
+    const ACCOUNTS: usize = 8;
+    for i in 0..ACCOUNTS * 2 {
+        t.set(kv(i), vec![0]).unwrap();
+    }
+    let sum = |values: &[u8]| {
+        values.iter().fold(0u8, |acc, v| acc.wrapping_add(*v))
+    };
+
+    let mut threads = vec![];
+    for _ in 0..2 {
+        let t = t.clone();
+        threads.push(thread::spawn(move || for i in 0..N_PER_THREAD {
+            let from = kv(i % ACCOUNTS * 2);
+            let to = kv((i + 1) % ACCOUNTS * 2);
+            t.tx(|tx| {
+                let a = tx.get(&*from)?.unwrap()[0];
+                let b = tx.get(&*to)?.unwrap()[0];
+                tx.set(from.clone(), vec![a.wrapping_sub(1)]);
+                tx.set(to.clone(), vec![b.wrapping_add(1)]);
+                Ok(())
+            }).unwrap();
+        }));
+    }
+    for w in 0..2 {
+        let t = t.clone();
+        threads.push(thread::spawn(move || for i in 0..N_PER_THREAD {
+            let k = kv((i + w) % ACCOUNTS * 2 + 1);
+            t.update_and_fetch(k, |v| Some(vec![v.unwrap()[0] + 1]))
+                .unwrap();
+        }));
+    }
+    {
+        // a transaction that only reads never sees part of another
+        let t = t.clone();
+        threads.push(thread::spawn(move || for _ in 0..N_PER_THREAD / 10 {
+            let values = t.tx(|tx| {
+                let mut values = vec![];
+                for i in 0..ACCOUNTS {
+                    values.push(tx.get(&*kv(i * 2))?.unwrap()[0]);
+                }
+                Ok(values)
+            }).unwrap();
+            assert_eq!(sum(&*values), 0);
+        }));
+    }
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    let check = |t: &Tree| {
+        let values: Vec<u8> = (0..ACCOUNTS)
+            .map(|i| t.get(&*kv(i * 2)).unwrap().unwrap()[0])
+            .collect();
+        assert_eq!(sum(&*values), 0);
+        let counted: usize = (0..ACCOUNTS)
+            .map(|i| t.get(&*kv(i * 2 + 1)).unwrap().unwrap()[0] as usize)
+            .sum();
+        assert_eq!(counted, N_PER_THREAD * 2);
+    };
+    check(&t);
+
+    // claims are not held by the recovered tree
+    drop(t);
+    let t = conf.tree().unwrap();
+    check(&t);
+    t.set(kv(0), vec![0]).unwrap();
+    t.tx(|tx| {
+        tx.set(kv(2), vec![7]);
+        Ok(())
+    }).unwrap();
+    assert_eq!(t.get(&*kv(2)).unwrap(), Some(vec![7]));
+}
+
+#[test]
+fn tree_merge_shrinks() {
+    let conf = Config::default()
+        .blink_fanout(2)
+        .io_buf_size(5000)
+        .flush_every_ms(None)
+        .snapshot_after_ops(100);
+    let t = conf.tree().unwrap();
+    for i in 0..N {
+        t.set(kv(i), kv(i)).unwrap();
+    }
+    let full = t.page_count();
+
+    for i in 0..N {
+        if i % 100 != 0 {
+            assert_eq!(t.del(&*kv(i)).unwrap(), Some(kv(i)));
+        }
+    }
+    let shrunk = t.page_count();
+    assert!(
+        shrunk * 5 < full * 4,
+        "expected deletes to shrink the tree from {} pages, got {}",
+        full,
+        shrunk
+    );
+
+    let remaining: Vec<_> = t.iter().map(|res| res.unwrap().0).collect();
+    let expected: Vec<_> = (0..N).filter(|i| i % 100 == 0).map(kv).collect();
+    assert_eq!(remaining, expected);
+    let mut reversed = expected.clone();
+    reversed.reverse();
+    let remaining_rev: Vec<_> =
+        t.iter().rev().map(|res| res.unwrap().0).collect();
+    assert_eq!(remaining_rev, reversed);
+
+    // merged leaves are gone after a restart, and the tree still works
+    drop(t);
+    let t = conf.tree().unwrap();
+    assert!(t.page_count() <= shrunk);
+    for i in 0..N {
+        t.set(kv(i), kv(i)).unwrap();
+    }
+    for i in 0..N {
+        assert_eq!(t.get(&*kv(i)).unwrap(), Some(kv(i)));
+    }
+}
+
+#[test]
+fn tree_merge_reclaims_key_range() {
+    let conf = Config::default()
+        .blink_fanout(2)
+        .io_buf_size(5000)
+        .flush_every_ms(None)
+        .snapshot_after_ops(100);
+    let t = conf.tree().unwrap();
+    for i in 0..N {
+        t.set(kv(i), kv(i)).unwrap();
+    }
+    let full = t.page_count();
+
+    // emptying a middle range reclaims its leaves and the index nodes
+    // above them, and emptying the rest reclaims first children too.
+    t.del_range(kv(N / 4)..kv(3 * N / 4)).unwrap();
+    let half = t.page_count();
+    assert!(
+        half * 3 < full * 2,
+        "expected deleting half of the keys to shrink the tree from {} \
+         pages, got {}",
+        full,
+        half
+    );
+    let remaining: Vec<_> = t.iter().map(|res| res.unwrap().0).collect();
+    let expected: Vec<_> =
+        (0..N / 4).chain(3 * N / 4..N).map(kv).collect();
+    assert_eq!(remaining, expected);
+
+    t.clear().unwrap();
+    let empty = t.page_count();
+    assert!(
+        empty * 10 < full,
+        "expected clearing the tree to shrink it from {} pages, got {}",
+        full,
+        empty
+    );
+    assert_eq!(t.iter().next(), None);
+
+    drop(t);
+    let t = conf.tree().unwrap();
+    assert!(t.page_count() <= empty);
+    assert_eq!(t.iter().next(), None);
+    for i in 0..N {
+        t.set(kv(i), kv(i)).unwrap();
+    }
+    let all: Vec<_> = t.iter().map(|res| res.unwrap().0).collect();
+    assert_eq!(all, (0..N).map(kv).collect::<Vec<_>>());
+}
+
+#[test]
+fn concurrent_tree_merges() {
+    let t = Arc::new(
+        Config::default().blink_fanout(2).flush_every_ms(None).tree().unwrap(),
+    );
+    let mut threads = vec![];
+    for tn in 0..N_THREADS {
+        let t = t.clone();
+        threads.push(thread::spawn(move || for _ in 0..3 {
+            for i in (tn * N_PER_THREAD)..((tn + 1) * N_PER_THREAD) {
+                t.set(kv(i), kv(i)).unwrap();
+            }
+            for i in (tn * N_PER_THREAD)..((tn + 1) * N_PER_THREAD) {
+                assert_eq!(t.del(&*kv(i)).unwrap(), Some(kv(i)));
+            }
+        }));
+    }
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    assert_eq!(t.iter().next(), None);
+    for i in 0..N {
+        t.set(kv(i), kv(i)).unwrap();
+    }
+    assert_eq!(t.iter().map(Result::unwrap).count(), N);
+}
+
+#[test]
+fn tree_iter_survives_concurrent_writers() {
+    let t = Arc::new(
+        Config::default().blink_fanout(2).flush_every_ms(None).tree().unwrap(),
+    );
+    for i in 0..N {
+        t.set(kv(i), kv(i)).unwrap();
+    }
+
+    // keys divisible by 10 are never touched by the writers, so every
+    // scan must see all of them, in order.
+    let mut writers = vec![];
+    for tn in 0..N_THREADS {
+        let t = t.clone();
+        writers.push(thread::spawn(move || for _ in 0..5 {
+            let keys = (tn * N_PER_THREAD)..((tn + 1) * N_PER_THREAD);
+            for i in keys.clone().filter(|i| i % 10 != 0) {
+                t.del(&*kv(i)).unwrap();
+            }
+            for i in keys.filter(|i| i % 10 != 0) {
+                t.set(kv(i), kv(i)).unwrap();
+            }
+        }));
+    }
+
+    let expected: Vec<_> = (0..N).filter(|i| i % 10 == 0).map(kv).collect();
+    for _ in 0..20 {
+        let mut last = None;
+        let mut stable = vec![];
+        for (k, v) in t.iter().map(Result::unwrap) {
+            assert_eq!(k, v);
+            assert!(Some(&k) > last.as_ref(), "keys went backwards");
+            if expected.binary_search(&k).is_ok() {
+                stable.push(k.clone());
+            }
+            last = Some(k);
+        }
+        assert_eq!(stable, expected);
+    }
+
+    for writer in writers {
+        writer.join().unwrap();
+    }
+
+    // the leaf an iterator is paused on may be merged away and its
+    // page reused elsewhere in the tree before the iterator resumes.
+    for (n, &start) in [200, 501, 802, 1103].iter().enumerate() {
+        let mut iter = t.range(kv(start)..kv(start + 250)).map(Result::unwrap);
+        assert_eq!(iter.next(), Some((kv(start), kv(start))));
+        for i in (start - 100)..(start + 200) {
+            t.del(&*kv(i)).unwrap();
+        }
+        for i in 0..300 {
+            t.set(vec![255, n as u8, (i >> 8) as u8, i as u8], vec![]).unwrap();
+        }
+        for i in (start + 200)..(start + 250) {
+            assert_eq!(iter.next(), Some((kv(i), kv(i))));
+        }
+        assert_eq!(iter.next(), None);
+    }
+}
+
+fn counter_merge(
+    _key: &[u8],
+    existing: Option<&[u8]>,
+    operand: &[u8],
+) -> Option<Vec<u8>> {
+    let count = existing.map(|e| e[0]).unwrap_or(0);
+    let count = count.wrapping_add(operand[0]);
+    if count == 0 { None } else { Some(vec![count]) }
+}
+
+#[test]
+fn tree_merge_operator() {
+    let conf = Config::default()
+        .blink_fanout(2)
+        .flush_every_ms(None)
+        .merge_operator(Some(counter_merge));
+    let t = Arc::new(conf.tree().unwrap());
+
+    let mut threads = vec![];
+    for _ in 0..N_THREADS {
+        let t = t.clone();
+        threads.push(thread::spawn(move || for i in 0..N_PER_THREAD {
+            t.merge(kv(i % 10), vec![1]).unwrap();
+        }));
+    }
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    let expected = (N / 10) as u8;
+    for i in 0..10 {
+        assert_eq!(t.get(&*kv(i)).unwrap(), Some(vec![expected]));
+    }
+
+    // returning None from the operator deletes the key
+    t.merge(kv(0), vec![0u8.wrapping_sub(expected)]).unwrap();
+    assert_eq!(t.get(&*kv(0)).unwrap(), None);
+
+    // merged values are consolidated with the operator after a restart
+    drop(t);
+    let t = conf.tree().unwrap();
+    assert_eq!(t.get(&*kv(0)).unwrap(), None);
+    for i in 1..10 {
+        assert_eq!(t.get(&*kv(i)).unwrap(), Some(vec![expected]));
+    }
+
+    // a tree that was merged into can't be read without an operator
+    drop(t);
+    match conf.clone().merge_operator(None).tree() {
+        Err(Error::Unsupported(ref msg)) if msg.contains("merge") => {}
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(_) => panic!("opened a merged tree without a merge operator"),
+    }
+
+    // merging needs an operator
+    let t = Config::default().tree().unwrap();
+    match t.merge(kv(0), vec![1]) {
+        Err(Error::Unsupported(_)) => {}
+        other => panic!("expected Unsupported, got {:?}", other),
+    }
+}
+
+#[test]
+fn tree_update_and_fetch() {
+    let conf = Config::default().blink_fanout(2).flush_every_ms(None);
+    let t = Arc::new(conf.tree().unwrap());
+
+    let increment = |old: Option<&[u8]>| {
+        let count = old.map(|o| o[0] as usize * 256 + o[1] as usize);
+        let count = count.map(|c| c + 1).unwrap_or(1);
+        Some(vec![(count >> 8) as u8, count as u8])
+    };
+
+    let mut threads = vec![];
+    for _ in 0..N_THREADS {
+        let t = t.clone();
+        threads.push(thread::spawn(move || {
+            let mut last = 0;
+            for _ in 0..N_PER_THREAD {
+                let new =
+                    t.update_and_fetch(vec![0], increment).unwrap().unwrap();
+                let old = t.fetch_and_update(vec![1], increment).unwrap();
+                let new = new[0] as usize * 256 + new[1] as usize;
+                assert!(new > last, "counter went backwards");
+                last = new;
+                if let Some(old) = old {
+                    assert!(old[0] as usize * 256 + (old[1] as usize) < N);
+                }
+            }
+        }));
+    }
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    let total = vec![(N >> 8) as u8, N as u8];
+    assert_eq!(t.get(&[0]).unwrap(), Some(total.clone()));
+    assert_eq!(t.get(&[1]).unwrap(), Some(total));
+
+    // read-only trees refuse the update without calling the closure
+    drop(t);
+    let t = conf.read_only(true).tree().unwrap();
+    assert_eq!(
+        t.update_and_fetch(vec![0], |_| panic!("called")),
+        Err(Error::ReadOnly)
+    );
+    assert_eq!(
+        t.fetch_and_update(vec![0], |_| panic!("called")),
+        Err(Error::ReadOnly)
+    );
+}
+
+#[test]
+fn tree_db() {
+    let conf = Config::default()
+        .blink_fanout(2)
+        .flush_every_ms(None)
+        .snapshot_after_ops(100);
+    let db = Arc::new(conf.db().unwrap());
+
+    // both trees hoist their roots many times, racing on the Meta
+    let mut threads = vec![];
+    for name in vec![b"a".to_vec(), b"b".to_vec()] {
+        let db = db.clone();
+        threads.push(thread::spawn(move || {
+            let t = db.open_tree(name.clone()).unwrap();
+            for i in 0..N_PER_THREAD {
+                t.set(kv(i), name.clone()).unwrap();
+            }
+        }));
+    }
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    let a = db.open_tree(b"a".to_vec()).unwrap();
+    let b = db.open_tree(b"b".to_vec()).unwrap();
+    a.del(&*kv(0)).unwrap();
+    assert_eq!(b.get(&*kv(0)).unwrap(), Some(b"b".to_vec()));
+    drop(a);
+    drop(b);
+    drop(db);
+
+    let db = conf.db().unwrap();
+    assert_eq!(db.tree_names().unwrap(), vec![b"a".to_vec(), b"b".to_vec()]);
+    let a = db.open_tree(b"a".to_vec()).unwrap();
+    let b = db.open_tree(b"b".to_vec()).unwrap();
+    assert_eq!(a.get(&*kv(0)).unwrap(), None);
+    assert_eq!(b.get(&*kv(0)).unwrap(), Some(b"b".to_vec()));
+    for i in 1..N_PER_THREAD {
+        assert_eq!(a.get(&*kv(i)).unwrap(), Some(b"a".to_vec()));
+        assert_eq!(b.get(&*kv(i)).unwrap(), Some(b"b".to_vec()));
+    }
+    assert_eq!(a.iter().map(Result::unwrap).count(), N_PER_THREAD - 1);
+    assert_eq!(b.iter().map(Result::unwrap).count(), N_PER_THREAD);
+
+    // new trees start out empty, including the one used by Tree::new
+    assert_eq!(db.open_tree(b"c".to_vec()).unwrap().iter().next(), None);
+    drop(a);
+    drop(b);
+    drop(db);
+    let t = conf.tree().unwrap();
+    assert_eq!(t.iter().next(), None);
+}
+
+#[test]
+fn tree_watch_prefix() {
+    let t = Arc::new(
+        Config::default().blink_fanout(2).flush_every_ms(None).tree().unwrap(),
+    );
+    let subscriber = t.watch_prefix(vec![0, 1]);
+
+    let mut threads = vec![];
+    for tn in 0..N_THREADS {
+        let t = t.clone();
+        threads.push(thread::spawn(move || {
+            for i in (tn * N_PER_THREAD)..((tn + 1) * N_PER_THREAD) {
+                t.set(kv(i), kv(i)).unwrap();
+                if i % 2 == 0 {
+                    t.del(&*kv(i)).unwrap();
+                }
+            }
+        }));
+    }
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    // iteration ends once the tree is gone
+    drop(t);
+    let mut sets = 0;
+    let mut dels = 0;
+    for event in subscriber {
+        match event {
+            Event::Set(k, v) => {
+                assert_eq!(k, v);
+                assert_eq!(&k[..2], &[0, 1]);
+                sets += 1;
+            }
+            Event::Del(k) => {
+                assert_eq!(&k[..2], &[0, 1]);
+                assert_eq!(k[2] % 2, 0);
+                dels += 1;
+            }
+        }
+    }
+    assert_eq!(sets, 256);
+    assert_eq!(dels, 128);
+}
+
+#[test]
+fn tree_ttl() {
+    use std::time::Duration;
+
+    let conf = Config::default()
+        .blink_fanout(2)
+        .flush_every_ms(None)
+        .reap_every_ms(Some(10));
+    let t = conf.tree().unwrap();
+    for i in 0..N_PER_THREAD {
+        if i % 100 == 0 {
+            t.set(kv(i), kv(i)).unwrap();
+        } else {
+            t.set_with_ttl(kv(i), kv(i), Duration::from_millis(100)).unwrap();
+        }
+    }
+    // a plain set clears the expiry
+    t.set(kv(1), vec![1]).unwrap();
+    let full = t.page_count();
+    assert_eq!(t.iter().map(Result::unwrap).count(), N_PER_THREAD);
+
+    thread::sleep(Duration::from_millis(150));
+    let expected: Vec<_> = (0..N_PER_THREAD)
+        .filter(|i| i % 100 == 0 || *i == 1)
+        .map(kv)
+        .collect();
+    let remaining: Vec<_> = t.iter().map(|res| res.unwrap().0).collect();
+    assert_eq!(remaining, expected);
+    let mut reversed = expected.clone();
+    reversed.reverse();
+    let remaining_rev: Vec<_> =
+        t.iter().rev().map(|res| res.unwrap().0).collect();
+    assert_eq!(remaining_rev, reversed);
+    assert_eq!(t.get(&*kv(2)).unwrap(), None);
+    assert_eq!(t.get(&*kv(1)).unwrap(), Some(vec![1]));
+    assert_eq!(t.del(&*kv(3)).unwrap(), None);
+
+    // the reaper deletes the expired keys, letting their leaves merge
+    let mut shrunk = t.page_count();
+    for _ in 0..100 {
+        if shrunk * 5 < full * 4 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+        shrunk = t.page_count();
+    }
+    assert!(
+        shrunk * 5 < full * 4,
+        "expected the reaper to shrink the tree from {} pages, got {}",
+        full,
+        shrunk
+    );
+    let keys: Vec<_> = t.iter().map(|res| res.unwrap().0).collect();
+    assert_eq!(keys, expected);
+
+    // a ttl too long to represent never expires
+    let forever = Duration::from_secs(u64::max_value());
+    t.set_with_ttl(kv(1), vec![2], forever).unwrap();
+    assert_eq!(t.get(&*kv(1)).unwrap(), Some(vec![2]));
+
+    // keys that expire while the tree is closed are reaped once it is
+    // reopened, without being read first
+    for i in 0..N_PER_THREAD {
+        if i % 100 != 0 {
+            t.set_with_ttl(kv(i), kv(i), Duration::from_millis(50)).unwrap();
+        }
+    }
+    let full = t.page_count();
+    drop(t);
+    thread::sleep(Duration::from_millis(100));
+
+    let t = conf.tree().unwrap();
+    let mut shrunk = t.page_count();
+    for _ in 0..100 {
+        if shrunk * 5 < full * 4 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(50));
+        shrunk = t.page_count();
+    }
+    assert!(
+        shrunk * 5 < full * 4,
+        "expected the reaper to shrink the reopened tree from {} pages, \
+         got {}",
+        full,
+        shrunk
+    );
+}
+
+#[test]
+fn tree_snapshot() {
+    let t = Arc::new(
+        Config::default().blink_fanout(2).flush_every_ms(None).tree().unwrap(),
+    );
+    for i in 0..N {
+        if i % 2 == 0 {
+            t.set(kv(i), kv(i)).unwrap();
+        }
+    }
+    let snapshot = t.snapshot().unwrap();
+
+    // the writer splits, merges and consolidates the leaves the
+    // snapshot reads from
+    let writer = {
+        let t = t.clone();
+        thread::spawn(move || for i in 0..N {
+            if i % 2 == 0 {
+                t.del(&*kv(i)).unwrap();
+            } else {
+                t.set(kv(i), vec![1]).unwrap();
+            }
+        })
+    };
+
+    let expected: Vec<_> =
+        (0..N).filter(|i| i % 2 == 0).map(|i| (kv(i), kv(i))).collect();
+    let items: Vec<_> = snapshot.iter().map(Result::unwrap).collect();
+    assert_eq!(items, expected);
+
+    writer.join().unwrap();
+
+    let items: Vec<_> = snapshot.iter().map(Result::unwrap).collect();
+    assert_eq!(items, expected);
+    assert_eq!(snapshot.get(&*kv(2)).unwrap(), Some(kv(2)));
+    assert_eq!(snapshot.get(&*kv(3)).unwrap(), None);
+    assert_eq!(t.get(&*kv(2)).unwrap(), None);
+    assert_eq!(t.get(&*kv(3)).unwrap(), Some(vec![1]));
+    assert_eq!(
+        snapshot
+            .range(kv(10)..kv(16))
+            .map(|res| res.unwrap().0)
+            .collect::<Vec<_>>(),
+        vec![kv(10), kv(12), kv(14)]
+    );
+    assert_eq!(snapshot.scan(&*kv(N - 3)).map(Result::unwrap).count(), 1);
+
+    // a later snapshot sees the writes
+    let later = t.snapshot().unwrap();
+    assert_eq!(later.iter().map(Result::unwrap).count(), N / 2);
+    assert!(later.iter().map(Result::unwrap).all(|(_, v)| v == vec![1]));
+}
+
+#[test]
+fn typed_tree() {
+    let t: TypedTree<(i64, String), (u8, Vec<u8>)> =
+        TypedTree::new(Config::default().blink_fanout(2).tree().unwrap());
+    let mut reference = BTreeMap::new();
+    let strings = ["", "\u{0}", "\u{0}a", "a", "a\u{0}", "ab", "b", "é"];
+    for i in -300i64..300 {
+        let string = strings[(i + 300) as usize % strings.len()];
+        let key = (i * 7919 % 1000, string.to_owned());
+        let value = (i as u8, vec![0, i as u8]);
+        t.set(&key, &value).unwrap();
+        reference.insert(key, value);
+    }
+
+    assert_eq!(
+        t.iter().map(Result::unwrap).collect::<Vec<_>>(),
+        reference.clone().into_iter().collect::<Vec<_>>()
+    );
+    assert_eq!(
+        t.iter().map(Result::unwrap).rev().collect::<Vec<_>>(),
+        reference.clone().into_iter().rev().collect::<Vec<_>>()
+    );
+
+    let lo = (-10, "a".to_owned());
+    let hi = (10, String::new());
+    let expected: Vec<_> = reference
+        .range(lo.clone()..hi.clone())
+        .map(|(k, v)| (k.clone(), v.clone()))
+        .collect();
+    let items: Vec<_> = t.range(lo..hi).map(Result::unwrap).collect();
+    assert_eq!(items, expected);
+
+    let (key, value) = reference.iter().next().unwrap();
+    assert_eq!(t.get(key).unwrap(), Some(value.clone()));
+    assert_eq!(
+        t.cas(key, None, Some(&(1, vec![]))).unwrap(),
+        Err(Some(value.clone()))
+    );
+    assert_eq!(t.cas(key, Some(value), Some(&(1, vec![]))).unwrap(), Ok(()));
+    assert_eq!(t.del(key).unwrap(), Some((1, vec![])));
+    assert_eq!(t.get(key).unwrap(), None);
+
+    // bytes written through the raw Tree are reported, not panicked on
+    let (raw_key, _) = t.tree().iter().next().unwrap().unwrap();
+    let (key, _) = t.iter().next().unwrap().unwrap();
+    t.tree().set(raw_key, vec![]).unwrap();
+    match t.get(&key) {
+        Err(Error::Unsupported(_)) => {}
+        other => panic!("decoded a garbage value: {:?}", other),
+    }
+    t.tree().set(vec![255], vec![]).unwrap();
+    match t.iter().next_back() {
+        Some(Err(Error::Unsupported(_))) => {}
+        other => panic!("decoded a garbage key: {:?}", other),
+    }
+}
+
+#[test]
+fn tree_neighbours() {
+    let t = Config::default()
+        .blink_fanout(2)
+        .flush_every_ms(None)
+        .tree()
+        .unwrap();
+    assert_eq!(t.first().unwrap(), None);
+    assert_eq!(t.last().unwrap(), None);
+    assert_eq!(t.get_lt(&*kv(1)).unwrap(), None);
+    assert_eq!(t.get_gt(&*kv(1)).unwrap(), None);
+
+    // leave runs of deleted keys, so that some leaves are emptied
+    for i in 0..N_PER_THREAD {
+        t.set(kv(i), kv(i)).unwrap();
+    }
+    for i in 0..N_PER_THREAD {
+        if i % 20 >= 10 || i < 5 || i >= N_PER_THREAD - 5 {
+            t.del(&*kv(i)).unwrap();
+        }
+    }
+    let present = |i: usize| i % 20 < 10 && i >= 5 && i < N_PER_THREAD - 5;
+
+    assert_eq!(t.first().unwrap(), Some((kv(5), kv(5))));
+    let max = (0..N_PER_THREAD).filter(|&i| present(i)).last().unwrap();
+    assert_eq!(t.last().unwrap(), Some((kv(max), kv(max))));
+
+    for i in 0..N_PER_THREAD + 1 {
+        let lt = (0..i).filter(|&j| present(j)).last();
+        assert_eq!(t.get_lt(&*kv(i)).unwrap(), lt.map(|j| (kv(j), kv(j))));
+        let gt = (i + 1..N_PER_THREAD).find(|&j| present(j));
+        assert_eq!(t.get_gt(&*kv(i)).unwrap(), gt.map(|j| (kv(j), kv(j))));
+    }
+}
+
+#[test]
+fn tree_pop_min_max() {
+    let t = Arc::new(
+        Config::default().blink_fanout(2).flush_every_ms(None).tree().unwrap(),
+    );
+    for i in 0..N {
+        t.set(kv(i), kv(i)).unwrap();
+    }
+
+    // new keys are always above the existing ones, so every popper
+    // must see the keys it pops strictly increase.
+    let pushed = |i: usize| {
+        let mut k = vec![1];
+        k.extend(kv(i));
+        k
+    };
+    let pusher = {
+        let t = t.clone();
+        thread::spawn(move || for i in 0..N {
+            t.set(pushed(i), vec![]).unwrap();
+        })
+    };
+    let mut poppers = vec![];
+    for _ in 0..N_THREADS {
+        let t = t.clone();
+        poppers.push(thread::spawn(move || {
+            let mut popped = vec![];
+            for _ in 0..N_PER_THREAD {
+                let (k, v) = t.pop_min().unwrap().unwrap();
+                assert_eq!(k, v);
+                if let Some(last) = popped.last() {
+                    assert!(last < &k, "popped {:?} after {:?}", k, last);
+                }
+                popped.push(k);
+            }
+            popped
+        }));
+    }
+
+    let mut seen = vec![];
+    for popper in poppers {
+        seen.extend(popper.join().unwrap());
+    }
+    pusher.join().unwrap();
+
+    seen.sort();
+    let expected: Vec<_> = (0..N_THREADS * N_PER_THREAD).map(kv).collect();
+    assert_eq!(seen, expected);
+
+    // drain the pushed keys from the top
+    for i in (0..N).rev() {
+        assert_eq!(t.pop_max().unwrap(), Some((pushed(i), vec![])));
+    }
+    assert_eq!(t.pop_max().unwrap(), None);
+    assert_eq!(t.pop_min().unwrap(), None);
+}
+
+#[test]
+fn tree_len() {
+    let conf = Config::default().blink_fanout(2).flush_every_ms(None);
+    let t = Arc::new(conf.tree().unwrap());
+    assert_eq!(t.len().unwrap(), 0);
+    assert!(t.is_empty().unwrap());
+    assert_eq!(t.estimated_len().unwrap(), 0);
+
+    // each thread sets its own keys twice, deletes every third one,
+    // and cas-es away every fifth one that is left.
+    let mut threads = vec![];
+    for tn in 0..N_THREADS {
+        let t = t.clone();
+        threads.push(thread::spawn(move || {
+            for i in (tn * N_PER_THREAD)..((tn + 1) * N_PER_THREAD) {
+                t.set(kv(i), kv(i)).unwrap();
+                t.set(kv(i), kv(i)).unwrap();
+                if i % 3 == 0 {
+                    t.del(&*kv(i)).unwrap();
+                } else if i % 5 == 0 {
+                    t.cas(kv(i), Some(kv(i)), None).unwrap().unwrap();
+                }
+            }
+        }));
+    }
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    let expected = (0..N).filter(|i| i % 3 != 0 && i % 5 != 0).count();
+    assert_eq!(t.len().unwrap(), expected);
+    assert!(!t.is_empty().unwrap());
+    assert_eq!(t.estimated_len().unwrap(), expected);
+    // everything fits in the first segment
+    assert_eq!(t.size_on_disk(), conf.get_io_buf_size() as u64);
+    drop(t);
+
+    // the estimate is recorded when the tree is dropped, and recovered
+    let t = conf.tree().unwrap();
+    assert_eq!(t.estimated_len().unwrap(), expected);
+    t.set(kv(0), vec![]).unwrap();
+    assert_eq!(t.estimated_len().unwrap(), expected + 1);
+    assert_eq!(t.len().unwrap(), expected + 1);
+}
+
+#[test]
+fn tree_del_range() {
+    let conf = Config::default().blink_fanout(2).flush_every_ms(None);
+    let t = Arc::new(conf.tree().unwrap());
+    for i in 0..N {
+        t.set(kv(i), kv(i)).unwrap();
+    }
+
+    // writers outside of the range keep splitting and rewriting the
+    // leaves at its edges while it is deleted.
+    let lo = N / 3;
+    let hi = N / 3 * 2;
+    let mut threads = vec![];
+    for &(from, to) in &[(0, lo), (hi, N)] {
+        let t = t.clone();
+        threads.push(thread::spawn(move || for i in from..to {
+            t.set(kv(i), vec![]).unwrap();
+        }));
+    }
+    t.del_range(kv(lo)..kv(hi)).unwrap();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    let keys: Vec<_> = t.iter().map(|res| res.unwrap().0).collect();
+    let expected: Vec<_> = (0..lo).chain(hi..N).map(kv).collect();
+    assert_eq!(keys, expected);
+    assert_eq!(t.estimated_len().unwrap(), expected.len());
+
+    t.del_range(kv(0)..=kv(0)).unwrap();
+    t.del_range(kv(N - 2)..).unwrap();
+    assert_eq!(t.first().unwrap(), Some((kv(1), vec![])));
+    assert_eq!(t.last().unwrap(), Some((kv(N - 3), vec![])));
+    drop(t);
+
+    let t = conf.tree().unwrap();
+    assert_eq!(t.len().unwrap(), expected.len() - 3);
+    t.clear().unwrap();
+    assert_eq!(t.iter().next(), None);
+    assert_eq!(t.estimated_len().unwrap(), 0);
+    t.set(kv(lo), kv(lo)).unwrap();
+    let items: Vec<_> = t.iter().map(Result::unwrap).collect();
+    assert_eq!(items, vec![(kv(lo), kv(lo))]);
+}
+
+#[test]
+fn tree_long_keys() {
+    let conf = Config::default().blink_fanout(2).flush_every_ms(None);
+    let t = Arc::new(conf.tree().unwrap());
+    // leaves split between keys that only differ in their last few
+    // digits, so their separators are much shorter than the keys.
+    let long_key = |i: usize| {
+        let mut key = format!("users/all/{:04}/", i).into_bytes();
+        key.extend_from_slice(&[b'x'; 200]);
+        key
+    };
+
+    let mut threads = vec![];
+    for tn in 0..N_THREADS {
+        let t = t.clone();
+        threads.push(thread::spawn(move || for i in 0..N_PER_THREAD {
+            let k = long_key(i * N_THREADS + tn);
+            t.set(k.clone(), k).unwrap();
+        }));
+    }
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    for i in 0..N {
+        assert_eq!(t.get(&*long_key(i)).unwrap(), Some(long_key(i)));
+        // prefixes of keys may have become separators
+        let prefix = format!("users/all/{:04}", i).into_bytes();
+        assert_eq!(t.get(&*prefix).unwrap(), None);
+    }
+    t.set(b"users/all/01".to_vec(), vec![]).unwrap();
+    let keys: Vec<_> = t.range(long_key(99)..long_key(101))
+        .map(|res| res.unwrap().0)
+        .collect();
+    let expected = vec![long_key(99), b"users/all/01".to_vec(), long_key(100)];
+    assert_eq!(keys, expected);
+    t.del(b"users/all/01").unwrap();
+
+    let expected: Vec<_> = (0..N).map(long_key).collect();
+    let keys: Vec<_> = t.iter().map(|res| res.unwrap().0).collect();
+    assert_eq!(keys, expected);
+    let mut keys: Vec<_> = t.iter().rev().map(|res| res.unwrap().0).collect();
+    keys.reverse();
+    assert_eq!(keys, expected);
+    drop(t);
+
+    let t = conf.tree().unwrap();
+    let keys: Vec<_> = t.iter().map(|res| res.unwrap().0).collect();
+    assert_eq!(keys, expected);
+}
+
+#[test]
+fn tree_bulk_load() {
+    let conf = Config::default()
+        .blink_fanout(3)
+        .blob_threshold(100)
+        .flush_every_ms(None);
+    let t = conf.tree().unwrap();
+    // every hundredth value is stored as a blob
+    let value = |i: usize| if i % 100 == 0 {
+        vec![i as u8; 1000]
+    } else {
+        kv(i)
+    };
+    let items = || (0..N).map(|i| (kv(i), value(i)));
+
+    // the old tree split and merged its way back to being empty
+    for i in 0..N_PER_THREAD {
+        t.set(kv(i), vec![]).unwrap();
+    }
+    for i in 0..N_PER_THREAD {
+        t.del(&*kv(i)).unwrap();
+    }
+
+    let mut unsorted: Vec<_> = items().collect();
+    unsorted.swap(500, 501);
+    assert_eq!(
+        t.bulk_load(unsorted).unwrap(),
+        Err(BulkLoadError::Unsorted(kv(500)))
+    );
+    let duplicated = vec![(kv(1), vec![]), (kv(1), vec![])];
+    assert_eq!(
+        t.bulk_load(duplicated).unwrap(),
+        Err(BulkLoadError::Unsorted(kv(1)))
+    );
+    assert!(t.is_empty().unwrap());
+
+    assert_eq!(t.bulk_load(items()).unwrap(), Ok(()));
+    assert_eq!(t.bulk_load(items()).unwrap(), Err(BulkLoadError::NotEmpty));
+    assert_eq!(t.len().unwrap(), N);
+    assert_eq!(t.estimated_len().unwrap(), N);
+    for i in 0..N {
+        assert_eq!(t.get(&*kv(i)).unwrap(), Some(value(i)));
+    }
+    let expected: Vec<_> = items().collect();
+    assert_eq!(t.iter().map(Result::unwrap).collect::<Vec<_>>(), expected);
+    let mut reversed: Vec<_> = t.iter().map(Result::unwrap).rev().collect();
+    reversed.reverse();
+    assert_eq!(reversed, expected);
+
+    // the loaded leaves are full, so writes split them right away
+    for i in 0..N {
+        if i % 2 == 0 {
+            t.set(kv(i), vec![]).unwrap();
+        } else {
+            t.del(&*kv(i)).unwrap();
+        }
+    }
+    t.set(vec![1, 0, 0, 0], vec![]).unwrap();
+    drop(t);
+
+    let t = conf.tree().unwrap();
+    let mut expected: Vec<_> =
+        (0..N).filter(|i| i % 2 == 0).map(|i| (kv(i), vec![])).collect();
+    expected.push((vec![1, 0, 0, 0], vec![]));
+    assert_eq!(t.iter().map(Result::unwrap).collect::<Vec<_>>(), expected);
+}
+
+fn append_merge(
+    _key: &[u8],
+    existing: Option<&[u8]>,
+    operand: &[u8],
+) -> Option<Vec<u8>> {
+    let mut value = existing.map(|e| e.to_vec()).unwrap_or_default();
+    value.extend_from_slice(operand);
+    Some(value)
+}
+
+#[test]
+fn tree_blobs() {
+    let conf = Config::default()
+        .blink_fanout(2)
+        .io_buf_size(10_000)
+        .blob_threshold(100)
+        .flush_every_ms(None)
+        .merge_operator(Some(append_merge));
+    let t = Arc::new(conf.tree().unwrap());
+    let big = |i: usize, len: usize| vec![i as u8; len];
+
+    // threads keep overwriting their keys with values several times
+    // larger than a segment, while we check that every value we see
+    // was written whole.
+    let mut threads = vec![];
+    for tn in 0..N_THREADS {
+        let t = t.clone();
+        threads.push(thread::spawn(move || for round in 0..20 {
+            let len = 30_000 + round * 1000;
+            t.set(kv(tn), big(tn, len)).unwrap();
+            t.set(kv(tn + N_THREADS), big(tn, len / 100)).unwrap();
+        }));
+    }
+    for (k, v) in t.iter().map(Result::unwrap) {
+        assert!(v.iter().all(|&b| b == k[2] % N_THREADS as u8));
+    }
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    for tn in 0..N_THREADS {
+        assert_eq!(t.get(&*kv(tn)).unwrap(), Some(big(tn, 49_000)));
+        assert_eq!(t.get(&*kv(tn + N_THREADS)).unwrap(), Some(big(tn, 490)));
+    }
+    // about 4mb was written, but only the last values are live, and
+    // the segments holding overwritten blobs are reused.
+    assert!(t.size_on_disk() < 2_000_000);
+
+    // merges into a blob, or past the threshold, are stored as blobs
+    t.merge(kv(0), vec![9; 10]).unwrap();
+    let mut expected = big(0, 49_000);
+    expected.extend_from_slice(&[9; 10]);
+    assert_eq!(t.get(&*kv(0)).unwrap(), Some(expected.clone()));
+    t.merge(kv(100), vec![1; 60]).unwrap();
+    t.merge(kv(100), vec![1; 60]).unwrap();
+    assert_eq!(t.get(&*kv(100)).unwrap(), Some(vec![1; 120]));
+
+    // snapshots still read blobs that were overwritten since
+    let snapshot = t.snapshot().unwrap();
+    t.set(kv(1), vec![1]).unwrap();
+    assert_eq!(
+        t.cas(kv(1), Some(vec![1]), Some(big(1, 20_000))).unwrap(),
+        Ok(())
+    );
+    assert_eq!(snapshot.get(&*kv(1)).unwrap(), Some(big(1, 49_000)));
+    drop(snapshot);
+    assert_eq!(t.del(&*kv(1)).unwrap(), Some(big(1, 20_000)));
+    assert_eq!(t.pop_max().unwrap(), Some((kv(100), vec![1; 120])));
+    drop(t);
+
+    let t = conf.tree().unwrap();
+    assert_eq!(t.get(&*kv(0)).unwrap(), Some(expected));
+    assert_eq!(t.get(&*kv(1)).unwrap(), None);
+    for tn in 2..N_THREADS {
+        assert_eq!(t.get(&*kv(tn)).unwrap(), Some(big(tn, 49_000)));
+    }
+    assert_eq!(t.len().unwrap(), N_THREADS * 2 - 1);
+}
+
+fn reversed(a: &[u8], b: &[u8]) -> Ordering {
+    b.cmp(a)
+}
+
+#[test]
+fn tree_key_comparator() {
+    let conf = Config::default()
+        .blink_fanout(2)
+        .flush_every_ms(None)
+        .key_comparator(Some(("reversed", reversed)));
+    let t = conf.tree().unwrap();
+    // ascending under the comparator
+    for i in (0..N_PER_THREAD).rev() {
+        t.set(kv(i), kv(i)).unwrap();
+    }
+
+    let keys =
+        |t: &Tree| t.iter().map(|res| res.unwrap().0).collect::<Vec<_>>();
+    assert_eq!(keys(&t), (0..N_PER_THREAD).rev().map(kv).collect::<Vec<_>>());
+    assert_eq!(
+        t.iter().rev().map(|res| res.unwrap().0).collect::<Vec<_>>(),
+        (0..N_PER_THREAD).map(kv).collect::<Vec<_>>()
+    );
+    assert_eq!(
+        t.range(kv(20)..kv(10)).map(|res| res.unwrap().0).collect::<Vec<_>>(),
+        (11..21).rev().map(kv).collect::<Vec<_>>()
+    );
+
+    // emptied leaves merge into their left siblings
+    for i in (0..N_PER_THREAD).rev() {
+        if i % 3 != 0 {
+            assert_eq!(t.del(&*kv(i)).unwrap(), Some(kv(i)));
+        }
+    }
+    drop(t);
+
+    let t = conf.tree().unwrap();
+    let expected: Vec<_> =
+        (0..N_PER_THREAD).rev().filter(|i| i % 3 == 0).map(kv).collect();
+    assert_eq!(keys(&t), expected);
+    assert_eq!(t.get(&*kv(3)).unwrap(), Some(kv(3)));
+    assert_eq!(t.get(&*kv(4)).unwrap(), None);
+
+    // keys sharing a prefix are not contiguous in every order
+    let mut iter = t.scan_prefix(&*kv(3));
+    match iter.next() {
+        Some(Err(Error::Unsupported(_))) => {}
+        other => panic!("scanned a prefix out of order: {:?}", other),
+    }
+    assert!(iter.next().is_none());
+    assert!(iter.next_back().is_none());
+    assert!(t.scan_prefix(&[]).next_back().unwrap().is_err());
+}
+
+#[test]
+fn tree_key_comparator_mismatch() {
+    let conf = Config::default()
+        .flush_every_ms(None)
+        .key_comparator(Some(("reversed", reversed)));
+    let t = conf.tree().unwrap();
+    t.set(vec![1], vec![1]).unwrap();
+    drop(t);
+
+    match conf.key_comparator(None).tree() {
+        Err(Error::Unsupported(ref msg)) if msg.contains("key comparator") => {}
+        Err(e) => panic!("unexpected error {}", e),
+        Ok(_) => panic!("opened a tree with the wrong key comparator"),
+    }
+}
+
+#[test]
+fn tree_read_only() {
+    let path = "test_tree_read_only/test.db";
+    let conf = Config::default()
+        .path(path.to_owned())
+        .blink_fanout(2)
+        .flush_every_ms(None)
+        .snapshot_after_ops(100);
+    let t = conf.tree().unwrap();
+    for i in 0..N_PER_THREAD {
+        t.set(kv(i), kv(i)).unwrap();
+    }
+    // leave some leaves empty, so they are merged away
+    for i in 0..N_PER_THREAD / 2 {
+        t.del(&*kv(i)).unwrap();
+    }
+    drop(t);
+
+    let before = std::fs::read(path).unwrap();
+    let mut perms = std::fs::metadata(path).unwrap().permissions();
+    perms.set_readonly(true);
+    std::fs::set_permissions(path, perms.clone()).unwrap();
+
+    let t = conf.read_only(true).tree().unwrap();
+    for i in 0..N_PER_THREAD {
+        let expected = if i < N_PER_THREAD / 2 { None } else { Some(kv(i)) };
+        assert_eq!(t.get(&*kv(i)).unwrap(), expected);
+    }
+    let keys: Vec<_> = t.iter().map(|res| res.unwrap().0).collect();
+    let expected: Vec<_> = (N_PER_THREAD / 2..N_PER_THREAD).map(kv).collect();
+    assert_eq!(keys, expected);
+    let snapshot = t.snapshot().unwrap();
+    assert_eq!(snapshot.iter().map(Result::unwrap).count(), expected.len());
+
+    assert_eq!(t.set(kv(0), vec![1]), Err(Error::ReadOnly));
+    assert_eq!(t.del(&*kv(N_PER_THREAD - 1)), Err(Error::ReadOnly));
+    assert_eq!(t.cas(kv(0), None, Some(vec![1])), Err(Error::ReadOnly));
+    assert_eq!(t.merge(kv(0), vec![1]), Err(Error::ReadOnly));
+    let mut batch = Batch::default();
+    batch.set(kv(0), vec![1]);
+    assert_eq!(t.apply_batch(batch), Err(Error::ReadOnly));
+    let tx_res = t.tx(|tx| {
+        tx.set(kv(0), vec![1]);
+        Ok(())
+    });
+    assert_eq!(tx_res, Err(Error::ReadOnly));
+    let last = kv(N_PER_THREAD - 1);
+    assert_eq!(t.tx(|tx| tx.get(&*last)), Ok(Some(last.clone())));
+    assert_eq!(t.get(&*kv(0)).unwrap(), None);
+    drop(snapshot);
+    drop(t);
+
+    assert_eq!(std::fs::read(path).unwrap(), before);
+
+    // there is nothing to open read-only at a new path
+    assert!(
+        Config::default()
+            .path("test_tree_read_only/missing.db".to_owned())
+            .read_only(true)
+            .tree()
+            .is_err()
+    );
+
+    perms.set_readonly(false);
+    std::fs::set_permissions(path, perms).unwrap();
+    std::fs::remove_dir_all("test_tree_read_only").unwrap();
+}
+
 #[test]
 fn recover_tree() {
     println!("========== recovery ==========");
@@ -159,25 +1660,25 @@ fn recover_tree() {
         .io_buf_size(5000)
         .flush_every_ms(None)
         .snapshot_after_ops(100);
-    let t = conf.tree();
+    let t = conf.tree().unwrap();
     for i in 0..N_PER_THREAD {
         let k = kv(i);
-        t.set(k.clone(), k);
+        t.set(k.clone(), k).unwrap();
     }
     drop(t);
 
-    let t = conf.tree();
+    let t = conf.tree().unwrap();
     for i in 0..conf.get_blink_fanout() << 1 {
         let k = kv(i);
-        assert_eq!(t.get(&*k), Some(k.clone()));
-        t.del(&*k);
+        assert_eq!(t.get(&*k).unwrap(), Some(k.clone()));
+        t.del(&*k).unwrap();
     }
     drop(t);
 
-    let t = conf.tree();
+    let t = conf.tree().unwrap();
     for i in 0..conf.get_blink_fanout() << 1 {
         let k = kv(i);
-        assert_eq!(t.get(&*k), None);
+        assert_eq!(t.get(&*k).unwrap(), None);
     }
 }
 
@@ -253,28 +1754,28 @@ fn prop_tree_matches_btreemap(
         .blink_fanout(blink_fanout as usize + 2)
         .cache_capacity(40);
 
-    let mut tree = config.tree();
+    let mut tree = config.tree().unwrap();
     let mut reference = BTreeMap::new();
 
     for op in ops.ops.into_iter() {
         match op {
             Set(k, v) => {
-                tree.set(vec![k], vec![v]);
+                tree.set(vec![k], vec![v]).unwrap();
                 reference.insert(k, v);
             }
             Get(k) => {
-                let res1 = tree.get(&*vec![k]).map(|v_vec| v_vec[0]);
+                let res1 = tree.get(&*vec![k]).unwrap().map(|v_vec| v_vec[0]);
                 let res2 = reference.get(&k).cloned();
                 assert_eq!(res1, res2);
             }
             Del(k) => {
-                tree.del(&*vec![k]);
+                tree.del(&*vec![k]).unwrap();
                 reference.remove(&k);
             }
             Cas(k, old, new) => {
-                let tree_old = tree.get(&*vec![k]);
+                let tree_old = tree.get(&*vec![k]).unwrap();
                 if tree_old == Some(vec![old]) {
-                    tree.set(vec![k], vec![new]);
+                    tree.set(vec![k], vec![new]).unwrap();
                 }
 
                 let ref_old = reference.get(&k).cloned();
@@ -283,8 +1784,8 @@ fn prop_tree_matches_btreemap(
                 }
             }
             Scan(k, len) => {
-                let tree_iter = tree.scan(&*vec![k]).take(len).map(|(ref tk,
-                  ref tv)| {
+                let tree_iter = tree.scan(&*vec![k]).take(len).map(|res| {
+                    let (tk, tv) = res.unwrap();
                     (tk[0], tv[0])
                 });
                 let ref_iter = reference
@@ -298,7 +1799,7 @@ fn prop_tree_matches_btreemap(
             }
             Restart => {
                 drop(tree);
-                tree = config.tree();
+                tree = config.tree().unwrap();
             }
         }
     }